@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::mem::size_of;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use jupiter_core::amm::{Amm, KeyedAccount, PartialAccount, QuoteParams};
+use jupiter_phoenix::JupiterPhoenix;
+use phoenix::program::status::MarketStatus;
+use phoenix::program::{load_with_dispatch, MarketHeader, MarketSizeParams, TokenParams};
+use phoenix::quantities::{
+    BaseAtomsPerBaseLot, BaseLots, BaseLotsPerBaseUnit, QuoteAtomsPerBaseUnitPerTick,
+    QuoteAtomsPerQuoteLot, QuoteLotsPerBaseUnitPerTick, WrapperU64,
+};
+use phoenix::state::markets::{FIFOMarket, FIFOOrderId, FIFORestingOrder};
+use sokoban::{FromSlice, NodeAllocatorMap};
+use solana_sdk::{account::Account, pubkey::Pubkey};
+
+type BenchMarket = FIFOMarket<Pubkey, 1024, 1024, 128>;
+
+/// SOL/USDC-like parameters: 0.001 SOL base lots, 1 atom quote lots, 0.001 USDC ticks
+const BASE_ATOMS_PER_BASE_LOT: u64 = 1_000_000;
+const BASE_LOTS_PER_BASE_UNIT: u64 = 1_000;
+const TICK_SIZE_IN_QUOTE_ATOMS_PER_BASE_UNIT: u64 = 1_000;
+/// 20 USDC in ticks
+const MID_PRICE_IN_TICKS: u64 = 20_000;
+/// 1 SOL per level
+const LEVEL_SIZE_IN_BASE_LOTS: u64 = 1_000;
+
+const LADDER_DEPTHS: [(&str, u64); 3] = [("shallow", 5), ("medium", 50), ("deep", 500)];
+/// Levels per side of the book `bench_update_deep_book` caches only the top of
+const DEEP_BOOK_LEVELS: u64 = 1_000;
+
+/// Builds the raw bytes of a Phoenix market account with `levels` price levels on each side
+/// of a synthetic SOL/USDC-like book, so the benchmarks never touch RPC.
+fn synthetic_market_data(
+    base_mint: Pubkey,
+    quote_mint: Pubkey,
+    levels: u64,
+    market_sequence_number: u64,
+) -> Vec<u8> {
+    let mut header = MarketHeader::new(
+        MarketSizeParams {
+            bids_size: 1024,
+            asks_size: 1024,
+            num_seats: 128,
+        },
+        TokenParams {
+            decimals: 9,
+            vault_bump: 0,
+            mint_key: base_mint,
+            vault_key: Pubkey::default(),
+        },
+        BaseAtomsPerBaseLot::new(BASE_ATOMS_PER_BASE_LOT),
+        TokenParams {
+            decimals: 6,
+            vault_bump: 0,
+            mint_key: quote_mint,
+            vault_key: Pubkey::default(),
+        },
+        QuoteAtomsPerQuoteLot::new(1),
+        QuoteAtomsPerBaseUnitPerTick::new(TICK_SIZE_IN_QUOTE_ATOMS_PER_BASE_UNIT),
+        Pubkey::default(),
+        Pubkey::default(),
+        Pubkey::default(),
+        1,
+    );
+    header.status = MarketStatus::Active as u64;
+    header.market_sequence_number = market_sequence_number;
+
+    let mut data = vec![0; size_of::<MarketHeader>() + size_of::<BenchMarket>()];
+    let (header_bytes, market_bytes) = data.split_at_mut(size_of::<MarketHeader>());
+    header_bytes.copy_from_slice(bytemuck::bytes_of(&header));
+
+    let market = BenchMarket::new_from_slice(market_bytes);
+    market.base_lots_per_base_unit = BaseLotsPerBaseUnit::new(BASE_LOTS_PER_BASE_UNIT);
+    market.tick_size_in_quote_lots_per_base_unit =
+        QuoteLotsPerBaseUnitPerTick::new(TICK_SIZE_IN_QUOTE_ATOMS_PER_BASE_UNIT);
+    market.taker_fee_bps = 2;
+    for level in 0..levels {
+        let order = FIFORestingOrder::new_default(0, BaseLots::new(LEVEL_SIZE_IN_BASE_LOTS));
+        // Bid sequence numbers have their bits inverted, see `FIFOOrderId`
+        market.bids.insert(
+            FIFOOrderId::new_from_untyped(MID_PRICE_IN_TICKS - 1 - level, !(level + 1)),
+            order,
+        );
+        market.asks.insert(
+            FIFOOrderId::new_from_untyped(MID_PRICE_IN_TICKS + 1 + level, level + 1),
+            order,
+        );
+    }
+    data
+}
+
+/// Also returns two snapshots of the same book at different sequence numbers. `update` skips
+/// the rebuild when the sequence number has not moved, so alternating between them makes every
+/// update reload the ladder, up to `ladder_depth` levels per side.
+fn synthetic_market(
+    levels: u64,
+    ladder_depth: u64,
+) -> (JupiterPhoenix, [HashMap<Pubkey, PartialAccount>; 2]) {
+    let market_key = Pubkey::new_unique();
+    let (base_mint, quote_mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let account = |market_sequence_number| Account {
+        lamports: 0,
+        data: synthetic_market_data(base_mint, quote_mint, levels, market_sequence_number),
+        owner: phoenix::id(),
+        executable: false,
+        rent_epoch: 0,
+    };
+    let keyed_account = KeyedAccount {
+        key: market_key,
+        account: account(0),
+        params: None,
+    };
+    let jupiter_phoenix =
+        JupiterPhoenix::new_from_keyed_account_with_ladder_depth(&keyed_account, ladder_depth)
+            .unwrap();
+    let accounts_maps = [1, 2].map(|sequence_number| {
+        HashMap::from([(market_key, PartialAccount::from(account(sequence_number)))])
+    });
+    (jupiter_phoenix, accounts_maps)
+}
+
+fn bench_quote(c: &mut Criterion) {
+    let mut group = c.benchmark_group("quote");
+    group.throughput(Throughput::Elements(1));
+    for (name, levels) in LADDER_DEPTHS {
+        // Cache the whole book, so the quotes walk every level
+        let (jupiter_phoenix, _) = synthetic_market(levels, levels);
+        let [base_mint, quote_mint] =
+            <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+
+        // Sized to walk every level of the book
+        let sell_params = QuoteParams {
+            in_amount: levels * LEVEL_SIZE_IN_BASE_LOTS * BASE_ATOMS_PER_BASE_LOT,
+            input_mint: base_mint,
+            output_mint: quote_mint,
+        };
+        let buy_params = QuoteParams {
+            in_amount: levels
+                * (MID_PRICE_IN_TICKS + levels)
+                * TICK_SIZE_IN_QUOTE_ATOMS_PER_BASE_UNIT,
+            input_mint: quote_mint,
+            output_mint: base_mint,
+        };
+
+        group.bench_with_input(
+            BenchmarkId::new("sell_base", name),
+            &sell_params,
+            |b, params| b.iter(|| jupiter_phoenix.quote(black_box(params)).unwrap()),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("buy_base", name),
+            &buy_params,
+            |b, params| b.iter(|| jupiter_phoenix.quote(black_box(params)).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+/// Quotes against the whole of a deep book. `quote` skips the levels it clears with the
+/// ladder's running totals, where `quote_against_ladder` with a copy of the ladder walks every
+/// level.
+fn bench_quote_deep_book(c: &mut Criterion) {
+    let mut group = c.benchmark_group("quote_deep_book");
+    group.throughput(Throughput::Elements(1));
+    let (jupiter_phoenix, _) = synthetic_market(DEEP_BOOK_LEVELS, DEEP_BOOK_LEVELS);
+    let ladder = JupiterPhoenix::deserialize_ladder(&jupiter_phoenix.serialize_ladder()).unwrap();
+    let [base_mint, quote_mint] =
+        <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+    let levels = DEEP_BOOK_LEVELS;
+    for (name, params) in [
+        (
+            "sell_base",
+            QuoteParams {
+                in_amount: levels * LEVEL_SIZE_IN_BASE_LOTS * BASE_ATOMS_PER_BASE_LOT,
+                input_mint: base_mint,
+                output_mint: quote_mint,
+            },
+        ),
+        (
+            "buy_base",
+            QuoteParams {
+                in_amount: levels
+                    * (MID_PRICE_IN_TICKS + levels)
+                    * TICK_SIZE_IN_QUOTE_ATOMS_PER_BASE_UNIT,
+                input_mint: quote_mint,
+                output_mint: base_mint,
+            },
+        ),
+    ] {
+        group.bench_with_input(
+            BenchmarkId::new("cumulative", name),
+            &params,
+            |b, params| b.iter(|| jupiter_phoenix.quote(black_box(params)).unwrap()),
+        );
+        group.bench_with_input(BenchmarkId::new("linear", name), &params, |b, params| {
+            b.iter(|| {
+                jupiter_phoenix
+                    .quote_against_ladder(black_box(&ladder), black_box(params))
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_update(c: &mut Criterion) {
+    let mut group = c.benchmark_group("update");
+    group.throughput(Throughput::Elements(1));
+    for (name, levels) in LADDER_DEPTHS {
+        let (mut jupiter_phoenix, accounts_maps) = synthetic_market(levels, levels);
+        let mut snapshots = accounts_maps.iter().cycle();
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                jupiter_phoenix
+                    .update(black_box(snapshots.next().unwrap()))
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Updates against a book much deeper than the ladder, which only materialize the top levels.
+/// `full_book` is the cost of building the whole ladder with `get_ladder(u64::MAX)`, for
+/// comparison.
+fn bench_update_deep_book(c: &mut Criterion) {
+    let mut group = c.benchmark_group("update_deep_book");
+    group.throughput(Throughput::Elements(1));
+    for (name, ladder_depth) in [("top_5", 5), ("top_64", 64), ("whole", DEEP_BOOK_LEVELS)] {
+        let (mut jupiter_phoenix, accounts_maps) = synthetic_market(DEEP_BOOK_LEVELS, ladder_depth);
+        let mut snapshots = accounts_maps.iter().cycle();
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                jupiter_phoenix
+                    .update(black_box(snapshots.next().unwrap()))
+                    .unwrap()
+            })
+        });
+    }
+    let data = synthetic_market_data(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        DEEP_BOOK_LEVELS,
+        0,
+    );
+    group.bench_function("full_book", |b| {
+        b.iter(|| {
+            let (header_bytes, market_bytes) = black_box(&data).split_at(size_of::<MarketHeader>());
+            let header = bytemuck::from_bytes::<MarketHeader>(header_bytes);
+            let market = load_with_dispatch(&header.market_size_params, market_bytes).unwrap();
+            market.inner.get_ladder(u64::MAX)
+        })
+    });
+    group.finish();
+}
+
+/// `clone_amm`, which routers call to explore routes in parallel, on books of every depth
+fn bench_clone_amm(c: &mut Criterion) {
+    let mut group = c.benchmark_group("clone_amm");
+    group.throughput(Throughput::Elements(1));
+    for (name, levels) in LADDER_DEPTHS
+        .into_iter()
+        .chain([("whole_deep_book", DEEP_BOOK_LEVELS)])
+    {
+        let (jupiter_phoenix, _) = synthetic_market(levels, levels);
+        group.bench_function(name, |b| b.iter(|| black_box(&jupiter_phoenix).clone_amm()));
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_quote,
+    bench_quote_deep_book,
+    bench_update,
+    bench_update_deep_book,
+    bench_clone_amm
+);
+criterion_main!(benches);
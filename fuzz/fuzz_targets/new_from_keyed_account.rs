@@ -0,0 +1,23 @@
+#![no_main]
+
+use jupiter_core::amm::KeyedAccount;
+use jupiter_phoenix::JupiterPhoenix;
+use libfuzzer_sys::fuzz_target;
+use solana_sdk::{account::Account, pubkey::Pubkey};
+
+// Arbitrary account data must produce `Ok` or `Err`, never a panic. The account is owned by
+// the Phoenix program, so that the data gets past the owner check to the parser.
+fuzz_target!(|data: &[u8]| {
+    let keyed_account = KeyedAccount {
+        key: Pubkey::default(),
+        account: Account {
+            lamports: 0,
+            data: data.to_vec(),
+            owner: phoenix::id(),
+            executable: false,
+            rent_epoch: 0,
+        },
+        params: None,
+    };
+    let _ = JupiterPhoenix::new_from_keyed_account(&keyed_account);
+});
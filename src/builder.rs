@@ -0,0 +1,134 @@
+//! Construction options beyond what the `Amm` trait's `from_keyed_account` can express.
+
+use anyhow::{Error, Result};
+use jupiter_core::amm::KeyedAccount;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{short_key, JupiterPhoenix, SwapLeg, DEFAULT_LADDER_DEPTH};
+
+/// Builds a `JupiterPhoenix` with a custom program id, taker fee, ladder depth, swap leg,
+/// label, or fee recipient. Without any setters it builds exactly what `JupiterPhoenix::new_from_keyed_account`
+/// does.
+#[derive(Clone, Debug)]
+pub struct JupiterPhoenixBuilder {
+    program_id: Pubkey,
+    taker_fee_override: Option<u16>,
+    ladder_depth: u64,
+    swap_leg: SwapLeg,
+    pair_label: bool,
+    symbols: Option<(String, String)>,
+    fee_recipient: Option<Pubkey>,
+}
+
+impl Default for JupiterPhoenixBuilder {
+    fn default() -> Self {
+        Self {
+            program_id: phoenix::id(),
+            taker_fee_override: None,
+            ladder_depth: DEFAULT_LADDER_DEPTH,
+            swap_leg: SwapLeg::Serum,
+            pair_label: false,
+            symbols: None,
+            fee_recipient: None,
+        }
+    }
+}
+
+impl JupiterPhoenixBuilder {
+    /// The Phoenix program the market belongs to, e.g. a localnet deployment. The market account
+    /// must be owned by it, and the vault and log authority PDAs are derived from it.
+    pub fn program_id(mut self, program_id: Pubkey) -> Self {
+        self.program_id = program_id;
+        self
+    }
+
+    /// Quotes with `taker_fee_bps` instead of the market's on-chain taker fee, for fee
+    /// simulations. `refresh_metadata` reloads the on-chain fee.
+    pub fn taker_fee_override(mut self, taker_fee_bps: u16) -> Self {
+        self.taker_fee_override = Some(taker_fee_bps);
+        self
+    }
+
+    /// Number of price levels cached per side, see
+    /// `JupiterPhoenix::new_from_keyed_account_with_ladder_depth`
+    pub fn ladder_depth(mut self, ladder_depth: u64) -> Self {
+        self.ladder_depth = ladder_depth;
+        self
+    }
+
+    /// Which `Swap` variant swap legs are emitted as, see `JupiterPhoenix::set_swap_leg`
+    pub fn swap_leg(mut self, swap_leg: SwapLeg) -> Self {
+        self.swap_leg = swap_leg;
+        self
+    }
+
+    /// Appends the pair to the label, so routes through different Phoenix markets can be told
+    /// apart: `Phoenix SOL/USDC` with `symbols`, otherwise the shortened mint addresses, e.g.
+    /// `Phoenix So11..1112/EPjF..Dt1v`. The label is just `Phoenix` by default.
+    pub fn pair_label(mut self) -> Self {
+        self.pair_label = true;
+        self
+    }
+
+    /// Labels the market `Phoenix <base_symbol>/<quote_symbol>`, see `pair_label`. The market
+    /// account has no symbols, so they are up to the caller.
+    pub fn symbols(
+        mut self,
+        base_symbol: impl Into<String>,
+        quote_symbol: impl Into<String>,
+    ) -> Self {
+        self.pair_label = true;
+        self.symbols = Some((base_symbol.into(), quote_symbol.into()));
+        self
+    }
+
+    /// Adds a referral fee account to the swap metas, see `JupiterPhoenix::set_fee_recipient`
+    pub fn fee_recipient(mut self, fee_recipient: Pubkey) -> Self {
+        self.fee_recipient = Some(fee_recipient);
+        self
+    }
+
+    pub fn build_from_keyed_account(&self, keyed_account: &KeyedAccount) -> Result<JupiterPhoenix> {
+        self.build_from_parts(
+            keyed_account.key,
+            &keyed_account.account.owner,
+            &keyed_account.account.data,
+        )
+    }
+
+    /// Like `build_from_keyed_account`, but from the market key, account owner and raw account
+    /// data, see `JupiterPhoenix::from_parts`
+    pub fn build_from_parts(
+        &self,
+        market_key: Pubkey,
+        owner: &Pubkey,
+        data: &[u8],
+    ) -> Result<JupiterPhoenix> {
+        let mut jupiter_phoenix = JupiterPhoenix::from_parts_with_options(
+            market_key,
+            owner,
+            data,
+            self.program_id,
+            self.ladder_depth,
+        )?;
+        if let Some(taker_fee_bps) = self.taker_fee_override {
+            if taker_fee_bps > 10_000 {
+                return Err(Error::msg("Taker fee override must be at most 10000 bps"));
+            }
+            jupiter_phoenix.taker_fee_bps = taker_fee_bps;
+        }
+        jupiter_phoenix.set_swap_leg(self.swap_leg);
+        jupiter_phoenix.set_fee_recipient(self.fee_recipient);
+        if self.pair_label {
+            let (base, quote) = match &self.symbols {
+                Some((base_symbol, quote_symbol)) => (base_symbol.clone(), quote_symbol.clone()),
+                None => (
+                    short_key(&jupiter_phoenix.base_mint),
+                    short_key(&jupiter_phoenix.quote_mint),
+                ),
+            };
+            jupiter_phoenix.label = format!("{} {}/{}", jupiter_phoenix.label, base, quote);
+        }
+        Ok(jupiter_phoenix)
+    }
+}
@@ -0,0 +1,56 @@
+//! Fixed-point quotes for accounting and settlement, behind the `decimal` feature.
+
+use anyhow::Result;
+use jupiter::Side;
+use jupiter_core::amm::{Amm, QuoteParams};
+use rust_decimal::Decimal;
+
+use crate::JupiterPhoenix;
+
+/// A quote with amounts in whole units of each mint, converted exactly from atoms
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecimalQuote {
+    /// Input amount, in whole units of the input mint
+    pub in_amount: Decimal,
+    /// Output amount after fees, in whole units of the output mint
+    pub out_amount: Decimal,
+    /// Execution price in whole quote units per whole base unit, including fees. `None` if
+    /// either side of the trade is zero.
+    pub price: Option<Decimal>,
+}
+
+impl JupiterPhoenix {
+    /// Like `quote`, but returns `Decimal` amounts and price instead of atoms. The amounts are
+    /// the integer atom results scaled by the mints' decimals, so they are exact; only the price
+    /// involves a division, which is exact whenever the ratio has at most 28 decimal digits.
+    pub fn quote_decimal(&self, quote_params: &QuoteParams) -> Result<DecimalQuote> {
+        let is_base_input = matches!(
+            self.side_for_input_mint(&quote_params.input_mint)?,
+            Side::Ask
+        );
+        let quote = self.quote(quote_params)?;
+        let (in_decimals, out_decimals) = if is_base_input {
+            (self.base_decimals, self.quote_decimals)
+        } else {
+            (self.quote_decimals, self.base_decimals)
+        };
+        let in_amount = Decimal::from_i128_with_scale(quote_params.in_amount as i128, in_decimals);
+        let out_amount = Decimal::from_i128_with_scale(quote.out_amount as i128, out_decimals);
+
+        let (base_amount, quote_amount) = if is_base_input {
+            (in_amount, out_amount)
+        } else {
+            (out_amount, in_amount)
+        };
+        let price = if base_amount.is_zero() || quote_amount.is_zero() {
+            None
+        } else {
+            quote_amount.checked_div(base_amount)
+        };
+        Ok(DecimalQuote {
+            in_amount,
+            out_amount,
+            price,
+        })
+    }
+}
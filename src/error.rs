@@ -0,0 +1,75 @@
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PhoenixAmmError {
+    /// The market's base and quote mints are the same
+    DegenerateMarket,
+    /// The market header's mints, decimals, or lot sizes no longer match the cached metadata.
+    /// `JupiterPhoenix::refresh_metadata` re-derives it.
+    MetadataChanged,
+    /// The side of the book a quote would fill against has no orders
+    EmptyBook,
+    /// The market header has a zero base or quote lot size, which every quote divides by
+    ZeroLotSize,
+    /// The market's status does not accept taker orders, e.g. it is paused or being closed
+    MarketNotSwappable,
+    /// A swap from the base mint does not output the market's quote mint
+    InvalidQuoteMint,
+    /// A swap from the quote mint does not output the market's base mint
+    InvalidBaseMint,
+    /// A swap's input mint is neither the market's base nor its quote mint
+    MintNotInMarket,
+    /// The book is too thin to fill the whole swap input
+    NotEnoughLiquidity,
+    /// The market account is not owned by the Phoenix program (or the program id given to the
+    /// builder), so its data is not a Phoenix market
+    InvalidMarketOwner,
+    /// The market account is too short for its header and market, its header is misaligned, or
+    /// it has an unknown market status
+    MalformedMarketAccount,
+    /// The accounts passed to `update` do not include the market account
+    MissingAccountUpdate,
+    /// A quote amount does not fit in the u64 the program would hold it in
+    AmountOverflow,
+}
+
+impl fmt::Display for PhoenixAmmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PhoenixAmmError::DegenerateMarket => {
+                write!(f, "Market base mint and quote mint are identical")
+            }
+            PhoenixAmmError::MetadataChanged => {
+                write!(
+                    f,
+                    "Market header no longer matches the cached market metadata"
+                )
+            }
+            PhoenixAmmError::EmptyBook => write!(f, "No liquidity on this side of the book"),
+            PhoenixAmmError::ZeroLotSize => write!(f, "Market lot sizes must be nonzero"),
+            PhoenixAmmError::MarketNotSwappable => {
+                write!(f, "Market status does not allow taker orders")
+            }
+            PhoenixAmmError::InvalidQuoteMint => write!(f, "Invalid quote mint"),
+            PhoenixAmmError::InvalidBaseMint => write!(f, "Invalid base mint"),
+            PhoenixAmmError::MintNotInMarket => {
+                write!(f, "Input mint is not traded on this market")
+            }
+            PhoenixAmmError::NotEnoughLiquidity => {
+                write!(f, "Swap input exceeds the depth of the book")
+            }
+            PhoenixAmmError::InvalidMarketOwner => {
+                write!(f, "Market account is not owned by the Phoenix program")
+            }
+            PhoenixAmmError::MalformedMarketAccount => {
+                write!(f, "Market account data is not a valid Phoenix market")
+            }
+            PhoenixAmmError::MissingAccountUpdate => {
+                write!(f, "Market account is missing from the accounts map")
+            }
+            PhoenixAmmError::AmountOverflow => write!(f, "Quote amount overflows a u64"),
+        }
+    }
+}
+
+impl std::error::Error for PhoenixAmmError {}
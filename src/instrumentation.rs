@@ -0,0 +1,37 @@
+//! `tracing` spans around `update` and `quote`, behind the `tracing` feature.
+
+use std::time::Instant;
+
+use tracing::span::EnteredSpan;
+use tracing::Span;
+
+/// Enters `span` and records how long it was entered for in its `elapsed_us` field when
+/// dropped. The clock is only read when a subscriber is interested in the span.
+pub(crate) struct TimedSpan {
+    span: EnteredSpan,
+    start: Option<Instant>,
+}
+
+impl TimedSpan {
+    pub(crate) fn enter(span: Span) -> Self {
+        let start = (!span.is_disabled()).then(Instant::now);
+        Self {
+            span: span.entered(),
+            start,
+        }
+    }
+
+    /// Records `value` in the span's `field`, which must have been declared when creating it
+    pub(crate) fn record(&self, field: &str, value: u64) {
+        self.span.record(field, value);
+    }
+}
+
+impl Drop for TimedSpan {
+    fn drop(&mut self) {
+        if let Some(start) = self.start {
+            self.span
+                .record("elapsed_us", start.elapsed().as_micros() as u64);
+        }
+    }
+}
@@ -1,23 +1,67 @@
+mod builder;
+#[cfg(feature = "decimal")]
+mod decimal;
+mod error;
+#[cfg(feature = "tracing")]
+mod instrumentation;
+mod liquidity;
+#[cfg(feature = "serde")]
+mod serialization;
+#[cfg(test)]
+mod test_utils;
+mod walk;
+
+pub use builder::JupiterPhoenixBuilder;
+#[cfg(feature = "decimal")]
+pub use decimal::DecimalQuote;
+pub use error::PhoenixAmmError;
+
+use crate::liquidity::{CumulativeLiquidity, CumulativeSide};
+use crate::walk::LadderWalker;
 use anyhow::{Error, Result};
 use jupiter::Side;
-use phoenix::program::load_with_dispatch;
+use phoenix::program::status::MarketStatus;
 use phoenix::program::MarketHeader;
-use phoenix::state::markets::{Ladder, LadderOrder};
+use phoenix::program::{
+    create_new_order_instruction_with_custom_token_accounts, get_market_size, load_with_dispatch,
+};
+use phoenix::quantities::WrapperU64;
+use phoenix::state::markets::{
+    FIFOOrderId, FIFORestingOrder, Ladder, LadderOrder, Market, RestingOrder,
+};
+use phoenix::state::{OrderPacket, SelfTradeBehavior};
 use phoenix_sdk_core::sdk_client_core::MarketMetadata;
+use rust_decimal::Decimal;
 use std::ops::Deref;
-use std::{collections::HashMap, mem::size_of};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    mem::size_of,
+    sync::{Arc, OnceLock},
+};
 
-use jupiter_core::amm::{Amm, KeyedAccount, PartialAccount};
-use solana_sdk::{instruction::AccountMeta, pubkey::Pubkey};
+use jupiter_core::amm::{Amm, AmmUserSetup, KeyedAccount, PartialAccount};
+use solana_sdk::{
+    account::Account,
+    instruction::{AccountMeta, Instruction},
+    program_utils::limited_deserialize,
+    pubkey::Pubkey,
+    sysvar::clock::{self, Clock},
+};
+use spl_associated_token_account::{
+    get_associated_token_address_with_program_id,
+    instruction::create_associated_token_account_idempotent,
+};
 
 use jupiter::jupiter_override::Swap;
 use jupiter_core::amm::{Quote, QuoteParams, SwapAndAccountMetas, SwapParams};
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct JupiterPhoenix {
     /// The pubkey of the market account
     market_key: Pubkey,
-    /// Will always be "Phoenix"
+    /// "Phoenix", followed by the pair if the builder was asked to add it
     label: String,
     /// The pubkey of the base mint
     base_mint: Pubkey,
@@ -25,12 +69,200 @@ pub struct JupiterPhoenix {
     quote_mint: Pubkey,
     /// The pubkey of the Phoenix program
     program_id: Pubkey,
+    /// The program's log authority PDA, which signs its event-logging self-CPI
+    log_authority: Pubkey,
+    /// The market's base token vault PDA
+    base_vault: Pubkey,
+    /// The market's quote token vault PDA
+    quote_vault: Pubkey,
+    /// The token program that owns the market's mints
+    token_program: Pubkey,
+    /// The market authority from the header as of the last load
+    authority: Pubkey,
+    /// Where the market's taker fees are collected, from the header as of the last load
+    market_fee_recipient: Pubkey,
+    /// Whether `get_accounts_to_update` also lists the vaults
+    vaults_in_accounts_to_update: bool,
     /// Contain the conversion functions for the market
+    #[cfg_attr(feature = "serde", serde(with = "serialization::MarketMetadataDef"))]
     market_metadata: MarketMetadata,
     /// Taker fee basis points
     taker_fee_bps: u16,
-    /// The state of the orderbook (L2)
-    ladder: Ladder,
+    /// The state of the orderbook (L2). Clones share it until one of them reloads it.
+    #[cfg_attr(feature = "serde", serde(with = "serialization::ladder"))]
+    ladder: Arc<Ladder>,
+    /// Running totals of the ladder, kept in step with it for `walk_ladder`. They are derived
+    /// from the ladder, so they are not serialized, and a restored market rebuilds them on its
+    /// first walk rather than trusting a snapshot's.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    cumulative_liquidity: Arc<OnceLock<CumulativeLiquidity>>,
+    /// Maximum number of price levels cached per side of the ladder
+    ladder_depth: u64,
+    /// Whether the bids and asks of the last load were cut off at the ladder depth
+    ladder_depth_limited: (bool, bool),
+    /// The market sequence number the cached ladder was loaded at, `None` once it is cleared
+    market_sequence_number: Option<u64>,
+    /// Slot and unix timestamp of the last clock sysvar seen by `update`, zero until then. Orders
+    /// that expired before them are left out of the ladder.
+    clock: (u64, u64),
+    /// Earliest last valid slot and unix timestamp among the cached orders that have one, so an
+    /// unchanged market only needs reloading once one of them expires
+    ladder_expiry: (u64, u64),
+    /// The market's status as of the last load, which decides whether takers can trade
+    #[cfg_attr(feature = "serde", serde(with = "serialization::market_status"))]
+    market_status: MarketStatus,
+    /// What happens to the cached ladder when `update` fails
+    update_failure_policy: UpdateFailurePolicy,
+    /// Number of `update` calls that have failed since the last successful one
+    consecutive_update_failures: u32,
+    /// Slot of the last update applied through `update_from_geyser`
+    last_update_slot: Option<u64>,
+    /// How output amounts that fall between two atoms or lots are rounded
+    rounding_mode: RoundingMode,
+    /// How input amounts are rounded to whole lots before walking the book
+    input_rounding_mode: RoundingMode,
+    /// Which `Swap` variant `get_swap_leg_and_account_metas` emits
+    swap_leg: SwapLeg,
+    /// Caller-supplied accounts appended to the swap metas
+    extra_swap_account_metas: Vec<AccountMeta>,
+    /// Referral fee account appended to the swap metas, see `set_fee_recipient`
+    fee_recipient: Option<Pubkey>,
+}
+
+/// What `update` does with the cached ladder when it fails to load the market account
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UpdateFailurePolicy {
+    /// Keep quoting against the last successfully loaded ladder
+    #[default]
+    KeepLadder,
+    /// Clear the ladder, so quotes fail with `PhoenixAmmError::EmptyBook` until the next
+    /// successful update
+    ClearLadder,
+}
+
+/// How the proceeds of a sell are rounded when the matched size is not a whole number of quote
+/// lots. Buys always receive whole base lots, since that is all the program can match, so
+/// there is nothing to round. Also how swap inputs are rounded to whole lots, see
+/// `JupiterPhoenix::set_input_rounding_mode`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RoundingMode {
+    /// Round down, which never quotes more than the program pays out for sells
+    #[default]
+    Floor,
+    /// Round up
+    Ceil,
+    /// Round to the nearest value, with halves rounded up
+    Nearest,
+}
+
+/// Which of Jupiter's `Swap` variants a swap leg is emitted as. Each expects its own account
+/// order, so the account metas change with it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SwapLeg {
+    /// `Swap::Serum`, the legacy leg Jupiter has routed Phoenix swaps through
+    #[default]
+    Serum,
+    /// `Swap::Phoenix`, whose accounts are those of Phoenix's own `Swap` instruction, in order
+    Phoenix,
+}
+
+impl RoundingMode {
+    fn div(self, numerator: u128, denominator: u128) -> u128 {
+        match self {
+            RoundingMode::Floor => numerator / denominator,
+            RoundingMode::Ceil => numerator.div_ceil(denominator),
+            RoundingMode::Nearest => (numerator + denominator / 2) / denominator,
+        }
+    }
+}
+
+/// A quote with the exact atom amounts consumed and produced, for chaining swap legs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QuoteDetails {
+    /// Input atoms that actually trade against the book, including the taker fee for buys. This
+    /// is less than the requested input when the book runs out or the input is not a whole
+    /// number of lots.
+    pub in_amount_consumed: u64,
+    /// The requested input rounded down to whole lots of the input mint, which is all the
+    /// program can trade
+    pub in_amount_in_lots: u64,
+    /// Atoms of the requested input below one lot, which are never traded. Callers can bump
+    /// the input by `lot size - lot_remainder` if they would rather not leave them behind.
+    pub lot_remainder: u64,
+    /// Output atoms after the taker fee
+    pub out_amount: u64,
+    /// Taker fee, in quote atoms
+    pub fee_amount: u64,
+    /// The mint the taker spends
+    pub in_mint: Pubkey,
+    /// The mint the taker receives
+    pub out_mint: Pubkey,
+}
+
+/// What a quote takes from one level of the book
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FillLevel {
+    pub price_in_ticks: u64,
+    pub base_lots: u64,
+    /// The level's price times `base_lots`, in quote atoms rounded down, before the taker fee.
+    /// Saturates at `u64::MAX` for buys.
+    pub quote_atoms: u64,
+    /// Whether the level has base lots left after the fill, which only the last fill can
+    pub partial: bool,
+}
+
+/// The identifying parameters and top of book of a market, without the cached ladder
+#[derive(Clone, Debug, PartialEq)]
+pub struct MarketSummary {
+    pub label: String,
+    pub market_key: Pubkey,
+    pub base_mint: Pubkey,
+    pub quote_mint: Pubkey,
+    /// The taker fee quotes are charged, which may be an override of the on-chain fee
+    pub taker_fee_bps: u16,
+    /// In quote units per base unit, `None` if there are no bids
+    pub best_bid: Option<f64>,
+    /// In quote units per base unit, `None` if there are no asks
+    pub best_ask: Option<f64>,
+}
+
+/// The Token-2022 program, which Phoenix markets can be created on instead of SPL Token
+const TOKEN_2022_PROGRAM_ID: Pubkey =
+    solana_sdk::pubkey!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb");
+
+/// Number of price levels per side `new_from_keyed_account` caches. Most quotes only touch the
+/// top few levels, so loading the whole book on every update is wasted work.
+pub const DEFAULT_LADDER_DEPTH: u64 = 64;
+
+/// Number of account metas `get_swap_leg_and_account_metas` returns for a Phoenix swap
+pub const SWAP_ACCOUNT_META_COUNT: usize = 9;
+
+/// Bytes per level in `JupiterPhoenix::serialize_ladder`
+const LADDER_LEVEL_LEN: usize = 16;
+
+/// The result of walking one side of the cached ladder
+#[derive(Clone, Copy, Debug, Default)]
+struct LadderWalk {
+    /// Output in atoms of the output mint. The taker fee has not been taken out of sell
+    /// proceeds yet, while buys have already paid it out of their budget.
+    out_amount: u64,
+    /// Input actually traded against the book, in atoms of the input mint. For buys this
+    /// includes the taker fee.
+    in_amount_consumed: u64,
+    /// Input left over once the walk stops, in lots of the input mint
+    remaining_budget: u64,
+    /// Number of levels the fill took liquidity from
+    levels_touched: usize,
+    /// Price of the worst level the fill took any base lots from, zero if it took none
+    last_price_in_ticks: u64,
+    /// Sum of base lots times price in ticks over every fill. This is the exact matched size the
+    /// program charges fees on, before any rounding.
+    matched_lot_ticks: u128,
+    /// Taker fee charged on the match, in basis points
+    taker_fee_bps: u64,
 }
 
 impl Deref for JupiterPhoenix {
@@ -41,338 +273,7862 @@ impl Deref for JupiterPhoenix {
     }
 }
 
+/// A one-line summary, e.g. `Phoenix 4DoN..pRgg So11..1112/EPjF..Dt1v fee 2 bps bid 19.995
+/// ask 20.005`. Unlike `Debug`, this never prints the ladder.
+impl fmt::Display for JupiterPhoenix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let price = |price: Option<f64>| match price {
+            Some(price) => price.to_string(),
+            None => "-".to_string(),
+        };
+        let summary = self.summary();
+        write!(
+            f,
+            "{} {} {}/{} fee {} bps bid {} ask {}",
+            summary.label,
+            short_key(&summary.market_key),
+            short_key(&summary.base_mint),
+            short_key(&summary.quote_mint),
+            summary.taker_fee_bps,
+            price(summary.best_bid),
+            price(summary.best_ask),
+        )
+    }
+}
+
+/// The first and last four characters of `key`, e.g. `4DoN..pRgg`
+pub(crate) fn short_key(key: &Pubkey) -> String {
+    let key = key.to_string();
+    format!("{}..{}", &key[..4], &key[key.len() - 4..])
+}
+
+/// Delegates to `JupiterPhoenix::new_from_keyed_account`
+impl TryFrom<&KeyedAccount> for JupiterPhoenix {
+    type Error = Error;
+
+    fn try_from(keyed_account: &KeyedAccount) -> Result<Self> {
+        Self::new_from_keyed_account(keyed_account)
+    }
+}
+
 impl JupiterPhoenix {
     pub fn new_from_keyed_account(keyed_account: &KeyedAccount) -> Result<Self> {
-        let (header_bytes, bytes) = &keyed_account
-            .account
-            .data
-            .split_at(size_of::<MarketHeader>());
-        let header = bytemuck::try_from_bytes::<MarketHeader>(header_bytes).unwrap();
+        Self::new_from_keyed_account_with_ladder_depth(keyed_account, DEFAULT_LADDER_DEPTH)
+    }
+
+    /// Like `new_from_keyed_account`, but caches up to `ladder_depth` price levels per side
+    /// instead of `DEFAULT_LADDER_DEPTH`, on construction and on every update. Quotes that
+    /// reach the deepest cached level are flagged by `ladder_possibly_truncated`.
+    pub fn new_from_keyed_account_with_ladder_depth(
+        keyed_account: &KeyedAccount,
+        ladder_depth: u64,
+    ) -> Result<Self> {
+        Self::from_parts_with_options(
+            keyed_account.key,
+            &keyed_account.account.owner,
+            &keyed_account.account.data,
+            phoenix::id(),
+            ladder_depth,
+        )
+    }
+
+    /// Like `new_from_keyed_account`, but from the market key, account owner and raw account
+    /// data alone, e.g. as delivered by a Geyser stream, without building a `KeyedAccount`
+    /// around them
+    pub fn from_parts(market_key: Pubkey, owner: &Pubkey, data: &[u8]) -> Result<Self> {
+        Self::from_parts_with_options(market_key, owner, data, phoenix::id(), DEFAULT_LADDER_DEPTH)
+    }
+
+    /// A builder for markets deployed under another program id, or with a simulated taker fee
+    pub fn builder() -> JupiterPhoenixBuilder {
+        JupiterPhoenixBuilder::default()
+    }
+
+    /// The shared parsing path of every constructor. The account must be owned by `program_id`,
+    /// which the vault and log authority PDAs are derived from.
+    fn from_parts_with_options(
+        market_key: Pubkey,
+        owner: &Pubkey,
+        data: &[u8],
+        program_id: Pubkey,
+        ladder_depth: u64,
+    ) -> Result<Self> {
+        if ladder_depth == 0 {
+            return Err(Error::msg("Ladder depth must be at least one level"));
+        }
+        // Any other program's account would be parsed as garbage, if at all
+        if *owner != program_id {
+            return Err(PhoenixAmmError::InvalidMarketOwner.into());
+        }
+        let (header, bytes) = parse_market_account(data)?;
+        if header.base_params.mint_key == header.quote_params.mint_key {
+            return Err(PhoenixAmmError::DegenerateMarket.into());
+        }
         let market = load_with_dispatch(&header.market_size_params, bytes)?;
         let taker_fee_bps = market.inner.get_taker_fee_bps();
-        let market_metadata = MarketMetadata::from_header(header)?;
-        Ok(Self {
-            market_key: keyed_account.key,
+        let market_metadata = market_metadata_from_header(header)?;
+        let mut jupiter_phoenix = Self {
+            market_key,
             label: "Phoenix".into(),
             base_mint: header.base_params.mint_key,
             quote_mint: header.quote_params.mint_key,
-            program_id: phoenix::id(),
+            program_id,
+            log_authority: Self::derive_log_authority(&program_id),
+            base_vault: Self::derive_vault(&program_id, &market_key, &header.base_params.mint_key),
+            quote_vault: Self::derive_vault(
+                &program_id,
+                &market_key,
+                &header.quote_params.mint_key,
+            ),
+            token_program: spl_token::id(),
+            authority: header.authority,
+            market_fee_recipient: header.fee_recipient,
+            vaults_in_accounts_to_update: false,
             taker_fee_bps: taker_fee_bps as u16,
             market_metadata,
-            ladder: market.inner.get_ladder(u64::MAX),
-        })
+            ladder: Arc::new(Ladder {
+                bids: vec![],
+                asks: vec![],
+            }),
+            cumulative_liquidity: Arc::default(),
+            ladder_depth,
+            ladder_depth_limited: (false, false),
+            market_sequence_number: Some(header.market_sequence_number),
+            clock: (0, 0),
+            ladder_expiry: (u64::MAX, u64::MAX),
+            market_status: MarketStatus::from(header.status),
+            update_failure_policy: UpdateFailurePolicy::default(),
+            rounding_mode: RoundingMode::default(),
+            input_rounding_mode: RoundingMode::default(),
+            swap_leg: SwapLeg::default(),
+            extra_swap_account_metas: vec![],
+            fee_recipient: None,
+            consecutive_update_failures: 0,
+            last_update_slot: None,
+        };
+        jupiter_phoenix.refill_ladder(market.inner);
+        Ok(jupiter_phoenix)
     }
 
-    pub fn get_base_decimals(&self) -> u32 {
-        self.base_decimals
+    /// Like `new_from_keyed_account`, but if the market is a key of `fees`, its taker fee (in
+    /// basis points) overrides the one in the market account. Markets missing from `fees` keep
+    /// the on-chain fee. Note that `refresh_metadata` reloads the on-chain fee.
+    pub fn new_from_keyed_account_with_fees(
+        keyed_account: &KeyedAccount,
+        fees: &HashMap<Pubkey, u16>,
+    ) -> Result<Self> {
+        let mut jupiter_phoenix = Self::new_from_keyed_account(keyed_account)?;
+        if let Some(&taker_fee_bps) = fees.get(&keyed_account.key) {
+            if taker_fee_bps > 10_000 {
+                return Err(Error::msg("Taker fee override must be at most 10000 bps"));
+            }
+            jupiter_phoenix.taker_fee_bps = taker_fee_bps;
+        }
+        Ok(jupiter_phoenix)
     }
 
-    pub fn get_quote_decimals(&self) -> u32 {
-        self.quote_decimals
+    /// Re-derives the cached metadata, taker fee, and ladder from fresh market account bytes.
+    /// `update` never touches the metadata and fails with `PhoenixAmmError::MetadataChanged`
+    /// once the header stops matching it (e.g. after a market migration or program upgrade);
+    /// calling this with the current account is how to recover without reconstructing. Like
+    /// the constructors, it rejects accounts the configured program does not own.
+    pub fn refresh_metadata(&mut self, account: &Account) -> Result<()> {
+        if account.owner != self.program_id {
+            return Err(PhoenixAmmError::InvalidMarketOwner.into());
+        }
+        let (header, bytes) = parse_market_account(&account.data)?;
+        if header.base_params.mint_key == header.quote_params.mint_key {
+            return Err(PhoenixAmmError::DegenerateMarket.into());
+        }
+        let market = load_with_dispatch(&header.market_size_params, bytes)?;
+        self.market_metadata = market_metadata_from_header(header)?;
+        self.base_mint = header.base_params.mint_key;
+        self.quote_mint = header.quote_params.mint_key;
+        self.base_vault = Self::derive_vault(&self.program_id, &self.market_key, &self.base_mint);
+        self.quote_vault = Self::derive_vault(&self.program_id, &self.market_key, &self.quote_mint);
+        self.taker_fee_bps = market.inner.get_taker_fee_bps() as u16;
+        self.refill_ladder(market.inner);
+        self.market_sequence_number = Some(header.market_sequence_number);
+        self.market_status = MarketStatus::from(header.status);
+        self.authority = header.authority;
+        self.market_fee_recipient = header.fee_recipient;
+        Ok(())
     }
-}
 
-impl Amm for JupiterPhoenix {
-    fn program_id(&self) -> Pubkey {
-        self.program_id
+    /// The token program swaps on this market go through. The market header does not record
+    /// it, so this is SPL Token until `detect_token_program` is given the mint accounts.
+    pub fn token_program(&self) -> Pubkey {
+        self.token_program
     }
 
-    fn from_keyed_account(keyed_account: &KeyedAccount) -> Result<Self> {
-        JupiterPhoenix::new_from_keyed_account(keyed_account)
+    /// The market's base token vault, the PDA with seeds `[b"vault", market, base mint]`. Its
+    /// token balance is all the base the market holds, resting orders and seat deposits alike.
+    pub fn base_vault(&self) -> Pubkey {
+        self.base_vault
     }
 
-    fn label(&self) -> String {
-        self.label.clone()
+    /// The market's quote token vault, the PDA with seeds `[b"vault", market, quote mint]`
+    pub fn quote_vault(&self) -> Pubkey {
+        self.quote_vault
     }
 
-    fn key(&self) -> Pubkey {
-        self.market_key
+    /// Also lists the base and quote vaults in `get_accounts_to_update`, after the market and
+    /// the clock, so callers that fetch those accounts get the vault balances alongside the
+    /// market. `update` does not read them. Off by default; set it before handing the market to
+    /// a router, since the accounts to update are only queried once.
+    pub fn set_vaults_in_accounts_to_update(&mut self, vaults_in_accounts_to_update: bool) {
+        self.vaults_in_accounts_to_update = vaults_in_accounts_to_update;
     }
 
-    fn get_reserve_mints(&self) -> Vec<Pubkey> {
-        vec![self.base_mint, self.quote_mint]
+    /// Sets the token program from the owner of the market's mint accounts, for markets
+    /// created on Token-2022 mints. A swap takes a single token program, so both mints must be
+    /// owned by the same one, either SPL Token or Token-2022.
+    pub fn detect_token_program(
+        &mut self,
+        base_mint_account: &Account,
+        quote_mint_account: &Account,
+    ) -> Result<()> {
+        if base_mint_account.owner != quote_mint_account.owner {
+            return Err(Error::msg(
+                "Base and quote mints are owned by different token programs",
+            ));
+        }
+        let token_program = base_mint_account.owner;
+        if token_program != spl_token::id() && token_program != TOKEN_2022_PROGRAM_ID {
+            return Err(Error::msg("Mints are not owned by a token program"));
+        }
+        self.token_program = token_program;
+        Ok(())
     }
 
-    fn get_accounts_to_update(&self) -> Vec<Pubkey> {
-        vec![self.market_key]
+    /// Applies a market account update from a geyser stream. `data` is the full market account
+    /// data as delivered by the account-update notification (header followed by the market),
+    /// the same bytes `update` reads from the accounts map. Geyser may deliver updates out of
+    /// order, so an update for a slot older than the last applied one is ignored. Failures are
+    /// handled like `update` failures, including the update failure policy.
+    pub fn update_from_geyser(&mut self, data: &[u8], slot: u64) -> Result<()> {
+        if matches!(self.last_update_slot, Some(last_slot) if slot < last_slot) {
+            return Ok(());
+        }
+        let result = self.load_ladder_from_data(data);
+        self.record_update_result(result)?;
+        self.last_update_slot = Some(slot);
+        Ok(())
     }
 
-    fn update(&mut self, accounts_map: &HashMap<Pubkey, PartialAccount>) -> Result<()> {
-        let market_account = accounts_map.get(&self.market_key).unwrap();
-        let (header_bytes, bytes) = &market_account.data.split_at(size_of::<MarketHeader>());
-        let header = bytemuck::try_from_bytes::<MarketHeader>(header_bytes).unwrap();
-        let market = load_with_dispatch(&header.market_size_params, bytes)?;
-        self.ladder = market.inner.get_ladder(u64::MAX);
-        Ok(())
+    /// Slot of the last update applied through `update_from_geyser`, for staleness checks.
+    /// `None` until the first geyser update; `update` does not know slots and leaves it as is.
+    pub fn last_update_slot(&self) -> Option<u64> {
+        self.last_update_slot
     }
 
-    fn quote(&self, quote_params: &QuoteParams) -> Result<Quote> {
-        let mut out_amount = 0;
-        if quote_params.input_mint == self.base_mint {
-            let mut base_lot_budget = quote_params.in_amount / self.base_atoms_per_base_lot;
-            for LadderOrder {
-                price_in_ticks,
-                size_in_base_lots,
-            } in self.ladder.bids.iter()
-            {
-                if base_lot_budget == 0 {
-                    break;
-                }
-                out_amount += self.base_lots_and_price_to_quote_atoms(
-                    *size_in_base_lots.min(&base_lot_budget),
-                    *price_in_ticks,
-                );
-                base_lot_budget = base_lot_budget.saturating_sub(*size_in_base_lots);
-            }
-        } else {
-            let mut quote_lot_budget = quote_params.in_amount / self.quote_atoms_per_quote_lot;
-            for LadderOrder {
-                price_in_ticks,
-                size_in_base_lots,
-            } in self.ladder.asks.iter()
-            {
-                if quote_lot_budget == 0 {
-                    break;
-                }
-                let book_amount_in_quote_lots =
-                    self.base_lots_and_price_to_quote_atoms(*size_in_base_lots, *price_in_ticks);
-
-                out_amount += size_in_base_lots.min(
-                    &((quote_lot_budget * self.num_base_lots_per_base_unit)
-                        / (self.tick_size_in_quote_atoms_per_base_unit * price_in_ticks)),
-                ) * self.base_atoms_per_base_lot;
-                quote_lot_budget = quote_lot_budget.saturating_sub(book_amount_in_quote_lots);
-            }
+    /// Sets what `update` does with the cached ladder when it fails. Defaults to
+    /// `UpdateFailurePolicy::KeepLadder`.
+    pub fn set_update_failure_policy(&mut self, update_failure_policy: UpdateFailurePolicy) {
+        self.update_failure_policy = update_failure_policy;
+    }
+
+    /// Sets how quoted output amounts are rounded. Defaults to `RoundingMode::Floor`, which
+    /// matches the program's truncation for sells.
+    pub fn set_rounding_mode(&mut self, rounding_mode: RoundingMode) {
+        self.rounding_mode = rounding_mode;
+    }
+
+    /// Sets how quote inputs that are not a whole number of lots (base lots for sells, quote
+    /// lots for buys) are rounded to the lot budget walked against the book. Defaults to
+    /// `RoundingMode::Floor`, which is what the program does with the atoms it is given.
+    /// `Ceil`, and `Nearest` with a remainder of at least half a lot, quote one more lot than
+    /// `in_amount` pays for, so the quote may consume more input than provided. They are only
+    /// meaningful for simulation: swap instructions always floor the input.
+    pub fn set_input_rounding_mode(&mut self, input_rounding_mode: RoundingMode) {
+        self.input_rounding_mode = input_rounding_mode;
+    }
+
+    /// `in_amount` in whole lots of `lot_size`, rounded by the input rounding mode
+    fn input_lots(&self, in_amount: u64, lot_size: u64) -> u64 {
+        let (lots, remainder) = (in_amount / lot_size, in_amount % lot_size);
+        let round_up = match self.input_rounding_mode {
+            RoundingMode::Floor => false,
+            RoundingMode::Ceil => remainder > 0,
+            RoundingMode::Nearest => remainder > 0 && remainder >= lot_size - remainder,
         };
+        lots + round_up as u64
+    }
 
-        // Not 100% accurate, but it's a reasoanble enough approximation
-        Ok(Quote {
-            out_amount: (out_amount * (10000 - self.taker_fee_bps as u64)) / 10000,
-            ..Quote::default()
-        })
+    /// Sets which `Swap` variant swap legs are emitted as. Defaults to `SwapLeg::Serum` for
+    /// routers that predate `Swap::Phoenix`.
+    pub fn set_swap_leg(&mut self, swap_leg: SwapLeg) {
+        self.swap_leg = swap_leg;
     }
 
-    fn get_swap_leg_and_account_metas(
-        &self,
-        swap_params: &SwapParams,
-    ) -> Result<SwapAndAccountMetas> {
-        let SwapParams {
-            destination_mint,
-            source_mint,
-            user_destination_token_account,
-            user_source_token_account,
-            user_transfer_authority,
-            ..
-        } = swap_params;
+    /// Appends `account_metas` to the metas `get_swap_leg_and_account_metas` returns, after the
+    /// accounts of Phoenix's `Swap` instruction, for routers or program versions that need
+    /// additional accounts such as a trader seat. No current market configuration does: the
+    /// `Swap` instruction never takes a seat, whatever the market's status or authority, and only
+    /// the resting-order instructions do.
+    pub fn set_extra_swap_account_metas(&mut self, account_metas: Vec<AccountMeta>) {
+        self.extra_swap_account_metas = account_metas;
+    }
 
-        let log_authority = Pubkey::find_program_address(&["log".as_ref()], &self.program_id).0;
+    /// Adds `fee_recipient` to the swap metas as a read-only account, after the accounts of
+    /// Phoenix's `Swap` instruction and before any `set_extra_swap_account_metas`. `Swap` has no
+    /// slot for it: only the routing program that invokes the swap reads it, to find where to
+    /// pay its referral fee, while Phoenix ignores trailing accounts and its taker fees always
+    /// accrue to the market. `None` restores the default layout.
+    pub fn set_fee_recipient(&mut self, fee_recipient: Option<Pubkey>) {
+        self.fee_recipient = fee_recipient;
+    }
 
-        let (side, base_account, quote_account) = if source_mint == &self.base_mint {
-            if destination_mint != &self.quote_mint {
-                return Err(Error::msg("Invalid quote mint"));
-            }
-            (
-                Side::Ask,
-                user_source_token_account,
-                user_destination_token_account,
-            )
-        } else {
-            if destination_mint != &self.base_mint {
-                return Err(Error::msg("Invalid base mint"));
-            }
-            (
-                Side::Bid,
-                user_destination_token_account,
-                user_source_token_account,
-            )
-        };
+    /// The referral fee account set by `set_fee_recipient`, if any. Not to be confused with
+    /// `market_fee_recipient`, where the market's taker fees accrue.
+    pub fn fee_recipient(&self) -> Option<Pubkey> {
+        self.fee_recipient
+    }
 
-        let base_vault = Pubkey::find_program_address(
-            &[b"vault", self.market_key.as_ref(), self.base_mint.as_ref()],
+    /// The seat PDA of `trader` on this market, for `set_extra_swap_account_metas`
+    pub fn seat_address(&self, trader: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(
+            &[b"seat", self.market_key.as_ref(), trader.as_ref()],
             &self.program_id,
         )
-        .0;
+        .0
+    }
 
-        let quote_vault = Pubkey::find_program_address(
-            &[b"vault", self.market_key.as_ref(), self.quote_mint.as_ref()],
-            &self.program_id,
-        )
-        .0;
+    /// Number of `update` calls that have failed in a row. Reset to zero by a successful
+    /// update, so a growing count means the cached ladder is getting staler.
+    pub fn consecutive_update_failures(&self) -> u32 {
+        self.consecutive_update_failures
+    }
 
-        let account_metas = vec![
-            AccountMeta::new(self.market_key, false),
-            AccountMeta::new(*user_transfer_authority, true),
-            AccountMeta::new_readonly(log_authority, false),
-            AccountMeta::new_readonly(self.program_id, false),
-            AccountMeta::new(*base_account, false),
-            AccountMeta::new(*quote_account, false),
-            AccountMeta::new(base_vault, false),
-            AccountMeta::new(quote_vault, false),
-            AccountMeta::new_readonly(spl_token::id(), false),
-        ];
+    pub fn get_base_decimals(&self) -> u32 {
+        self.base_decimals
+    }
 
-        Ok(SwapAndAccountMetas {
-            swap: Swap::Serum { side },
-            account_metas,
-        })
+    pub fn get_quote_decimals(&self) -> u32 {
+        self.quote_decimals
     }
 
-    fn clone_amm(&self) -> Box<dyn Amm + Send + Sync> {
-        Box::new(self.clone())
+    /// `atoms` of the base mint in whole units, e.g. 1.5 SOL. Exact below 2^53 atoms, see
+    /// `base_atoms_to_ui_parts` for larger amounts.
+    pub fn base_atoms_to_ui(&self, atoms: u64) -> f64 {
+        atoms_to_ui(atoms, self.base_decimals)
     }
-}
 
-#[test]
-fn test_jupiter_phoenix_integration() {
-    use jupiter_core::amm::Amm;
-    use solana_client::rpc_client::RpcClient;
-    use solana_sdk::pubkey;
-    use solana_sdk::pubkey::Pubkey;
-    use std::collections::HashMap;
+    /// `atoms` of the quote mint in whole units, like `base_atoms_to_ui`
+    pub fn quote_atoms_to_ui(&self, atoms: u64) -> f64 {
+        atoms_to_ui(atoms, self.quote_decimals)
+    }
 
-    const SOL_USDC_MARKET: Pubkey = pubkey!("4DoNfFBfF7UokCC2FQzriy7yHK6DY6NVdYpuekQ5pRgg");
-    const BONK_USDC_MARKET: Pubkey = pubkey!("GBMoNx84HsFdVK63t8BZuDgyZhSBaeKWB4pHHpoeRM9z");
+    /// `ui` whole units of the base mint in atoms, rounded to the nearest atom. Negative and NaN
+    /// amounts convert to zero and amounts past `u64::MAX` atoms to `u64::MAX`.
+    pub fn ui_to_base_atoms(&self, ui: f64) -> u64 {
+        ui_to_atoms(ui, self.base_decimals)
+    }
 
-    let rpc = RpcClient::new("https://api.mainnet-beta.solana.com/");
-    let account = rpc.get_account(&SOL_USDC_MARKET).unwrap();
+    /// `ui` whole units of the quote mint in atoms, like `ui_to_base_atoms`
+    pub fn ui_to_quote_atoms(&self, ui: f64) -> u64 {
+        ui_to_atoms(ui, self.quote_decimals)
+    }
 
-    let market_account = KeyedAccount {
-        key: SOL_USDC_MARKET,
-        account,
-        params: None,
-    };
+    /// `atoms` of the base mint split into whole units and the atoms left over, e.g. 1.5 SOL is
+    /// `(1, 500_000_000)`. Unlike `base_atoms_to_ui` this is exact for every amount.
+    pub fn base_atoms_to_ui_parts(&self, atoms: u64) -> (u64, u64) {
+        let atoms_per_unit = 10u64.pow(self.base_decimals);
+        (atoms / atoms_per_unit, atoms % atoms_per_unit)
+    }
 
-    let mut jupiter_phoenix = JupiterPhoenix::new_from_keyed_account(&market_account).unwrap();
+    /// `atoms` of the quote mint split into whole units and the atoms left over, like
+    /// `base_atoms_to_ui_parts`
+    pub fn quote_atoms_to_ui_parts(&self, atoms: u64) -> (u64, u64) {
+        let atoms_per_unit = 10u64.pow(self.quote_decimals);
+        (atoms / atoms_per_unit, atoms % atoms_per_unit)
+    }
 
-    let accounts_to_update = jupiter_phoenix.get_accounts_to_update();
+    /// The inverse of `base_atoms_to_ui_parts`. `None` if `fractional` is not below one whole
+    /// unit, or the amount overflows a u64.
+    pub fn ui_parts_to_base_atoms(&self, whole: u64, fractional: u64) -> Option<u64> {
+        ui_parts_to_atoms(whole, fractional, self.base_decimals)
+    }
 
-    let accounts_map = rpc
-        .get_multiple_accounts(&accounts_to_update)
-        .unwrap()
-        .iter()
-        .enumerate()
-        .fold(HashMap::new(), |mut m, (index, account)| {
-            if let Some(account) = account {
-                m.insert(
-                    accounts_to_update[index],
-                    PartialAccount::from(account.clone()),
-                );
-            }
-            m
-        });
-    jupiter_phoenix.update(&accounts_map).unwrap();
-    let in_amount = 1_000_000_000_000;
-    println!(
-        "Getting quote for selling {} SOL",
-        in_amount as f64 / 10.0_f64.powf(jupiter_phoenix.get_base_decimals() as f64)
-    );
-    let quote_in = in_amount as f64 / 10.0_f64.powf(jupiter_phoenix.get_base_decimals() as f64);
-    let quote = jupiter_phoenix
-        .quote(&QuoteParams {
-            /// 1 SOL
-            in_amount,
-            input_mint: jupiter_phoenix.base_mint,
-            output_mint: jupiter_phoenix.quote_mint,
-        })
-        .unwrap();
+    /// The inverse of `quote_atoms_to_ui_parts`, like `ui_parts_to_base_atoms`
+    pub fn ui_parts_to_quote_atoms(&self, whole: u64, fractional: u64) -> Option<u64> {
+        ui_parts_to_atoms(whole, fractional, self.quote_decimals)
+    }
 
-    let Quote { out_amount, .. } = quote;
+    /// Whether the market accepts taker orders. Only active markets do: post-only, paused,
+    /// closed (possibly being wound down) and tombstoned markets reject swaps, so `quote` and
+    /// `get_swap_leg_and_account_metas` fail with `PhoenixAmmError::MarketNotSwappable` for
+    /// them. Frozen vaults are not visible in the market account, so they are not detected.
+    pub fn is_swappable(&self) -> bool {
+        self.market_status.cross_allowed()
+    }
 
-    let quote_out = out_amount as f64 / 10.0_f64.powf(jupiter_phoenix.get_quote_decimals() as f64);
-    println!("Quote result: {:?} ({})", quote_out, quote_out / quote_in);
+    /// Whether the cached ladder has liquidity for a taker on `side`: any bids for `Side::Ask`
+    /// (selling base), any asks for `Side::Bid`. This only looks for a top level, so it is O(1).
+    pub fn has_liquidity(&self, side: Side) -> bool {
+        match side {
+            Side::Ask => !self.ladder.bids.is_empty(),
+            Side::Bid => !self.ladder.asks.is_empty(),
+        }
+    }
 
-    let in_amount = out_amount;
+    /// Whether a swap out of `input_mint` into the market's other mint can be quoted right now:
+    /// the market is swappable and has liquidity on the side the swap takes. Always `false` for
+    /// mints outside the market.
+    pub fn is_quotable(&self, input_mint: Pubkey) -> bool {
+        self.side_for_input_mint(&input_mint)
+            .is_ok_and(|side| self.is_swappable() && self.has_liquidity(side))
+    }
 
-    println!(
-        "Getting quote for buying SOL with {} USDC",
-        in_amount as f64 / 10.0_f64.powf(jupiter_phoenix.get_quote_decimals() as f64)
-    );
-    let quote_in = in_amount as f64 / 10.0_f64.powf(jupiter_phoenix.get_quote_decimals() as f64);
-    let quote = jupiter_phoenix
-        .quote(&QuoteParams {
-            in_amount,
-            input_mint: jupiter_phoenix.quote_mint,
-            output_mint: jupiter_phoenix.base_mint,
-        })
-        .unwrap();
+    /// Whether `self` and `other` quote every input identically: same market, mints, taker fee,
+    /// lot and tick sizes, status, rounding mode, and cached ladder. Bookkeeping that does not
+    /// change a quote, like the sequence number or the update failure count, is ignored, so an
+    /// update that leaves this `true` does not need re-quoting.
+    pub fn quote_eq(&self, other: &Self) -> bool {
+        let metadata = |jupiter_phoenix: &Self| {
+            let MarketMetadata {
+                base_mint,
+                quote_mint,
+                base_decimals,
+                quote_decimals,
+                base_atoms_per_raw_base_unit,
+                quote_atoms_per_quote_unit,
+                quote_atoms_per_quote_lot,
+                base_atoms_per_base_lot,
+                tick_size_in_quote_atoms_per_base_unit,
+                num_base_lots_per_base_unit,
+                raw_base_units_per_base_unit,
+                market_size_params: _,
+            } = jupiter_phoenix.market_metadata;
+            (
+                base_mint,
+                quote_mint,
+                base_decimals,
+                quote_decimals,
+                base_atoms_per_raw_base_unit,
+                quote_atoms_per_quote_unit,
+                quote_atoms_per_quote_lot,
+                base_atoms_per_base_lot,
+                tick_size_in_quote_atoms_per_base_unit,
+                num_base_lots_per_base_unit,
+                raw_base_units_per_base_unit,
+            )
+        };
+        self.market_key == other.market_key
+            && self.base_mint == other.base_mint
+            && self.quote_mint == other.quote_mint
+            && self.taker_fee_bps == other.taker_fee_bps
+            && metadata(self) == metadata(other)
+            && self.market_status == other.market_status
+            && self.rounding_mode == other.rounding_mode
+            && self.input_rounding_mode == other.input_rounding_mode
+            && self.ladder == other.ladder
+    }
 
-    let Quote { out_amount, .. } = quote;
+    /// Whether both of this market's mints are in `stable_mints`, for routers that treat
+    /// stable-stable pairs differently. The stable set is up to the caller.
+    pub fn is_likely_stable_pair(&self, stable_mints: &HashSet<Pubkey>) -> bool {
+        stable_mints.contains(&self.base_mint) && stable_mints.contains(&self.quote_mint)
+    }
 
-    let quote_out = out_amount as f64 / 10.0_f64.powf(jupiter_phoenix.get_base_decimals() as f64);
-    println!(
-        "Quote result: {:?} ({})",
-        out_amount as f64 / 10.0_f64.powf(jupiter_phoenix.get_base_decimals() as f64),
-        quote_in / quote_out
-    );
+    /// Whether `mint` is this market's base mint (`Some(true)`), its quote mint (`Some(false)`),
+    /// or not traded on this market at all (`None`)
+    pub fn is_base(&self, mint: &Pubkey) -> Option<bool> {
+        if *mint == self.base_mint {
+            Some(true)
+        } else if *mint == self.quote_mint {
+            Some(false)
+        } else {
+            None
+        }
+    }
 
-    let account = rpc.get_account(&BONK_USDC_MARKET).unwrap();
+    /// The price increment, in quote atoms per base unit. A price of `n` ticks is
+    /// `n * tick_size_in_quote_atoms_per_base_unit()` quote atoms per base unit, where a base
+    /// unit is `raw_base_units_per_base_unit` whole base tokens (one for most markets, a
+    /// million BONK for BONK/USDC).
+    pub fn tick_size_in_quote_atoms_per_base_unit(&self) -> u64 {
+        self.market_metadata.tick_size_in_quote_atoms_per_base_unit
+    }
 
-    let market_account = KeyedAccount {
-        key: BONK_USDC_MARKET,
-        account,
-        params: None,
-    };
+    /// The base size increment, in base atoms per base lot. Order sizes are whole base lots.
+    pub fn base_lot_size(&self) -> u64 {
+        self.market_metadata.base_atoms_per_base_lot
+    }
 
-    let mut jupiter_phoenix = JupiterPhoenix::new_from_keyed_account(&market_account).unwrap();
+    /// The quote size increment, in quote atoms per quote lot
+    pub fn quote_lot_size(&self) -> u64 {
+        self.market_metadata.quote_atoms_per_quote_lot
+    }
 
-    let accounts_to_update = jupiter_phoenix.get_accounts_to_update();
+    /// Base atoms (the smallest unit of the base mint) per base lot, the same as
+    /// `base_lot_size`
+    pub fn base_atoms_per_base_lot(&self) -> u64 {
+        self.market_metadata.base_atoms_per_base_lot
+    }
 
-    let accounts_map = rpc
-        .get_multiple_accounts(&accounts_to_update)
-        .unwrap()
-        .iter()
-        .enumerate()
-        .fold(HashMap::new(), |mut m, (index, account)| {
-            if let Some(account) = account {
-                m.insert(
-                    accounts_to_update[index],
-                    PartialAccount::from(account.clone()),
-                );
-            }
-            m
-        });
-    jupiter_phoenix.update(&accounts_map).unwrap();
-    let in_amount = 100_000_000_000_000;
-    println!(
-        "Getting quote for selling {} BONK",
-        in_amount as f64 / 10.0_f64.powf(jupiter_phoenix.get_base_decimals() as f64)
-    );
-    let quote_in = in_amount as f64 / 10.0_f64.powf(jupiter_phoenix.get_base_decimals() as f64);
-    let quote = jupiter_phoenix
-        .quote(&QuoteParams {
-            /// 1B Bonk
-            in_amount,
-            input_mint: jupiter_phoenix.base_mint,
-            output_mint: jupiter_phoenix.quote_mint,
-        })
-        .unwrap();
+    /// Quote atoms (the smallest unit of the quote mint) per quote lot, the same as
+    /// `quote_lot_size`
+    pub fn quote_atoms_per_quote_lot(&self) -> u64 {
+        self.market_metadata.quote_atoms_per_quote_lot
+    }
 
-    let Quote { out_amount, .. } = quote;
+    /// Base lots per base unit, the divisor that turns base lots times a price per base unit
+    /// into quote: `base_lots * price_in_ticks * tick_size_in_quote_atoms_per_base_unit() /
+    /// num_base_lots_per_base_unit()` quote atoms, rounded down like the program.
+    pub fn num_base_lots_per_base_unit(&self) -> u64 {
+        self.market_metadata.num_base_lots_per_base_unit
+    }
 
-    let quote_out = out_amount as f64 / 10.0_f64.powf(jupiter_phoenix.get_quote_decimals() as f64);
+    /// Quotes a swap of `in_amount` atoms for a taker on `side`, using the same mapping as
+    /// `get_swap_leg_and_account_metas`: `Side::Ask` sells base for quote (walks the bids) and
+    /// `Side::Bid` buys base with quote (walks the asks). Errors with `PhoenixAmmError::EmptyBook`
+    /// if the side being walked has no orders, and with `PhoenixAmmError::MarketNotSwappable` if
+    /// the market does not accept taker orders. `in_amount` is the input that actually trades:
+    /// whole lots of it, and for buys what the fill and taker fee cost. If the book runs out
+    /// while whole lots of the input are left, `not_enough_liquidity` is set, so probing with a
+    /// size far beyond the book clamps to the whole book. Buys
+    /// with more quote lots than the program can hold, `u64::MAX` divided by the base lots per
+    /// base unit, error with `PhoenixAmmError::AmountOverflow` instead, since no swap that large
+    /// can execute. Dust below one lot never counts as unfilled. A zero `in_amount` on a
+    /// swappable market always quotes to the default (all zero) `Quote`, without looking at the
+    /// book. The taker fee is reported in `fee_amount`, in quote atoms (Phoenix charges it in
+    /// quote in both directions), and `fee_pct` is that fee as a fraction of the quote matched.
+    /// `price_impact_pct` is how much worse the average fill price is than the top of book, as a
+    /// fraction of the top of book price.
+    pub fn quote_side(&self, side: Side, in_amount: u64) -> Result<Quote> {
+        if !self.is_swappable() {
+            return Err(PhoenixAmmError::MarketNotSwappable.into());
+        }
+        self.quote_on_ladder(&self.ladder, &side, in_amount)
+    }
+
+    /// `quote` against `ladder` instead of the cached one, with this market's lot sizes, ticks
+    /// and taker fee, e.g. to see what a swap gets once a large order has taken the top level.
+    /// `quote` itself is this with the cached ladder. Like `quote`, it errors unless the market
+    /// accepts taker orders.
+    pub fn quote_against_ladder(
+        &self,
+        ladder: &Ladder,
+        quote_params: &QuoteParams,
+    ) -> Result<Quote> {
+        let side = self.side_for_mints(&quote_params.input_mint, &quote_params.output_mint)?;
+        if !self.is_swappable() {
+            return Err(PhoenixAmmError::MarketNotSwappable.into());
+        }
+        self.quote_on_ladder(ladder, &side, quote_params.in_amount)
+    }
+
+    /// Quotes `quote_params` as if every level of the book were `tick_shift` ticks worse for
+    /// the taker: bids are lowered and asks are raised by `tick_shift` (a negative shift
+    /// improves the book instead). Prices clamp at zero, and levels that reach zero are dropped
+    /// since nothing can rest there. The cached ladder is left untouched.
+    pub fn quote_with_price_shift(
+        &self,
+        quote_params: &QuoteParams,
+        tick_shift: i64,
+    ) -> Result<Quote> {
+        let shift_levels = |levels: &[LadderOrder], tick_shift: i64| {
+            levels
+                .iter()
+                .map(|level| LadderOrder {
+                    price_in_ticks: level.price_in_ticks.saturating_add_signed(tick_shift),
+                    size_in_base_lots: level.size_in_base_lots,
+                })
+                .filter(|level| level.price_in_ticks > 0)
+                .collect()
+        };
+        let ladder = Ladder {
+            bids: shift_levels(&self.ladder.bids, tick_shift.saturating_neg()),
+            asks: shift_levels(&self.ladder.asks, tick_shift),
+        };
+        let side = self.side_for_mints(&quote_params.input_mint, &quote_params.output_mint)?;
+        self.quote_on_ladder(&ladder, &side, quote_params.in_amount)
+    }
+
+    /// How much more output `quote_params` gets against the cached ladder than it did against
+    /// `prev_ladder`, in atoms of the output mint. A negative delta means the book got worse
+    /// for this trade, e.g. because liquidity was pulled.
+    pub fn quote_delta(&self, prev_ladder: &Ladder, quote_params: &QuoteParams) -> Result<i128> {
+        let side = self.side_for_mints(&quote_params.input_mint, &quote_params.output_mint)?;
+        let prev_quote = self.quote_on_ladder(prev_ladder, &side, quote_params.in_amount)?;
+        let quote = self.quote_on_ladder(&self.ladder, &side, quote_params.in_amount)?;
+        Ok(quote.out_amount as i128 - prev_quote.out_amount as i128)
+    }
+
+    /// Quotes selling `base_in` base atoms for quote, then buying base back with everything the
+    /// sale returns. The second leg spends the first leg's `out_amount`, which is already net of
+    /// the taker fee and rounded to whole quote lots, and pays the fee again, so the base it
+    /// gets back reflects the spread and both fees. Both legs are quoted against the same cached
+    /// ladder, as if nothing traded in between.
+    pub fn quote_round_trip(&self, base_in: u64) -> Result<(Quote, Quote)> {
+        let sell = self.quote(&QuoteParams {
+            in_amount: base_in,
+            input_mint: self.base_mint,
+            output_mint: self.quote_mint,
+        })?;
+        let buy = self.quote(&QuoteParams {
+            in_amount: sell.out_amount,
+            input_mint: self.quote_mint,
+            output_mint: self.base_mint,
+        })?;
+        Ok((sell, buy))
+    }
+
+    /// Quotes every amount in `amounts` from `input_mint` into `output_mint`, walking the cached
+    /// ladder once instead of once per amount. The amounts are walked in ascending order, each
+    /// picking up from the levels the smaller ones filled completely, but the quotes come back
+    /// in the order of `amounts`. Each quote is identical to `quote` with that amount, and if
+    /// any of those would fail the whole batch fails with its error.
+    pub fn quote_batch(
+        &self,
+        amounts: &[u64],
+        input_mint: Pubkey,
+        output_mint: Pubkey,
+    ) -> Result<Vec<Quote>> {
+        let side = self.side_for_mints(&input_mint, &output_mint)?;
+        let mut walker = LadderWalker::new(self, &self.ladder, &side, self.taker_fee_bps as u64)?;
+
+        let mut order: Vec<usize> = (0..amounts.len()).collect();
+        order.sort_by_key(|&i| amounts[i]);
+        let mut quotes = vec![Quote::default(); amounts.len()];
+        // The walk through every level the amounts so far filled completely, and the budget
+        // it spent there
+        let mut filled = LadderWalk::default();
+        let mut filled_budget = 0;
+        for i in order {
+            let in_amount = amounts[i];
+            if in_amount == 0 {
+                continue;
+            }
+            if walker.levels().is_empty() {
+                return Err(PhoenixAmmError::EmptyBook.into());
+            }
+            let budget = walker.budget(in_amount)?;
+            let walk = walker.walk_increasing(&mut filled, &mut filled_budget, budget)?;
+            quotes[i] = self.quote_from_walk(&self.ladder, &side, &walk);
+        }
+        Ok(quotes)
+    }
+
+    /// `quote_side` against `ladder` instead of the cached one
+    fn quote_on_ladder(&self, ladder: &Ladder, side: &Side, in_amount: u64) -> Result<Quote> {
+        if in_amount == 0 {
+            return Ok(Quote::default());
+        }
+        let walk = self.walk_ladder_on(ladder, side, in_amount)?;
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("levels_walked", walk.levels_touched as u64);
+        Ok(self.quote_from_walk(ladder, side, &walk))
+    }
+
+    /// The quote for `walk`, a walk of `ladder` for a taker on `side`
+    fn quote_from_walk(&self, ladder: &Ladder, side: &Side, walk: &LadderWalk) -> Quote {
+        let not_enough_liquidity = walk.remaining_budget > 0;
+        let (out_amount, fee_amount) = self.apply_taker_fee(side, walk);
+        // The effective rate against the quote matched, which rounding can push off the
+        // nominal taker fee
+        let matched_quote_atoms = match side {
+            Side::Ask => out_amount.saturating_add(fee_amount),
+            Side::Bid => walk.in_amount_consumed - fee_amount,
+        };
+        let fee_pct = if fee_amount == 0 {
+            Decimal::ZERO
+        } else {
+            Decimal::from(fee_amount) / Decimal::from(matched_quote_atoms)
+        };
+
+        Quote {
+            out_amount,
+            fee_amount,
+            fee_mint: self.quote_mint,
+            fee_pct,
+            price_impact_pct: self.price_impact_pct(ladder, side, walk),
+            not_enough_liquidity,
+            // What actually trades; partial fills are flagged by `not_enough_liquidity` alone
+            in_amount: walk.in_amount_consumed,
+            ..Quote::default()
+        }
+    }
+
+    /// How far the average execution price of `walk` is from the top of book on `ladder`, as a
+    /// fraction of the top of book price. The taker fee is not included. Zero when nothing
+    /// fills or every fill is at the top of book.
+    fn price_impact_pct(&self, ladder: &Ladder, side: &Side, walk: &LadderWalk) -> Decimal {
+        let (top_of_book, filled_base_lots) = match side {
+            Side::Ask => (
+                ladder.bids.first(),
+                walk.in_amount_consumed / self.base_atoms_per_base_lot,
+            ),
+            Side::Bid => (
+                ladder.asks.first(),
+                walk.out_amount / self.base_atoms_per_base_lot,
+            ),
+        };
+        let Some(top_of_book) = top_of_book else {
+            return Decimal::ZERO;
+        };
+        // Both in lots times ticks, so the ratio is exact
+        let top_of_book_lot_ticks = top_of_book.price_in_ticks as u128 * filled_base_lots as u128;
+        if top_of_book_lot_ticks == 0 {
+            return Decimal::ZERO;
+        }
+        let deviation = walk.matched_lot_ticks.abs_diff(top_of_book_lot_ticks);
+        // Decimal mantissas are 96 bits, so drop low bits from both sides of huge fills
+        let shift = (128 - deviation.max(top_of_book_lot_ticks).leading_zeros()).saturating_sub(96);
+        Decimal::from_i128_with_scale((deviation >> shift) as i128, 0)
+            / Decimal::from_i128_with_scale((top_of_book_lot_ticks >> shift).max(1) as i128, 0)
+    }
+
+    /// Whether `quote_exact_out` is available. The pinned `Amm` trait has no ExactOut swap mode
+    /// for routers to query, so this is exposed on the market directly.
+    pub fn supports_exact_out(&self) -> bool {
+        true
+    }
+
+    /// ExactOut counterpart of `quote`: quotes the smallest input, in whole lots of
+    /// `input_mint`, that receives at least `out_amount` atoms of the other mint after the
+    /// taker fee. The fee is grossed up on the same side `quote` charges it, so quoting the
+    /// returned `in_amount` with `quote` yields at least `out_amount`, and one lot less does
+    /// not. If the whole side of the book cannot produce `out_amount`, the quote takes all of
+    /// it, reporting the input that buys out the side, and sets `not_enough_liquidity`.
+    pub fn quote_exact_out(&self, input_mint: &Pubkey, out_amount: u64) -> Result<Quote> {
+        let side = self.side_for_input_mint(input_mint)?;
+        if !self.is_swappable() {
+            return Err(PhoenixAmmError::MarketNotSwappable.into());
+        }
+        if out_amount == 0 {
+            return Ok(Quote::default());
+        }
+        // Enough input lots to take every level on the side being walked. Extreme books can
+        // need more than a swap can hold, so this saturates, and is capped at the most input
+        // lots a swap can take: whatever fits in a u64 of atoms, and for buys in the program's
+        // u64 of adjusted quote lots.
+        let (in_atoms_per_lot, hi) = match side {
+            Side::Ask => (
+                self.base_atoms_per_base_lot,
+                self.ladder.bids.iter().fold(0u64, |lots, level| {
+                    lots.saturating_add(level.size_in_base_lots)
+                }),
+            ),
+            Side::Bid => {
+                let quote_lots = self.ladder.asks.iter().fold(0u64, |lots, level| {
+                    let quote_atoms = self
+                        .quote_atoms_for_base_lots(level.size_in_base_lots, level.price_in_ticks)
+                        .unwrap_or(u64::MAX);
+                    lots.saturating_add(
+                        (quote_atoms / self.quote_atoms_per_quote_lot).saturating_add(1),
+                    )
+                });
+                // Buys pay the fee on top, out of the same budget
+                let fee_in_quote_lots = (quote_lots as u128 * self.taker_fee_bps as u128)
+                    .div_ceil(10000)
+                    .min(u64::MAX as u128) as u64;
+                (
+                    self.quote_atoms_per_quote_lot,
+                    quote_lots
+                        .saturating_add(fee_in_quote_lots)
+                        .saturating_add(1)
+                        .min(u64::MAX / self.num_base_lots_per_base_unit),
+                )
+            }
+        };
+        let mut hi = hi.min(u64::MAX / in_atoms_per_lot);
+        let out_amount_for = |in_lots: u64| -> Result<u64> {
+            match self.walk_ladder(&side, in_lots * in_atoms_per_lot) {
+                Ok(walk) => Ok(self.apply_taker_fee(&side, &walk).0),
+                // Output past a u64 is more than any `out_amount`
+                Err(err) if err.downcast_ref() == Some(&PhoenixAmmError::AmountOverflow) => {
+                    Ok(u64::MAX)
+                }
+                Err(err) => Err(err),
+            }
+        };
+
+        if out_amount_for(hi)? < out_amount {
+            // `hi` is padded past the book, so report the input the walk actually takes
+            return Ok(Quote {
+                not_enough_liquidity: true,
+                ..self.quote_on_ladder(&self.ladder, &side, hi * in_atoms_per_lot)?
+            });
+        }
+        // The output only grows with the input, so binary search for the smallest input lots
+        // that are enough, keeping `lo` too small and `hi` enough
+        let mut lo = 0;
+        while hi - lo > 1 {
+            let mid = lo + (hi - lo) / 2;
+            if out_amount_for(mid)? >= out_amount {
+                hi = mid;
+            } else {
+                lo = mid;
+            }
+        }
+        Ok(Quote {
+            in_amount: hi * in_atoms_per_lot,
+            ..self.quote_on_ladder(&self.ladder, &side, hi * in_atoms_per_lot)?
+        })
+    }
+
+    /// Like `quote`, but as if the market charged no taker fee: sells receive the whole matched
+    /// size, and buys spend their whole budget on the book. The other fields are filled in like
+    /// `quote`, with a zero fee, and `in_amount` is the input consumed.
+    pub fn quote_no_fee(&self, quote_params: &QuoteParams) -> Result<Quote> {
+        if quote_params.in_amount == 0 {
+            return Ok(Quote::default());
+        }
+        let side = self.side_for_mints(&quote_params.input_mint, &quote_params.output_mint)?;
+        let walk =
+            self.walk_ladder_with_fee(&self.ladder, &side, quote_params.in_amount, 0, None, None)?;
+        Ok(self.quote_from_walk(&self.ladder, &side, &walk))
+    }
+
+    /// Like `quote`, but reports exactly how much of the input is consumed and how much goes to
+    /// the taker fee, so the next leg of a route does not have to assume the
+    /// whole input was used
+    pub fn quote_detailed(&self, quote_params: &QuoteParams) -> Result<QuoteDetails> {
+        let side = self.side_for_mints(&quote_params.input_mint, &quote_params.output_mint)?;
+        let (in_mint, out_mint) = (quote_params.input_mint, quote_params.output_mint);
+        let (in_amount_consumed, out_amount, fee_amount) = if quote_params.in_amount == 0 {
+            (0, 0, 0)
+        } else {
+            let walk = self.walk_ladder(&side, quote_params.in_amount)?;
+            let (out_amount, fee_amount) = self.apply_taker_fee(&side, &walk);
+            (walk.in_amount_consumed, out_amount, fee_amount)
+        };
+        let (in_amount_in_lots, lot_remainder) =
+            self.split_input_at_lots(&quote_params.input_mint, quote_params.in_amount)?;
+        Ok(QuoteDetails {
+            in_amount_consumed,
+            in_amount_in_lots,
+            lot_remainder,
+            out_amount,
+            fee_amount,
+            in_mint,
+            out_mint,
+        })
+    }
+
+    /// `quote`, along with every level the fill takes liquidity from, best first, from the same
+    /// walk. For sells the fills' `quote_atoms` add up to the proceeds before the taker fee,
+    /// `out_amount + fee_amount`, and for buys their base lots add up to `out_amount`.
+    pub fn quote_with_fills(&self, quote_params: &QuoteParams) -> Result<(Quote, Vec<FillLevel>)> {
+        let side = self.side_for_mints(&quote_params.input_mint, &quote_params.output_mint)?;
+        if !self.is_swappable() {
+            return Err(PhoenixAmmError::MarketNotSwappable.into());
+        }
+        if quote_params.in_amount == 0 {
+            return Ok((Quote::default(), vec![]));
+        }
+        let mut fills = vec![];
+        let walk = self.walk_ladder_with_fee(
+            &self.ladder,
+            &side,
+            quote_params.in_amount,
+            self.taker_fee_bps as u64,
+            Some(&mut fills),
+            None,
+        )?;
+        Ok((self.quote_from_walk(&self.ladder, &side, &walk), fills))
+    }
+
+    /// Splits `in_amount` of `input_mint` into the part that is a whole number of lots and the
+    /// atoms left over below one lot, which `quote` drops before walking the book with the
+    /// default input rounding mode. Fails with `PhoenixAmmError::MintNotInMarket` for mints
+    /// outside the market.
+    pub fn split_input_at_lots(&self, input_mint: &Pubkey, in_amount: u64) -> Result<(u64, u64)> {
+        let lot_size = match self.side_for_input_mint(input_mint)? {
+            Side::Ask => self.base_atoms_per_base_lot,
+            Side::Bid => self.quote_atoms_per_quote_lot,
+        };
+        let lot_remainder = in_amount % lot_size;
+        Ok((in_amount - lot_remainder, lot_remainder))
+    }
+
+    /// The taker side of a swap from `input_mint` into `output_mint`, which must be the
+    /// market's base and quote mints, one each
+    fn side_for_mints(&self, input_mint: &Pubkey, output_mint: &Pubkey) -> Result<Side> {
+        match (self.is_base(input_mint), self.is_base(output_mint)) {
+            (Some(true), Some(false)) => Ok(Side::Ask),
+            (Some(false), Some(true)) => Ok(Side::Bid),
+            (Some(true), _) => Err(PhoenixAmmError::InvalidQuoteMint.into()),
+            (Some(false), _) => Err(PhoenixAmmError::InvalidBaseMint.into()),
+            (None, _) => Err(PhoenixAmmError::MintNotInMarket.into()),
+        }
+    }
+
+    /// The taker side for a swap out of `input_mint` into the market's other mint: selling base
+    /// is an ask, selling quote a bid
+    fn side_for_input_mint(&self, input_mint: &Pubkey) -> Result<Side> {
+        match self.is_base(input_mint) {
+            Some(true) => Ok(Side::Ask),
+            Some(false) => Ok(Side::Bid),
+            None => Err(PhoenixAmmError::MintNotInMarket.into()),
+        }
+    }
+
+    /// Splits `total_in` into `slices` equal slices (the last one also takes the remainder) and
+    /// quotes each slice as if they were executed one after another against the current static
+    /// ladder. Each slice fills from where the previous one left off, and the book is assumed to
+    /// never replenish between slices, so later slices see progressively worse prices. Each
+    /// slice is rounded to lots and charged the taker fee like a swap of just that slice, and
+    /// reports the input it consumes, so the first slice quotes exactly like `quote` of it. With
+    /// the default `RoundingMode::Floor`, the outputs sum to at most a single full-size quote.
+    pub fn quote_slice(&self, side: Side, total_in: u64, slices: u32) -> Result<Vec<Quote>> {
+        if slices == 0 {
+            return Err(Error::msg("Must quote at least one slice"));
+        }
+        let mut walker = LadderWalker::new(self, &self.ladder, &side, self.taker_fee_bps as u64)?;
+        let slice_in = total_in / slices as u64;
+
+        let mut quotes = Vec::with_capacity(slices as usize);
+        for slice in 0..slices {
+            let in_amount = if slice + 1 == slices {
+                total_in - slice_in * (slices as u64 - 1)
+            } else {
+                slice_in
+            };
+            if in_amount == 0 {
+                quotes.push(Quote::default());
+                continue;
+            }
+            if walker.levels().is_empty() {
+                return Err(PhoenixAmmError::EmptyBook.into());
+            }
+            let mut walk = LadderWalk::default();
+            let remaining_budget = walker.take(&mut walk, walker.budget(in_amount)?, None)?;
+            let walk = walker.finish(walk, remaining_budget)?;
+            quotes.push(self.quote_from_walk(&self.ladder, &side, &walk));
+        }
+        Ok(quotes)
+    }
+
+    /// Quotes the largest swap a trader can self-execute from the free funds held in their
+    /// seat, rather than assuming unlimited input. `Side::Ask` spends all of `free_base_atoms`
+    /// and `Side::Bid` spends all of `free_quote_atoms`; the other balance is ignored. Funds
+    /// below one lot of the input mint cannot trade and quote to zero.
+    pub fn quote_with_free_funds(
+        &self,
+        side: Side,
+        free_base_atoms: u64,
+        free_quote_atoms: u64,
+    ) -> Result<Quote> {
+        let in_amount = match side {
+            Side::Ask => free_base_atoms,
+            Side::Bid => free_quote_atoms,
+        };
+        self.quote_side(side, in_amount)
+    }
+
+    /// Whether a swap with `quote_params` fills into the last level of the cached ladder. The
+    /// ladder only holds up to the ladder depth's worth of levels captured on the last load, so
+    /// a fill that reaches its end may be running into the edge of the snapshot rather than
+    /// the true end of the book, and the real book may be deeper than the quote suggests. Whether
+    /// it actually is is recorded by `ladder_depth_limited`. Always `false` for swaps whose
+    /// mints `quote` rejects.
+    pub fn ladder_possibly_truncated(&self, quote_params: &QuoteParams) -> bool {
+        let Ok(side) = self.side_for_mints(&quote_params.input_mint, &quote_params.output_mint)
+        else {
+            return false;
+        };
+        let levels = match side {
+            Side::Ask => self.ladder.bids.len(),
+            Side::Bid => self.ladder.asks.len(),
+        };
+        matches!(
+            self.walk_ladder(&side, quote_params.in_amount),
+            Ok(walk) if walk.levels_touched == levels
+        )
+    }
+
+    /// Taker fee in quote lots on a match of `adjusted_quote_lots` (quote lots times base lots
+    /// per base unit): `ceil(matched * taker_fee_bps / 10000)`, rounded up to whole quote lots,
+    /// like `FIFOMarket::compute_fee` and `round_adjusted_quote_lots_up`
+    fn fee_in_quote_lots(&self, adjusted_quote_lots: u128, taker_fee_bps: u64) -> u128 {
+        (adjusted_quote_lots * taker_fee_bps as u128)
+            .div_ceil(10000 * self.num_base_lots_per_base_unit as u128)
+    }
+
+    /// Splits the outcome of `walk` into what the taker receives, in atoms of the output mint,
+    /// and the taker fee the walk was charged, in quote atoms. Phoenix always charges the fee in quote, on the total
+    /// matched size (see `fee_in_quote_lots`). Sells receive the matched size rounded down to
+    /// whole quote lots less that fee, where other rounding modes only change how the matched
+    /// size is converted to quote lots, never the fee. Buys paid the fee out of their quote
+    /// budget during the walk, so they receive every base lot matched.
+    fn apply_taker_fee(&self, side: &Side, walk: &LadderWalk) -> (u64, u64) {
+        match side {
+            Side::Ask => {
+                let quote_lots_per_base_unit_per_tick =
+                    (self.tick_size_in_quote_atoms_per_base_unit / self.quote_atoms_per_quote_lot)
+                        as u128;
+                let base_lots_per_base_unit = self.num_base_lots_per_base_unit as u128;
+                let adjusted_quote_lots =
+                    walk.matched_lot_ticks * quote_lots_per_base_unit_per_tick;
+                let matched_quote_lots = self
+                    .rounding_mode
+                    .div(adjusted_quote_lots, base_lots_per_base_unit);
+                let fee_in_quote_lots = self
+                    .fee_in_quote_lots(adjusted_quote_lots, walk.taker_fee_bps)
+                    .min(matched_quote_lots);
+                let quote_atoms_per_quote_lot = self.quote_atoms_per_quote_lot as u128;
+                // Rounding up can push the proceeds of a fill that just fits a u64 over it
+                let to_atoms = |quote_lots: u128| {
+                    u64::try_from(quote_lots * quote_atoms_per_quote_lot).unwrap_or(u64::MAX)
+                };
+                (
+                    to_atoms(matched_quote_lots - fee_in_quote_lots),
+                    to_atoms(fee_in_quote_lots),
+                )
+            }
+            Side::Bid => {
+                let adjusted_quote_lots = walk.matched_lot_ticks
+                    * (self.tick_size_in_quote_atoms_per_base_unit / self.quote_atoms_per_quote_lot)
+                        as u128;
+                let fee_in_quote_lots =
+                    self.fee_in_quote_lots(adjusted_quote_lots, walk.taker_fee_bps);
+                (
+                    walk.out_amount,
+                    fee_in_quote_lots as u64 * self.quote_atoms_per_quote_lot,
+                )
+            }
+        }
+    }
+
+    /// Walks the cached ladder for a taker on `side`, erroring if that side of the book is empty
+    fn walk_ladder(&self, side: &Side, in_amount: u64) -> Result<LadderWalk> {
+        let cumulative_liquidity = self
+            .cumulative_liquidity
+            .get_or_init(|| self.build_cumulative_liquidity());
+        let (levels, cumulative) = match side {
+            Side::Ask => (&self.ladder.bids, &cumulative_liquidity.bids),
+            Side::Bid => (&self.ladder.asks, &cumulative_liquidity.asks),
+        };
+        let cumulative = Some(cumulative).filter(|c| c.num_levels() == levels.len());
+        self.walk_ladder_with_fee(
+            &self.ladder,
+            side,
+            in_amount,
+            self.taker_fee_bps as u64,
+            None,
+            cumulative,
+        )
+    }
+
+    /// Like `walk_ladder`, but against `ladder` instead of the cached one
+    fn walk_ladder_on(&self, ladder: &Ladder, side: &Side, in_amount: u64) -> Result<LadderWalk> {
+        if std::ptr::eq(ladder, &*self.ladder) {
+            return self.walk_ladder(side, in_amount);
+        }
+        self.walk_ladder_with_fee(
+            ladder,
+            side,
+            in_amount,
+            self.taker_fee_bps as u64,
+            None,
+            None,
+        )
+    }
+
+    /// Like `walk_ladder_on`, charging `taker_fee_bps` instead of the market's taker fee. Buys
+    /// pay the fee out of their quote budget, so the fee changes how much base they can buy.
+    /// Each level the walk takes from is pushed onto `fills`, if given.
+    ///
+    /// Given the `cumulative` totals of the side being walked, the levels before the last one
+    /// the budget reaches are cleared in full, so they are found by binary search and taken
+    /// from the totals at once, and only the rest are walked. This is the same walk down to
+    /// the atom, but cannot record fills.
+    ///
+    /// Every quote goes through here, so this is where paused and closed markets are refused,
+    /// with `PhoenixAmmError::MarketNotSwappable`.
+    fn walk_ladder_with_fee(
+        &self,
+        ladder: &Ladder,
+        side: &Side,
+        in_amount: u64,
+        taker_fee_bps: u64,
+        fills: Option<&mut Vec<FillLevel>>,
+        cumulative: Option<&CumulativeSide>,
+    ) -> Result<LadderWalk> {
+        debug_assert!(fills.is_none() || cumulative.is_none());
+        let mut walker = LadderWalker::new(self, ladder, side, taker_fee_bps)?;
+        if walker.levels().is_empty() {
+            return Err(PhoenixAmmError::EmptyBook.into());
+        }
+        let mut budget = walker.budget(in_amount)?;
+        let mut walk = LadderWalk::default();
+        if let Some(cumulative) = cumulative {
+            budget -= walker.take_cleared(&mut walk, cumulative, budget)?;
+        }
+        let remaining_budget = walker.take(&mut walk, budget, fills)?;
+        walker.finish(walk, remaining_budget)
+    }
+
+    /// Cumulative liquidity available to a taker on `side`, one entry per level of the cached
+    /// ladder, best first and at most `max_levels` of them. Each entry is the level's price in
+    /// quote units per base unit, then the base atoms and quote atoms resting at that level and
+    /// every better one. The quote atoms are converted like a fill in `quote`, before fees, and
+    /// saturate at `u64::MAX`. An empty side has no entries.
+    pub fn depth(&self, side: Side, max_levels: usize) -> Vec<(f64, u64, u64)> {
+        let levels = match side {
+            Side::Ask => &self.ladder.bids,
+            Side::Bid => &self.ladder.asks,
+        };
+        let (mut base_atoms, mut quote_atoms) = (0u64, 0u64);
+        levels
+            .iter()
+            .take(max_levels)
+            .map(|level| {
+                base_atoms = base_atoms.saturating_add(
+                    level
+                        .size_in_base_lots
+                        .saturating_mul(self.base_atoms_per_base_lot),
+                );
+                quote_atoms = quote_atoms.saturating_add(
+                    self.quote_atoms_for_base_lots(level.size_in_base_lots, level.price_in_ticks)
+                        .unwrap_or(u64::MAX),
+                );
+                (
+                    self.ticks_to_float_price(level.price_in_ticks),
+                    base_atoms,
+                    quote_atoms,
+                )
+            })
+            .collect()
+    }
+
+    /// Exports the quote curve for a taker on `side` as CSV, for studying a market's liquidity in
+    /// a spreadsheet. There is one row per input size `step, 2 * step, ..., steps * step` (in
+    /// atoms of the input mint) with the columns:
+    ///
+    /// - `in_human`, `out_human`: input and output (after fees) in whole units of each mint
+    /// - `avg_price`: average execution price of the filled part, in quote units per base unit
+    /// - `marginal_price`: price of the deepest level the fill reaches
+    /// - `impact_bps`: how far `avg_price` is from the top of book, in basis points
+    ///
+    /// Amounts and prices are printed with their mint's decimals. Sizes too small to receive
+    /// any output are skipped, the curve stops at the first size that exhausts the book, and an
+    /// empty side exports only the header row.
+    pub fn quote_curve_csv(&self, side: Side, step: u64, steps: usize) -> String {
+        let mut csv = String::from("in_human,out_human,avg_price,marginal_price,impact_bps\n");
+        let (levels, in_decimals, out_decimals) = match side {
+            Side::Ask => (&self.ladder.bids, self.base_decimals, self.quote_decimals),
+            Side::Bid => (&self.ladder.asks, self.quote_decimals, self.base_decimals),
+        };
+        let top_price = match levels.first() {
+            Some(level) => self.ticks_to_float_price(level.price_in_ticks),
+            None => return csv,
+        };
+
+        // One pass down the book: each size picks up from the levels the smaller ones cleared
+        let Ok(mut walker) =
+            LadderWalker::new(self, &self.ladder, &side, self.taker_fee_bps as u64)
+        else {
+            return csv;
+        };
+        let mut filled = LadderWalk::default();
+        let mut filled_budget = 0;
+        for row in 1..=steps as u64 {
+            let in_amount = step.saturating_mul(row);
+            // A size that overflows takes every fill a smaller one did, so larger ones do too
+            let Ok(walk) = walker
+                .budget(in_amount)
+                .and_then(|budget| walker.walk_increasing(&mut filled, &mut filled_budget, budget))
+            else {
+                break;
+            };
+            let (out_amount, _) = self.apply_taker_fee(&side, &walk);
+            // A size can reach a level without filling a lot there, or only pay the fee
+            if out_amount == 0 {
+                continue;
+            }
+            let (in_human, out_human) = (
+                atoms_to_ui(in_amount, in_decimals),
+                atoms_to_ui(out_amount, out_decimals),
+            );
+            // Input that found no liquidity does not count towards the average price
+            let consumed_human = atoms_to_ui(walk.in_amount_consumed, in_decimals);
+            let avg_price = match side {
+                Side::Ask => out_human / consumed_human,
+                Side::Bid => consumed_human / out_human,
+            };
+            let marginal_price = self.ticks_to_float_price(walk.last_price_in_ticks);
+            let impact_bps = (avg_price - top_price).abs() / top_price * 10_000.0;
+            csv.push_str(&format!(
+                "{:.*},{:.*},{:.*},{:.*},{:.2}\n",
+                in_decimals as usize,
+                in_human,
+                out_decimals as usize,
+                out_human,
+                self.quote_decimals as usize,
+                avg_price,
+                self.quote_decimals as usize,
+                marginal_price,
+                impact_bps,
+            ));
+            if walk.remaining_budget > 0 {
+                break;
+            }
+        }
+        csv
+    }
+
+    /// The highest bid in quote units per base unit, or `None` if there are no bids
+    pub fn best_bid(&self) -> Option<f64> {
+        self.ladder
+            .bids
+            .first()
+            .map(|level| self.ticks_to_float_price(level.price_in_ticks))
+    }
+
+    /// The lowest ask in quote units per base unit, or `None` if there are no asks
+    pub fn best_ask(&self) -> Option<f64> {
+        self.ladder
+            .asks
+            .first()
+            .map(|level| self.ticks_to_float_price(level.price_in_ticks))
+    }
+
+    /// The market's label, key, mints, taker fee, and top of book, see `MarketSummary`. This is
+    /// also what `Display` prints.
+    pub fn summary(&self) -> MarketSummary {
+        MarketSummary {
+            label: self.label.clone(),
+            market_key: self.market_key,
+            base_mint: self.base_mint,
+            quote_mint: self.quote_mint,
+            taker_fee_bps: self.taker_fee_bps,
+            best_bid: self.best_bid(),
+            best_ask: self.best_ask(),
+        }
+    }
+
+    /// The midpoint of the best bid and ask in quote units per base unit, or `None` if either
+    /// side of the book is empty
+    pub fn mid_price(&self) -> Option<f64> {
+        Some((self.best_bid()? + self.best_ask()?) / 2.0)
+    }
+
+    /// The gap between the best ask and bid as a fraction of the mid price, in basis points
+    /// rounded to the nearest, or `None` if either side of the book is empty. Prices are a
+    /// fixed multiple of ticks, so this is computed exactly from the ticks. A crossed book, which
+    /// the program never leaves resting, has a spread of 0.
+    pub fn spread_bps(&self) -> Option<u64> {
+        let best_bid = self.ladder.bids.first()?.price_in_ticks as u128;
+        let best_ask = self.ladder.asks.first()?.price_in_ticks as u128;
+        // (ask - bid) / ((ask + bid) / 2) * 10000, as numerator / denominator
+        let numerator = best_ask.saturating_sub(best_bid) * 20_000;
+        let denominator = best_ask + best_bid;
+        if denominator == 0 {
+            return None;
+        }
+        Some(((2 * numerator + denominator) / (2 * denominator)) as u64)
+    }
+
+    /// Volume-weighted average price of the fill `quote` simulates for `quote_params`, in quote
+    /// units per base unit, from the same level-by-level walk. A partially consumed last level
+    /// only counts the lots actually matched there. The taker fee is not included. `None` if
+    /// nothing fills, e.g. because that side of the book is empty.
+    pub fn vwap(&self, quote_params: &QuoteParams) -> Result<Option<f64>> {
+        let side = self.side_for_mints(&quote_params.input_mint, &quote_params.output_mint)?;
+        let walk = match self.walk_ladder(&side, quote_params.in_amount) {
+            Ok(walk) => walk,
+            Err(err) if err.downcast_ref() == Some(&PhoenixAmmError::EmptyBook) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let filled_base_lots = match side {
+            Side::Ask => walk.in_amount_consumed,
+            Side::Bid => walk.out_amount,
+        } / self.base_atoms_per_base_lot;
+        if filled_base_lots == 0 {
+            return Ok(None);
+        }
+        Ok(Some(
+            self.ticks_to_float_price(1) * walk.matched_lot_ticks as f64 / filled_base_lots as f64,
+        ))
+    }
+
+    /// The best bid and ask, in quote units per base unit, once the fill `quote` simulates for
+    /// `quote_params` has taken its liquidity, from the same walk. The side the swap takes from
+    /// moves to the first level the swap leaves resting base lots on, which is the last level it
+    /// touched if that one was only partially consumed, and is `None` if the swap clears it. The
+    /// other side is unchanged. Like the walk, this only sees the cached levels.
+    pub fn top_of_book_after(
+        &self,
+        quote_params: &QuoteParams,
+    ) -> Result<(Option<f64>, Option<f64>)> {
+        let side = self.side_for_mints(&quote_params.input_mint, &quote_params.output_mint)?;
+        let (levels, untouched) = match side {
+            Side::Ask => (&self.ladder.bids, self.best_ask()),
+            Side::Bid => (&self.ladder.asks, self.best_bid()),
+        };
+        let walk = match self.walk_ladder(&side, quote_params.in_amount) {
+            Ok(walk) => walk,
+            Err(err) if err.downcast_ref() == Some(&PhoenixAmmError::EmptyBook) => {
+                return Ok((self.best_bid(), self.best_ask()))
+            }
+            Err(err) => return Err(err),
+        };
+        let mut filled_base_lots = match side {
+            Side::Ask => walk.in_amount_consumed,
+            Side::Bid => walk.out_amount,
+        } / self.base_atoms_per_base_lot;
+        // The walk skips zero-priced levels, which no real order rests at
+        let new_top = levels
+            .iter()
+            .filter(|level| level.price_in_ticks > 0)
+            .find(|level| {
+                if filled_base_lots < level.size_in_base_lots {
+                    return true;
+                }
+                filled_base_lots -= level.size_in_base_lots;
+                false
+            })
+            .map(|level| self.ticks_to_float_price(level.price_in_ticks));
+        Ok(match side {
+            Side::Ask => (new_top, untouched),
+            Side::Bid => (untouched, new_top),
+        })
+    }
+
+    /// Which side it is profitable to take against this book, given an `external_price` in quote
+    /// units per base unit: `Side::Bid` (buy base here) if the external price is above the best
+    /// ask, `Side::Ask` (sell base here) if it is below the best bid, and `None` if it is within
+    /// the spread or the relevant side is empty. This only looks at the top of book and ignores
+    /// the taker fee, so it is a cheap pre-check before quoting a full size.
+    pub fn better_direction(&self, external_price: f64) -> Option<Side> {
+        if matches!(self.best_ask(), Some(best_ask) if external_price > best_ask) {
+            Some(Side::Bid)
+        } else if matches!(self.best_bid(), Some(best_bid) if external_price < best_bid) {
+            Some(Side::Ask)
+        } else {
+            None
+        }
+    }
+
+    /// The input atoms a taker on `side` can trade before the next level they would fill against
+    /// is at least `target_bps` away from the top of book. This is the inverse of a price impact
+    /// query: every level strictly inside the band is consumed in full, and the walk stops at the
+    /// first level that deviates by `target_bps` or more. Returns zero for an empty side or when
+    /// even the top of book is already beyond the target (i.e. `target_bps == 0`). The input is
+    /// converted like `depth`, before fees, and saturates at `u64::MAX`.
+    pub fn input_to_move_price(&self, side: Side, target_bps: u16) -> u64 {
+        let levels = match side {
+            Side::Ask => &self.ladder.bids,
+            Side::Bid => &self.ladder.asks,
+        };
+        let top_of_book = match levels.first() {
+            Some(level) => level.price_in_ticks as u128,
+            None => return 0,
+        };
+
+        let mut input = 0u64;
+        for LadderOrder {
+            price_in_ticks,
+            size_in_base_lots,
+        } in levels.iter()
+        {
+            let deviation = top_of_book.abs_diff(*price_in_ticks as u128);
+            if deviation * 10_000 >= target_bps as u128 * top_of_book {
+                break;
+            }
+            let level_input = match side {
+                Side::Ask => size_in_base_lots.saturating_mul(self.base_atoms_per_base_lot),
+                Side::Bid => self
+                    .quote_atoms_for_base_lots(*size_in_base_lots, *price_in_ticks)
+                    .unwrap_or(u64::MAX),
+            };
+            input = input.saturating_add(level_input);
+        }
+        input
+    }
+
+    /// The base atoms and quote atoms a taker on `side` can fill against the cached ladder
+    /// without crossing `limit_price_in_ticks`: bids at or above the limit when selling base
+    /// (`Side::Ask`), asks at or below it when buying (`Side::Bid`). The quote atoms are
+    /// converted like `depth`, before fees, and both totals saturate at `u64::MAX`. An empty side
+    /// gives zero, a limit beyond the whole side gives all of it.
+    pub fn fillable_to_limit(&self, side: Side, limit_price_in_ticks: u64) -> (u64, u64) {
+        let (levels, within_limit): (_, fn(u64, u64) -> bool) = match side {
+            Side::Ask => (&self.ladder.bids, |price, limit| price >= limit),
+            Side::Bid => (&self.ladder.asks, |price, limit| price <= limit),
+        };
+        // Levels are sorted best first, so the first one past the limit ends the walk
+        levels
+            .iter()
+            .take_while(|level| within_limit(level.price_in_ticks, limit_price_in_ticks))
+            .fold((0u64, 0u64), |(base_atoms, quote_atoms), level| {
+                (
+                    base_atoms.saturating_add(
+                        level
+                            .size_in_base_lots
+                            .saturating_mul(self.base_atoms_per_base_lot),
+                    ),
+                    quote_atoms.saturating_add(
+                        self.quote_atoms_for_base_lots(
+                            level.size_in_base_lots,
+                            level.price_in_ticks,
+                        )
+                        .unwrap_or(u64::MAX),
+                    ),
+                )
+            })
+    }
+
+    /// Encodes the cached ladder in a compact fixed-width binary format for IPC. All integers are
+    /// little-endian:
+    ///
+    /// - bytes `0..4`: number of bid levels `b`, u32
+    /// - bytes `4..8`: number of ask levels `a`, u32
+    /// - bytes `8..8 + 16b`: bids, best first, each `price_in_ticks` u64 then
+    ///   `size_in_base_lots` u64
+    /// - bytes `8 + 16b..8 + 16(b + a)`: asks, best first, in the same layout
+    pub fn serialize_ladder(&self) -> Vec<u8> {
+        let levels = self.ladder.bids.iter().chain(self.ladder.asks.iter());
+        let mut bytes = Vec::with_capacity(
+            8 + LADDER_LEVEL_LEN * (self.ladder.bids.len() + self.ladder.asks.len()),
+        );
+        bytes.extend_from_slice(&(self.ladder.bids.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.ladder.asks.len() as u32).to_le_bytes());
+        for level in levels {
+            bytes.extend_from_slice(&level.price_in_ticks.to_le_bytes());
+            bytes.extend_from_slice(&level.size_in_base_lots.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Decodes a ladder written by `serialize_ladder`, erroring if `bytes` is not exactly the
+    /// length its level counts imply
+    pub fn deserialize_ladder(bytes: &[u8]) -> Result<Ladder> {
+        let read_u32 = |offset: usize| {
+            bytes
+                .get(offset..offset + 4)
+                .and_then(|b| b.try_into().ok())
+                .map(|b| u32::from_le_bytes(b) as usize)
+                .ok_or_else(|| Error::msg("Serialized ladder is missing its level counts"))
+        };
+        let (num_bids, num_asks) = (read_u32(0)?, read_u32(4)?);
+        let levels = &bytes[8..];
+        if (levels.len() as u64) != LADDER_LEVEL_LEN as u64 * (num_bids as u64 + num_asks as u64) {
+            return Err(Error::msg(
+                "Serialized ladder length does not match its level counts",
+            ));
+        }
+
+        let mut levels = levels
+            .chunks_exact(LADDER_LEVEL_LEN)
+            .map(|level| LadderOrder {
+                price_in_ticks: u64::from_le_bytes(level[..8].try_into().unwrap()),
+                size_in_base_lots: u64::from_le_bytes(level[8..].try_into().unwrap()),
+            });
+        Ok(Ladder {
+            bids: levels.by_ref().take(num_bids).collect(),
+            asks: levels.collect(),
+        })
+    }
+
+    /// Empties the cached ladder so that quotes fail with `PhoenixAmmError::EmptyBook` until the
+    /// next `update`, rather than serving stale liquidity. Use this to explicitly invalidate a
+    /// market that is known to be stale, or before reusing the allocation for another market.
+    pub fn clear_ladder(&mut self) {
+        let ladder = unshared_ladder(&mut self.ladder);
+        ladder.bids.clear();
+        ladder.asks.clear();
+        self.cache_cumulative_liquidity();
+        self.ladder_depth_limited = (false, false);
+        self.market_sequence_number = None;
+    }
+
+    /// Whether the cached bids and asks stop at the ladder depth with more levels left in the
+    /// book. A quote that reaches the deepest level of a depth-limited side is priced against
+    /// only part of the book, where an unlimited side holds all of its liquidity.
+    pub fn ladder_depth_limited(&self) -> (bool, bool) {
+        self.ladder_depth_limited
+    }
+
+    /// Number of price levels in the cached bids, 0 for an empty side. Capped at the ladder
+    /// depth, see `ladder_depth_limited` for whether the book has more.
+    pub fn bid_levels(&self) -> usize {
+        self.ladder.bids.len()
+    }
+
+    /// Number of price levels in the cached asks, 0 for an empty side. Capped at the ladder
+    /// depth, like `bid_levels`.
+    pub fn ask_levels(&self) -> usize {
+        self.ladder.asks.len()
+    }
+
+    /// The market authority, which can change the market's status and fees, as of the last
+    /// load
+    pub fn authority(&self) -> Pubkey {
+        self.authority
+    }
+
+    /// The account the market's taker fees are collected for, as of the last load. Not to be
+    /// confused with `fee_recipient`, the referral account added to swap metas.
+    pub fn market_fee_recipient(&self) -> Pubkey {
+        self.market_fee_recipient
+    }
+
+    /// The market header's sequence number as of the cached ladder. The program advances it on
+    /// every instruction that touches the market, so an unchanged number means an unchanged
+    /// book. `None` if the ladder has been cleared and not reloaded since.
+    pub fn market_sequence_number(&self) -> Option<u64> {
+        self.market_sequence_number
+    }
+
+    /// Quotes both sides of the book for `size_in_base_lots` against the same ladder snapshot,
+    /// returning `(sell, buy)`. The two are made comparable by trading the same base notional:
+    /// the sell quote spends `size_in_base_lots` of base, and the buy quote is the
+    /// `quote_exact_out` for that much base, so swapping its `in_amount` of quote, taker fee
+    /// included, fills exactly `size_in_base_lots`. If the asks are thinner than that, the buy
+    /// quote takes all of them and sets `not_enough_liquidity`.
+    pub fn two_way_quote(&self, size_in_base_lots: u64) -> Result<(Quote, Quote)> {
+        let base_atoms = size_in_base_lots
+            .checked_mul(self.base_atoms_per_base_lot)
+            .ok_or(PhoenixAmmError::AmountOverflow)?;
+        let sell = Quote {
+            in_amount: base_atoms,
+            ..self.quote_side(Side::Ask, base_atoms)?
+        };
+        let buy = self.quote_exact_out(&self.quote_mint, base_atoms)?;
+        Ok((sell, buy))
+    }
+
+    /// Loads a fresh ladder from the market account in `accounts_map`
+    fn load_ladder(&mut self, accounts_map: &HashMap<Pubkey, PartialAccount>) -> Result<()> {
+        if let Some(clock_account) = accounts_map.get(&clock::id()) {
+            let clock: Clock = limited_deserialize(&clock_account.data)
+                .map_err(|_| Error::msg("Failed to parse the clock sysvar"))?;
+            // Timestamps before the epoch cannot be after any expiry
+            self.clock = (clock.slot, clock.unix_timestamp.max(0) as u64);
+        }
+        let market_account = accounts_map
+            .get(&self.market_key)
+            .ok_or(PhoenixAmmError::MissingAccountUpdate)?;
+        self.load_ladder_from_data(&market_account.data)
+    }
+
+    /// Reloads the cached ladder from raw market account data. This produces the same ladder as
+    /// `get_ladder(ladder_depth)`, but refills the existing vectors in place instead of allocating
+    /// new ones on every update. The ladder is only touched once the account has been fully
+    /// validated, so on error it is left as it was.
+    fn load_ladder_from_data(&mut self, data: &[u8]) -> Result<()> {
+        let (header, bytes) = parse_market_account(data)?;
+        if !self.metadata_matches_header(header) {
+            return Err(PhoenixAmmError::MetadataChanged.into());
+        }
+        let market = load_with_dispatch(&header.market_size_params, bytes)?;
+        self.market_status = MarketStatus::from(header.status);
+        // The authority can hand over the market or redirect its fees at any time
+        self.authority = header.authority;
+        self.market_fee_recipient = header.fee_recipient;
+        // Nothing has happened on the market, and no cached order has expired, since the
+        // ladder was loaded
+        let (current_slot, current_unix_timestamp) = self.clock;
+        if self.market_sequence_number == Some(header.market_sequence_number)
+            && current_slot <= self.ladder_expiry.0
+            && current_unix_timestamp <= self.ladder_expiry.1
+        {
+            return Ok(());
+        }
+        self.refill_ladder(market.inner);
+        self.market_sequence_number = Some(header.market_sequence_number);
+        Ok(())
+    }
+
+    /// Refills the cached ladder from `market` in place, leaving out orders that have expired as
+    /// of the last clock `update` saw
+    fn refill_ladder(
+        &mut self,
+        market: &dyn Market<Pubkey, FIFOOrderId, FIFORestingOrder, OrderPacket>,
+    ) {
+        let (current_slot, current_unix_timestamp) = self.clock;
+        let mut ladder_expiry = (u64::MAX, u64::MAX);
+        let ladder_depth = self.ladder_depth as usize;
+        let ladder = unshared_ladder(&mut self.ladder);
+        for (side, levels, depth_limited) in [
+            (
+                phoenix::state::Side::Bid,
+                &mut ladder.bids,
+                &mut self.ladder_depth_limited.0,
+            ),
+            (
+                phoenix::state::Side::Ask,
+                &mut ladder.asks,
+                &mut self.ladder_depth_limited.1,
+            ),
+        ] {
+            levels.clear();
+            *depth_limited = false;
+            // The book iterates best price first, so orders at the same price are adjacent. The
+            // tree is walked lazily, so stopping at the ladder depth leaves the rest unvisited.
+            for (order_id, resting_order) in market.get_book(side).iter() {
+                // Like the program, an order still fills in its last valid slot and second
+                if resting_order.is_expired(current_slot, current_unix_timestamp) {
+                    continue;
+                }
+                let price_in_ticks = order_id.price_in_ticks.as_u64();
+                let size_in_base_lots = resting_order.num_base_lots.as_u64();
+                match levels.last_mut() {
+                    Some(level) if level.price_in_ticks == price_in_ticks => {
+                        level.size_in_base_lots += size_in_base_lots;
+                    }
+                    _ => {
+                        if levels.len() == ladder_depth {
+                            *depth_limited = true;
+                            break;
+                        }
+                        levels.push(LadderOrder {
+                            price_in_ticks,
+                            size_in_base_lots,
+                        })
+                    }
+                }
+                // Zero means the order never expires by that measure
+                if resting_order.last_valid_slot != 0 {
+                    ladder_expiry.0 = ladder_expiry.0.min(resting_order.last_valid_slot);
+                }
+                if resting_order.last_valid_unix_timestamp_in_seconds != 0 {
+                    ladder_expiry.1 = ladder_expiry
+                        .1
+                        .min(resting_order.last_valid_unix_timestamp_in_seconds);
+                }
+            }
+        }
+        self.ladder_expiry = ladder_expiry;
+        self.cache_cumulative_liquidity();
+    }
+
+    /// Recomputes the running totals `walk_ladder` uses from the cached ladder, which must be
+    /// done whenever the ladder changes
+    fn cache_cumulative_liquidity(&mut self) {
+        self.cumulative_liquidity = Arc::new(OnceLock::from(self.build_cumulative_liquidity()));
+    }
+
+    fn build_cumulative_liquidity(&self) -> CumulativeLiquidity {
+        CumulativeLiquidity::new(&self.ladder, |base_lots, price| {
+            self.quote_atoms_for_base_lots(base_lots, price)
+        })
+    }
+
+    /// Tracks the outcome of a ladder reload, applying the update failure policy on errors
+    fn record_update_result(&mut self, result: Result<()>) -> Result<()> {
+        match result {
+            Ok(()) => {
+                self.consecutive_update_failures = 0;
+                Ok(())
+            }
+            Err(err) => {
+                self.consecutive_update_failures =
+                    self.consecutive_update_failures.saturating_add(1);
+                if self.update_failure_policy == UpdateFailurePolicy::ClearLadder {
+                    self.clear_ladder();
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Whether `header` still describes the market the cached metadata was derived from. Only
+    /// the raw header fields are compared, so this is cheap enough to run on every update.
+    fn metadata_matches_header(&self, header: &MarketHeader) -> bool {
+        let metadata = &self.market_metadata;
+        header.base_params.mint_key == metadata.base_mint
+            && header.quote_params.mint_key == metadata.quote_mint
+            && header.base_params.decimals == metadata.base_decimals
+            && header.quote_params.decimals == metadata.quote_decimals
+            && u64::from(header.get_base_lot_size()) == metadata.base_atoms_per_base_lot
+            && u64::from(header.get_quote_lot_size()) == metadata.quote_atoms_per_quote_lot
+            && u64::from(header.get_tick_size_in_quote_atoms_per_base_unit())
+                == metadata.tick_size_in_quote_atoms_per_base_unit
+            && header.raw_base_units_per_base_unit.max(1) as u64
+                == metadata.raw_base_units_per_base_unit as u64
+    }
+
+    /// Quotes `input_amount` whole units (e.g. 1.5 SOL) of `input_mint` and returns the output in
+    /// whole units of the other mint. The input is scaled to atoms and rounded to the nearest
+    /// atom, so inputs like 0.1 do not lose an atom to binary floating point. An `f64` carries
+    /// about 15 significant digits, which is exact for any balance below 2^53 atoms; beyond that
+    /// the conversion loses precision, and amounts that must be exact should use `quote` instead.
+    pub fn quote_human(&self, input_mint: Pubkey, input_amount: f64) -> Result<f64> {
+        let (input_decimals, output_mint, output_decimals) =
+            match self.side_for_input_mint(&input_mint)? {
+                Side::Ask => (self.base_decimals, self.quote_mint, self.quote_decimals),
+                Side::Bid => (self.quote_decimals, self.base_mint, self.base_decimals),
+            };
+        if !input_amount.is_finite() || input_amount < 0.0 {
+            return Err(Error::msg(
+                "Input amount must be a finite, non-negative number",
+            ));
+        }
+
+        let in_amount = ui_to_atoms(input_amount, input_decimals);
+        let quote = self.quote(&QuoteParams {
+            in_amount,
+            input_mint,
+            output_mint,
+        })?;
+        Ok(atoms_to_ui(quote.out_amount, output_decimals))
+    }
+
+    /// Approximate `(base, quote)` reserves for bucketing venues by liquidity, like an AMM's
+    /// pool balances: the base atoms resting on the asks, which takers can buy, and the quote
+    /// atoms resting on the bids, which takers can sell into. This only counts the cached
+    /// ladder and ignores fees and lot rounding, so it is not what a swap can actually get.
+    /// Each total saturates at `u64::MAX`.
+    pub fn get_reserves(&self) -> (u64, u64) {
+        let base_atoms: u128 = self
+            .ladder
+            .asks
+            .iter()
+            .map(|level| level.size_in_base_lots as u128 * self.base_atoms_per_base_lot as u128)
+            .fold(0, u128::saturating_add);
+        let adjusted_quote_atoms: u128 = self
+            .ladder
+            .bids
+            .iter()
+            .map(|level| {
+                (level.size_in_base_lots as u128 * level.price_in_ticks as u128)
+                    .saturating_mul(self.tick_size_in_quote_atoms_per_base_unit as u128)
+            })
+            .fold(0, u128::saturating_add);
+        let quote_atoms = adjusted_quote_atoms / self.num_base_lots_per_base_unit as u128;
+        (
+            u64::try_from(base_atoms).unwrap_or(u64::MAX),
+            u64::try_from(quote_atoms).unwrap_or(u64::MAX),
+        )
+    }
+
+    /// Total quote atoms needed to buy out every ask in the cached ladder, saturating at
+    /// `u64::MAX`
+    pub fn ask_depth_in_quote_atoms(&self) -> u64 {
+        self.ladder.asks.iter().fold(0u64, |depth, order| {
+            depth.saturating_add(
+                self.quote_atoms_for_base_lots(order.size_in_base_lots, order.price_in_ticks)
+                    .unwrap_or(u64::MAX),
+            )
+        })
+    }
+
+    /// `base_lots_and_price_to_quote_atoms` with u128 intermediates, which overflow a u64 on
+    /// large-notional markets. `None` if the result itself does not fit in a u64.
+    fn quote_atoms_for_base_lots(&self, base_lots: u64, price_in_ticks: u64) -> Option<u64> {
+        let quote_atoms = (base_lots as u128 * price_in_ticks as u128)
+            .checked_mul(self.tick_size_in_quote_atoms_per_base_unit as u128)?
+            / self.num_base_lots_per_base_unit as u128;
+        u64::try_from(quote_atoms).ok()
+    }
+
+    /// Number of account metas a swap on this market adds to a transaction:
+    /// `SWAP_ACCOUNT_META_COUNT` plus the fee recipient, if any, and any accounts set with
+    /// `set_extra_swap_account_metas`
+    pub fn swap_account_meta_count(&self) -> usize {
+        SWAP_ACCOUNT_META_COUNT
+            + self.fee_recipient.is_some() as usize
+            + self.extra_swap_account_metas.len()
+    }
+
+    /// `swap_account_meta_count` under the name later versions of the `Amm` trait give this hook,
+    /// which the `jupiter-core` revision this crate is pinned to does not have yet
+    pub fn get_accounts_len(&self) -> usize {
+        self.swap_account_meta_count()
+    }
+
+    /// Always `false`: `update` only reads the market and the clock, never the reserve mints, so
+    /// changes to the mint accounts do not need an update. Like `get_accounts_len`, this is a
+    /// later `Amm` hook provided as an inherent method until the trait has it.
+    pub fn requires_update_for_reserve_mints(&self) -> bool {
+        false
+    }
+
+    /// Dry-run check that `swap_params` is consistent with a `quote` produced separately,
+    /// before building the swap: the mints must match a direction of this market, the user
+    /// accounts must be set, the input must match the quote's when the quote records one (either
+    /// as given, or as the part of it that trades), and the book must be deep enough to fill
+    /// the whole input.
+    pub fn validate_swap(&self, quote: &Quote, swap_params: &SwapParams) -> Result<()> {
+        let (side, base_account, quote_account) = self.swap_side_and_token_accounts(swap_params)?;
+        if [
+            swap_params.user_transfer_authority,
+            base_account,
+            quote_account,
+        ]
+        .contains(&Pubkey::default())
+        {
+            return Err(Error::msg("Swap accounts must not be the default pubkey"));
+        }
+        // Checked first, since a partial quote's `in_amount` is what it consumed, never the input
+        let walk = self.walk_ladder(&side, swap_params.in_amount)?;
+        if quote.not_enough_liquidity || walk.remaining_budget > 0 {
+            return Err(PhoenixAmmError::NotEnoughLiquidity.into());
+        }
+        if quote.in_amount != 0
+            && quote.in_amount != swap_params.in_amount
+            && quote.in_amount != walk.in_amount_consumed
+        {
+            return Err(Error::msg("Swap input does not match the quoted input"));
+        }
+        Ok(())
+    }
+
+    /// Builds a standalone Phoenix `Swap` instruction for `swap_params`, outside of Jupiter's
+    /// CPI. Jupiter's `Swap` leg has no room for a client order id, so this is the path for
+    /// integrators who want to tag their fills: `client_order_id` is carried in the
+    /// immediate-or-cancel order packet and surfaces in the `FillSummaryEvent` that Phoenix logs
+    /// for the swap. When it is `None`, the id is zero, which is what Jupiter's CPI sends.
+    ///
+    /// The order has no limit price or minimum fill, like the Jupiter leg, so the caller is
+    /// responsible for enforcing slippage, e.g. with `swap_instruction_with_min_out`. The
+    /// instruction targets the market's program id and token program.
+    pub fn swap_instruction(
+        &self,
+        swap_params: &SwapParams,
+        client_order_id: Option<u128>,
+    ) -> Result<Instruction> {
+        self.swap_instruction_with_min_out(swap_params, client_order_id, 0)
+    }
+
+    /// Like `swap_instruction`, but the program voids the swap unless it outputs at least
+    /// `min_out_amount` atoms of the destination mint, after the taker fee. The minimum is
+    /// rounded up to whole lots of the output mint, since that is all the program can check.
+    /// See `min_out_with_slippage` for deriving it from a quote.
+    pub fn swap_instruction_with_min_out(
+        &self,
+        swap_params: &SwapParams,
+        client_order_id: Option<u128>,
+        min_out_amount: u64,
+    ) -> Result<Instruction> {
+        let (side, base_account, quote_account) = self.swap_side_and_token_accounts(swap_params)?;
+        let (side, num_base_lots, num_quote_lots, min_base_lots_to_fill, min_quote_lots_to_fill) =
+            match side {
+                Side::Ask => (
+                    phoenix::state::Side::Ask,
+                    swap_params.in_amount / self.base_atoms_per_base_lot,
+                    0,
+                    0,
+                    min_out_amount.div_ceil(self.quote_atoms_per_quote_lot),
+                ),
+                Side::Bid => (
+                    phoenix::state::Side::Bid,
+                    0,
+                    swap_params.in_amount / self.quote_atoms_per_quote_lot,
+                    min_out_amount.div_ceil(self.base_atoms_per_base_lot),
+                    0,
+                ),
+            };
+        let order_packet = OrderPacket::new_ioc(
+            side,
+            None,
+            num_base_lots,
+            num_quote_lots,
+            min_base_lots_to_fill,
+            min_quote_lots_to_fill,
+            SelfTradeBehavior::CancelProvide,
+            None,
+            client_order_id.unwrap_or_default(),
+            false,
+            None,
+            None,
+        );
+        let mut instruction = create_new_order_instruction_with_custom_token_accounts(
+            &self.market_key,
+            &swap_params.user_transfer_authority,
+            &base_account,
+            &quote_account,
+            &self.base_mint,
+            &self.quote_mint,
+            &order_packet,
+        );
+        // The instruction builder always uses SPL Token and the mainnet program and its PDAs
+        let mut replacements = vec![(spl_token::id(), self.token_program)];
+        if self.program_id != phoenix::id() {
+            instruction.program_id = self.program_id;
+            replacements.extend([
+                (phoenix::id(), self.program_id),
+                (
+                    Self::derive_log_authority(&phoenix::id()),
+                    self.log_authority,
+                ),
+                (
+                    Self::derive_vault(&phoenix::id(), &self.market_key, &self.base_mint),
+                    self.base_vault,
+                ),
+                (
+                    Self::derive_vault(&phoenix::id(), &self.market_key, &self.quote_mint),
+                    self.quote_vault,
+                ),
+            ]);
+        }
+        for account_meta in instruction.accounts.iter_mut() {
+            if let Some((_, replacement)) = replacements
+                .iter()
+                .find(|(default, _)| *default == account_meta.pubkey)
+            {
+                account_meta.pubkey = *replacement;
+            }
+        }
+        Ok(instruction)
+    }
+
+    /// The least output to accept for `quote` with a tolerance of `slippage_bps` basis points:
+    /// its `out_amount` less the tolerance, rounded down so the floor is always achievable at
+    /// the quoted price. Zero bps gives the quoted amount exactly, and tolerances of 10000 bps
+    /// or more accept any output.
+    pub fn min_out_with_slippage(&self, quote: &Quote, slippage_bps: u16) -> u64 {
+        let kept_bps = 10_000 - slippage_bps.min(10_000) as u128;
+        (quote.out_amount as u128 * kept_bps / 10_000) as u64
+    }
+
+    /// Instructions creating whichever of the source and destination token accounts in
+    /// `swap_params` do not exist yet, funded by `payer`, to run before the swap. An account
+    /// exists if it is a key of `accounts_map`, so nothing is returned once both have been
+    /// created. Only the user's associated token accounts for the market's token program can be
+    /// created, so a missing account at any other address is an error.
+    ///
+    /// Jupiter's `get_user_setup` can only describe Serum open orders accounts, so this is for
+    /// integrators that build the swap transaction themselves. The instructions are idempotent,
+    /// so a transaction built from a stale `accounts_map` still lands.
+    pub fn user_setup_instructions(
+        &self,
+        swap_params: &SwapParams,
+        payer: &Pubkey,
+        accounts_map: &HashMap<Pubkey, PartialAccount>,
+    ) -> Result<Vec<Instruction>> {
+        // Validates the mints against the market
+        self.swap_side_and_token_accounts(swap_params)?;
+        let mut instructions = vec![];
+        for (mint, token_account) in [
+            (
+                swap_params.source_mint,
+                swap_params.user_source_token_account,
+            ),
+            (
+                swap_params.destination_mint,
+                swap_params.user_destination_token_account,
+            ),
+        ] {
+            if accounts_map.contains_key(&token_account) {
+                continue;
+            }
+            let associated_token_account = get_associated_token_address_with_program_id(
+                &swap_params.user_transfer_authority,
+                &mint,
+                &self.token_program,
+            );
+            if token_account != associated_token_account {
+                return Err(Error::msg(
+                    "Missing user token account is not an associated token account",
+                ));
+            }
+            instructions.push(create_associated_token_account_idempotent(
+                payer,
+                &swap_params.user_transfer_authority,
+                &mint,
+                &self.token_program,
+            ));
+        }
+        Ok(instructions)
+    }
+
+    /// Builds the account list for Phoenix's `PlaceLimitOrder` instruction, in the order the
+    /// program expects. This is a maker path and is entirely separate from the `Amm` swap path:
+    /// the trader must already have a seat on the market and the caller is responsible for
+    /// encoding the order packet. Token accounts are the trader's associated token accounts under
+    /// the market's token program. The accounts are the same for either side, and `price_in_ticks`
+    /// and `size_in_base_lots` do not change them either; they are only validated, so an order
+    /// the program would reject fails here before the caller encodes its packet.
+    pub fn place_limit_order_metas(
+        &self,
+        price_in_ticks: u64,
+        size_in_base_lots: u64,
+        trader: &Pubkey,
+    ) -> Result<Vec<AccountMeta>> {
+        if price_in_ticks == 0 {
+            return Err(Error::msg("Order price must be at least one tick"));
+        }
+        if size_in_base_lots == 0 {
+            return Err(Error::msg("Order size must be at least one base lot"));
+        }
+
+        let seat = Pubkey::find_program_address(
+            &[b"seat", self.market_key.as_ref(), trader.as_ref()],
+            &self.program_id,
+        )
+        .0;
+
+        Ok(vec![
+            AccountMeta::new_readonly(self.program_id, false),
+            AccountMeta::new_readonly(self.log_authority, false),
+            AccountMeta::new(self.market_key, false),
+            AccountMeta::new_readonly(*trader, true),
+            AccountMeta::new_readonly(seat, false),
+            AccountMeta::new(
+                get_associated_token_address_with_program_id(
+                    trader,
+                    &self.base_mint,
+                    &self.token_program,
+                ),
+                false,
+            ),
+            AccountMeta::new(
+                get_associated_token_address_with_program_id(
+                    trader,
+                    &self.quote_mint,
+                    &self.token_program,
+                ),
+                false,
+            ),
+            AccountMeta::new(self.base_vault, false),
+            AccountMeta::new(self.quote_vault, false),
+            AccountMeta::new_readonly(self.token_program, false),
+        ])
+    }
+
+    /// Validates the mints of `swap_params` against the market and returns the taker side
+    /// along with the user's base and quote token accounts
+    fn swap_side_and_token_accounts(
+        &self,
+        swap_params: &SwapParams,
+    ) -> Result<(Side, Pubkey, Pubkey)> {
+        let SwapParams {
+            destination_mint,
+            source_mint,
+            user_destination_token_account,
+            user_source_token_account,
+            ..
+        } = swap_params;
+
+        if source_mint == destination_mint {
+            return Err(Error::msg("Source and destination mints are identical"));
+        }
+        // Aliased writable token accounts would only fail later, and opaquely, on-chain
+        if user_source_token_account == user_destination_token_account {
+            return Err(Error::msg(
+                "Source and destination token accounts are identical",
+            ));
+        }
+
+        match self.side_for_mints(source_mint, destination_mint)? {
+            Side::Ask => Ok((
+                Side::Ask,
+                *user_source_token_account,
+                *user_destination_token_account,
+            )),
+            Side::Bid => Ok((
+                Side::Bid,
+                *user_destination_token_account,
+                *user_source_token_account,
+            )),
+        }
+    }
+
+    fn derive_log_authority(program_id: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(&["log".as_ref()], program_id).0
+    }
+
+    fn derive_vault(program_id: &Pubkey, market_key: &Pubkey, mint: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(&[b"vault", market_key.as_ref(), mint.as_ref()], program_id).0
+    }
+}
+
+/// The ladder behind `ladder`, for a reload to overwrite. Clones of the market share it until
+/// then, and a shared ladder is swapped for an empty one rather than copied, since the reload
+/// replaces every level anyway.
+fn unshared_ladder(ladder: &mut Arc<Ladder>) -> &mut Ladder {
+    if Arc::get_mut(ladder).is_none() {
+        *ladder = Arc::new(Ladder {
+            bids: vec![],
+            asks: vec![],
+        });
+    }
+    Arc::get_mut(ladder).expect("ladder is not shared")
+}
+
+fn atoms_to_ui(atoms: u64, decimals: u32) -> f64 {
+    atoms as f64 / 10f64.powi(decimals as i32)
+}
+
+/// Float to integer casts saturate, and map NaN to zero
+fn ui_to_atoms(ui: f64, decimals: u32) -> u64 {
+    (ui * 10f64.powi(decimals as i32)).round() as u64
+}
+
+fn ui_parts_to_atoms(whole: u64, fractional: u64, decimals: u32) -> Option<u64> {
+    let atoms_per_unit = 10u64.pow(decimals);
+    if fractional >= atoms_per_unit {
+        return None;
+    }
+    whole.checked_mul(atoms_per_unit)?.checked_add(fractional)
+}
+
+/// Splits a market account into its header and the market bytes that follow it, returning an
+/// error instead of panicking on data that is too short or misaligned
+fn parse_market_account(data: &[u8]) -> Result<(&MarketHeader, &[u8])> {
+    if data.len() < size_of::<MarketHeader>() {
+        return Err(PhoenixAmmError::MalformedMarketAccount.into());
+    }
+    let (header_bytes, bytes) = data.split_at(size_of::<MarketHeader>());
+    let header = bytemuck::try_from_bytes::<MarketHeader>(header_bytes)
+        .map_err(|_| PhoenixAmmError::MalformedMarketAccount)?;
+    // `MarketStatus::from` panics on unknown statuses
+    if header.status > MarketStatus::Tombstoned as u64 {
+        return Err(PhoenixAmmError::MalformedMarketAccount.into());
+    }
+    if bytes.len() < get_market_size(&header.market_size_params)? {
+        return Err(PhoenixAmmError::MalformedMarketAccount.into());
+    }
+    Ok((header, bytes))
+}
+
+/// `MarketMetadata::from_header` panics on zero lot sizes and on decimals that overflow a u64,
+/// so those are rejected before it is called
+fn market_metadata_from_header(header: &MarketHeader) -> Result<MarketMetadata> {
+    if u64::from(header.get_base_lot_size()) == 0 || u64::from(header.get_quote_lot_size()) == 0 {
+        return Err(PhoenixAmmError::ZeroLotSize.into());
+    }
+    let base_atoms_per_base_unit = 10u64
+        .checked_pow(header.base_params.decimals)
+        .and_then(|atoms| atoms.checked_mul(header.raw_base_units_per_base_unit.max(1) as u64));
+    if base_atoms_per_base_unit.is_none()
+        || 10u64.checked_pow(header.quote_params.decimals).is_none()
+    {
+        return Err(Error::msg("Market decimals are out of range"));
+    }
+    MarketMetadata::from_header(header)
+}
+
+impl Amm for JupiterPhoenix {
+    fn program_id(&self) -> Pubkey {
+        self.program_id
+    }
+
+    fn from_keyed_account(keyed_account: &KeyedAccount) -> Result<Self> {
+        JupiterPhoenix::new_from_keyed_account(keyed_account)
+    }
+
+    fn label(&self) -> String {
+        self.label.clone()
+    }
+
+    fn key(&self) -> Pubkey {
+        self.market_key
+    }
+
+    /// Always `[base_mint, quote_mint]`, in that order. Use `is_base` to map a mint to its role
+    /// without relying on the order.
+    fn get_reserve_mints(&self) -> Vec<Pubkey> {
+        vec![self.base_mint, self.quote_mint]
+    }
+
+    /// The market, and the clock sysvar so orders that have expired since they were placed are
+    /// not quoted. Without the clock, `update` treats every resting order as live.
+    fn get_accounts_to_update(&self) -> Vec<Pubkey> {
+        if self.vaults_in_accounts_to_update {
+            vec![
+                self.market_key,
+                clock::id(),
+                self.base_vault,
+                self.quote_vault,
+            ]
+        } else {
+            vec![self.market_key, clock::id()]
+        }
+    }
+
+    fn update(&mut self, accounts_map: &HashMap<Pubkey, PartialAccount>) -> Result<()> {
+        #[cfg(feature = "tracing")]
+        let span = instrumentation::TimedSpan::enter(tracing::debug_span!(
+            "update",
+            market = %self.market_key,
+            levels = tracing::field::Empty,
+            elapsed_us = tracing::field::Empty,
+        ));
+        let result = self.load_ladder(accounts_map);
+        #[cfg(feature = "tracing")]
+        span.record(
+            "levels",
+            (self.ladder.bids.len() + self.ladder.asks.len()) as u64,
+        );
+        self.record_update_result(result)
+    }
+
+    /// `quote_side` for the side `quote_params` trades on. Errors with
+    /// `PhoenixAmmError::MintNotInMarket`, `InvalidQuoteMint`, or `InvalidBaseMint` unless its
+    /// mints are the market's base and quote mints, one each, like a swap would.
+    fn quote(&self, quote_params: &QuoteParams) -> Result<Quote> {
+        // `levels_walked` is recorded by `quote_on_ladder`
+        #[cfg(feature = "tracing")]
+        let _span = instrumentation::TimedSpan::enter(tracing::debug_span!(
+            "quote",
+            market = %self.market_key,
+            direction = if quote_params.input_mint == self.base_mint { "sell" } else { "buy" },
+            in_amount = quote_params.in_amount,
+            levels_walked = tracing::field::Empty,
+            elapsed_us = tracing::field::Empty,
+        ));
+        self.quote_against_ladder(&self.ladder, quote_params)
+    }
+
+    fn get_swap_leg_and_account_metas(
+        &self,
+        swap_params: &SwapParams,
+    ) -> Result<SwapAndAccountMetas> {
+        if !self.is_swappable() {
+            return Err(PhoenixAmmError::MarketNotSwappable.into());
+        }
+        let (side, base_account, quote_account) = self.swap_side_and_token_accounts(swap_params)?;
+        // The PDAs are derived once from the market key and mints, which never change
+        debug_assert_eq!(
+            self.base_vault,
+            Self::derive_vault(&self.program_id, &self.market_key, &self.base_mint)
+        );
+        debug_assert_eq!(
+            self.quote_vault,
+            Self::derive_vault(&self.program_id, &self.market_key, &self.quote_mint)
+        );
+
+        // These are the accounts of Phoenix's `Swap` instruction (program, log authority, market,
+        // trader, trader base and quote accounts, vaults, token program) in the order each leg's
+        // CPI expects. Event logging is a self-CPI signed by the log authority, so it needs no
+        // instructions sysvar or separate event authority.
+        let (swap, account_metas): (Swap, [AccountMeta; SWAP_ACCOUNT_META_COUNT]) =
+            match self.swap_leg {
+                SwapLeg::Serum => (
+                    Swap::Serum { side },
+                    [
+                        AccountMeta::new(self.market_key, false),
+                        AccountMeta::new(swap_params.user_transfer_authority, true),
+                        AccountMeta::new_readonly(self.log_authority, false),
+                        AccountMeta::new_readonly(self.program_id, false),
+                        AccountMeta::new(base_account, false),
+                        AccountMeta::new(quote_account, false),
+                        AccountMeta::new(self.base_vault, false),
+                        AccountMeta::new(self.quote_vault, false),
+                        AccountMeta::new_readonly(self.token_program, false),
+                    ],
+                ),
+                // Passed through to the program as is, so the trader is a read-only signer
+                SwapLeg::Phoenix => (
+                    Swap::Phoenix { side },
+                    [
+                        AccountMeta::new_readonly(self.program_id, false),
+                        AccountMeta::new_readonly(self.log_authority, false),
+                        AccountMeta::new(self.market_key, false),
+                        AccountMeta::new_readonly(swap_params.user_transfer_authority, true),
+                        AccountMeta::new(base_account, false),
+                        AccountMeta::new(quote_account, false),
+                        AccountMeta::new(self.base_vault, false),
+                        AccountMeta::new(self.quote_vault, false),
+                        AccountMeta::new_readonly(self.token_program, false),
+                    ],
+                ),
+            };
+
+        let mut account_metas = account_metas.to_vec();
+        // Phoenix's `Swap` instruction defines exactly the nine accounts above and ignores any
+        // that follow, so the referral account is only there for the routing program to read
+        if let Some(fee_recipient) = self.fee_recipient {
+            account_metas.push(AccountMeta::new_readonly(fee_recipient, false));
+        }
+        account_metas.extend_from_slice(&self.extra_swap_account_metas);
+        Ok(SwapAndAccountMetas {
+            swap,
+            account_metas,
+        })
+    }
+
+    /// `update` only ever reads the market and the clock, so Jupiter need not re-query
+    /// `get_accounts_to_update` after each update
+    fn has_dynamic_accounts(&self) -> bool {
+        false
+    }
+
+    /// Phoenix swaps need no open orders account. Missing token accounts can be created with
+    /// `JupiterPhoenix::user_setup_instructions`, which this hook has no way to express.
+    fn get_user_setup(&self) -> Option<AmmUserSetup> {
+        None
+    }
+
+    /// Phoenix swaps are immediate-or-cancel taker orders that can sell base into the bids or
+    /// buy base from the asks, so both directions are always routable
+    fn unidirectional(&self) -> bool {
+        false
+    }
+
+    /// Swaps CPI into the market's token program, which is only listed for Token-2022 markets
+    /// since every router can already execute SPL Token
+    fn program_dependencies(&self) -> Vec<(Pubkey, String)> {
+        if self.token_program == TOKEN_2022_PROGRAM_ID {
+            vec![(TOKEN_2022_PROGRAM_ID, "spl-token-2022".to_string())]
+        } else {
+            vec![]
+        }
+    }
+
+    fn clone_amm(&self) -> Box<dyn Amm + Send + Sync> {
+        Box::new(self.clone())
+    }
+}
+
+#[test]
+#[ignore = "hits mainnet RPC, see test_market_fixtures for the offline equivalent"]
+fn test_jupiter_phoenix_integration() {
+    use jupiter_core::amm::Amm;
+    use solana_client::rpc_client::RpcClient;
+    use solana_sdk::pubkey;
+    use solana_sdk::pubkey::Pubkey;
+    use std::collections::HashMap;
+
+    const SOL_USDC_MARKET: Pubkey = pubkey!("4DoNfFBfF7UokCC2FQzriy7yHK6DY6NVdYpuekQ5pRgg");
+    const BONK_USDC_MARKET: Pubkey = pubkey!("GBMoNx84HsFdVK63t8BZuDgyZhSBaeKWB4pHHpoeRM9z");
+
+    let rpc = RpcClient::new("https://api.mainnet-beta.solana.com/");
+    let account = rpc.get_account(&SOL_USDC_MARKET).unwrap();
+
+    let market_account = KeyedAccount {
+        key: SOL_USDC_MARKET,
+        account,
+        params: None,
+    };
+
+    let mut jupiter_phoenix = JupiterPhoenix::new_from_keyed_account(&market_account).unwrap();
+    // The only check of the header accessors against a real market's header
+    assert_ne!(jupiter_phoenix.authority(), Pubkey::default());
+    assert_ne!(jupiter_phoenix.market_fee_recipient(), Pubkey::default());
+
+    let accounts_to_update = jupiter_phoenix.get_accounts_to_update();
+
+    let accounts_map = rpc
+        .get_multiple_accounts(&accounts_to_update)
+        .unwrap()
+        .iter()
+        .enumerate()
+        .fold(HashMap::new(), |mut m, (index, account)| {
+            if let Some(account) = account {
+                m.insert(
+                    accounts_to_update[index],
+                    PartialAccount::from(account.clone()),
+                );
+            }
+            m
+        });
+    jupiter_phoenix.update(&accounts_map).unwrap();
+    let in_amount = 1_000_000_000_000;
+    println!(
+        "Getting quote for selling {} SOL",
+        jupiter_phoenix.base_atoms_to_ui(in_amount)
+    );
+    let quote_in = jupiter_phoenix.base_atoms_to_ui(in_amount);
+    let quote = jupiter_phoenix
+        .quote(&QuoteParams {
+            // 1000 SOL
+            in_amount,
+            input_mint: jupiter_phoenix.base_mint,
+            output_mint: jupiter_phoenix.quote_mint,
+        })
+        .unwrap();
+
+    let Quote { out_amount, .. } = quote;
+
+    let quote_out = jupiter_phoenix.quote_atoms_to_ui(out_amount);
+    println!("Quote result: {:?} ({})", quote_out, quote_out / quote_in);
+
+    let in_amount = out_amount;
+
+    println!(
+        "Getting quote for buying SOL with {} USDC",
+        jupiter_phoenix.quote_atoms_to_ui(in_amount)
+    );
+    let quote_in = jupiter_phoenix.quote_atoms_to_ui(in_amount);
+    let quote = jupiter_phoenix
+        .quote(&QuoteParams {
+            in_amount,
+            input_mint: jupiter_phoenix.quote_mint,
+            output_mint: jupiter_phoenix.base_mint,
+        })
+        .unwrap();
+
+    let Quote { out_amount, .. } = quote;
+
+    let quote_out = jupiter_phoenix.base_atoms_to_ui(out_amount);
+    println!(
+        "Quote result: {:?} ({})",
+        jupiter_phoenix.base_atoms_to_ui(out_amount),
+        quote_in / quote_out
+    );
+
+    let account = rpc.get_account(&BONK_USDC_MARKET).unwrap();
+
+    let market_account = KeyedAccount {
+        key: BONK_USDC_MARKET,
+        account,
+        params: None,
+    };
+
+    let mut jupiter_phoenix = JupiterPhoenix::new_from_keyed_account(&market_account).unwrap();
+
+    let accounts_to_update = jupiter_phoenix.get_accounts_to_update();
+
+    let accounts_map = rpc
+        .get_multiple_accounts(&accounts_to_update)
+        .unwrap()
+        .iter()
+        .enumerate()
+        .fold(HashMap::new(), |mut m, (index, account)| {
+            if let Some(account) = account {
+                m.insert(
+                    accounts_to_update[index],
+                    PartialAccount::from(account.clone()),
+                );
+            }
+            m
+        });
+    jupiter_phoenix.update(&accounts_map).unwrap();
+    let in_amount = 100_000_000_000_000;
+    println!(
+        "Getting quote for selling {} BONK",
+        jupiter_phoenix.base_atoms_to_ui(in_amount)
+    );
+    let quote_in = jupiter_phoenix.base_atoms_to_ui(in_amount);
+    let quote = jupiter_phoenix
+        .quote(&QuoteParams {
+            // 1B Bonk
+            in_amount,
+            input_mint: jupiter_phoenix.base_mint,
+            output_mint: jupiter_phoenix.quote_mint,
+        })
+        .unwrap();
+
+    let Quote { out_amount, .. } = quote;
+
+    let quote_out = jupiter_phoenix.quote_atoms_to_ui(out_amount);
     println!("Quote result: {:?} ({})", quote_out, quote_out / quote_in);
 
-    let in_amount = out_amount;
+    let in_amount = out_amount;
+
+    println!(
+        "Getting quote for buying BONK with {} USDC",
+        jupiter_phoenix.quote_atoms_to_ui(in_amount)
+    );
+    let quote_in = jupiter_phoenix.quote_atoms_to_ui(in_amount);
+    let quote = jupiter_phoenix
+        .quote(&QuoteParams {
+            in_amount,
+            input_mint: jupiter_phoenix.quote_mint,
+            output_mint: jupiter_phoenix.base_mint,
+        })
+        .unwrap();
+
+    let Quote { out_amount, .. } = quote;
+
+    let quote_out = jupiter_phoenix.base_atoms_to_ui(out_amount);
+    println!(
+        "Quote result: {:?} ({})",
+        jupiter_phoenix.base_atoms_to_ui(out_amount),
+        quote_in / quote_out
+    );
+}
+
+#[test]
+fn test_degenerate_market_is_rejected() {
+    use crate::test_utils::{keyed_account, market_data, market_header};
+    use phoenix::state::markets::Ladder;
+
+    let mint = Pubkey::new_unique();
+    let header = market_header(mint, mint, 1_000_000, 1, 1_000);
+    let ladder = Ladder {
+        bids: vec![],
+        asks: vec![],
+    };
+    let keyed_account = keyed_account(market_data(&header, &ladder, 0));
+
+    let err = JupiterPhoenix::new_from_keyed_account(&keyed_account).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<PhoenixAmmError>(),
+        Some(&PhoenixAmmError::DegenerateMarket)
+    );
+}
+
+#[test]
+fn test_ask_depth_in_quote_atoms() {
+    use crate::test_utils::sol_usdc_market;
+
+    let ladder = Ladder {
+        bids: vec![],
+        asks: vec![
+            LadderOrder {
+                price_in_ticks: 20_001,
+                size_in_base_lots: 1_000,
+            },
+            LadderOrder {
+                price_in_ticks: 20_002,
+                size_in_base_lots: 500,
+            },
+        ],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 0);
+
+    // 1 SOL at 20.001 USDC plus 0.5 SOL at 20.002 USDC
+    assert_eq!(jupiter_phoenix.ask_depth_in_quote_atoms(), 30_002_000);
+}
+
+#[test]
+fn test_quote_side_matches_swap_side() {
+    use crate::test_utils::{sol_usdc_market, swap_params};
+
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 19_999,
+            size_in_base_lots: 1_000,
+        }],
+        asks: vec![LadderOrder {
+            price_in_ticks: 20_001,
+            size_in_base_lots: 1_000,
+        }],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 0);
+    let (base_mint, quote_mint) = (jupiter_phoenix.base_mint, jupiter_phoenix.quote_mint);
+
+    // Selling base walks the bids and is emitted as an ask
+    let quote = jupiter_phoenix
+        .quote(&QuoteParams {
+            in_amount: 1_000_000_000,
+            input_mint: base_mint,
+            output_mint: quote_mint,
+        })
+        .unwrap();
+    let sell = jupiter_phoenix
+        .quote_side(Side::Ask, 1_000_000_000)
+        .unwrap();
+    assert_eq!(quote.out_amount, sell.out_amount);
+    assert_eq!(sell.out_amount, 19_999_000);
+    let swap = jupiter_phoenix
+        .get_swap_leg_and_account_metas(&swap_params(base_mint, quote_mint))
+        .unwrap()
+        .swap;
+    assert!(matches!(swap, Swap::Serum { side: Side::Ask }));
+
+    // Buying base walks the asks and is emitted as a bid
+    let quote = jupiter_phoenix
+        .quote(&QuoteParams {
+            in_amount: 20_001_000,
+            input_mint: quote_mint,
+            output_mint: base_mint,
+        })
+        .unwrap();
+    let buy = jupiter_phoenix.quote_side(Side::Bid, 20_001_000).unwrap();
+    assert_eq!(quote.out_amount, buy.out_amount);
+    assert_eq!(buy.out_amount, 1_000_000_000);
+    let swap = jupiter_phoenix
+        .get_swap_leg_and_account_metas(&swap_params(quote_mint, base_mint))
+        .unwrap()
+        .swap;
+    assert!(matches!(swap, Swap::Serum { side: Side::Bid }));
+}
+
+#[test]
+fn test_not_enough_liquidity() {
+    use crate::test_utils::sol_usdc_market;
+
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 19_999,
+            size_in_base_lots: 1_000,
+        }],
+        asks: vec![LadderOrder {
+            price_in_ticks: 20_001,
+            size_in_base_lots: 1_000,
+        }],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 0);
+
+    // Exactly the depth of each side can be filled, and full fills report their input too
+    let sell = jupiter_phoenix
+        .quote_side(Side::Ask, 1_000_000_000)
+        .unwrap();
+    assert!(!sell.not_enough_liquidity);
+    assert_eq!(sell.in_amount, 1_000_000_000);
+    let buy = jupiter_phoenix.quote_side(Side::Bid, 20_001_000).unwrap();
+    assert!(!buy.not_enough_liquidity);
+    assert_eq!(buy.in_amount, 20_001_000);
+    // Dust below one base lot is not unfilled liquidity, and does not trade
+    let sell = jupiter_phoenix
+        .quote_side(Side::Ask, 1_000_999_999)
+        .unwrap();
+    assert!(!sell.not_enough_liquidity);
+    assert_eq!(sell.in_amount, 1_000_000_000);
+    // Half a level's cost buys half its lots
+    let buy = jupiter_phoenix.quote_side(Side::Bid, 10_000_500).unwrap();
+    assert!(!buy.not_enough_liquidity);
+    assert_eq!(buy.in_amount, 10_000_500);
+    assert_eq!(buy.out_amount, 500_000_000);
+
+    // One more lot than the book holds cannot, and the quote reports the fillable input
+    let sell = jupiter_phoenix
+        .quote_side(Side::Ask, 1_001_000_000)
+        .unwrap();
+    assert!(sell.not_enough_liquidity);
+    assert_eq!(sell.in_amount, 1_000_000_000);
+    assert_eq!(sell.out_amount, 19_999_000);
+    let buy = jupiter_phoenix.quote_side(Side::Bid, 20_001_001).unwrap();
+    assert!(buy.not_enough_liquidity);
+    assert_eq!(buy.in_amount, 20_001_000);
+    assert_eq!(buy.out_amount, 1_000_000_000);
+
+    // Probing with a size far beyond the book clamps to the whole book the same way, up to the
+    // largest quote budget the program can hold
+    let (base_mint, quote_mint) = (jupiter_phoenix.base_mint, jupiter_phoenix.quote_mint);
+    for (input_mint, output_mint, probe, in_amount, out_amount) in [
+        (
+            base_mint,
+            quote_mint,
+            u64::MAX / 2,
+            1_000_000_000,
+            19_999_000,
+        ),
+        (
+            quote_mint,
+            base_mint,
+            u64::MAX / 1_000,
+            20_001_000,
+            1_000_000_000,
+        ),
+    ] {
+        let quote = jupiter_phoenix
+            .quote(&QuoteParams {
+                in_amount: probe,
+                input_mint,
+                output_mint,
+            })
+            .unwrap();
+        assert!(quote.not_enough_liquidity);
+        assert_eq!(quote.in_amount, in_amount);
+        assert_eq!(quote.out_amount, out_amount);
+    }
+}
+
+#[test]
+fn test_truncated_market_account_is_rejected() {
+    use crate::test_utils::{keyed_account, market_data, market_header};
+    use phoenix::state::markets::Ladder;
+
+    let header = market_header(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        1_000_000,
+        1,
+        1_000,
+    );
+    let ladder = Ladder {
+        bids: vec![],
+        asks: vec![],
+    };
+    let data = market_data(&header, &ladder, 0);
+    // Shorter than the header, cut inside the market, and full length
+    for len in [0, size_of::<MarketHeader>() - 1, data.len() - 1] {
+        let keyed_account = keyed_account(data[..len].to_vec());
+        let err = JupiterPhoenix::new_from_keyed_account(&keyed_account)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.downcast_ref::<PhoenixAmmError>(),
+            Some(&PhoenixAmmError::MalformedMarketAccount)
+        );
+    }
+    assert!(JupiterPhoenix::new_from_keyed_account(&keyed_account(data)).is_ok());
+}
+
+#[test]
+fn test_update_with_missing_or_truncated_account() {
+    use crate::test_utils::{keyed_account, market_data, market_header};
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let header = market_header(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        1_000_000,
+        1,
+        1_000,
+    );
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 19_999,
+            size_in_base_lots: 1_000,
+        }],
+        asks: vec![],
+    };
+    let keyed_account = keyed_account(market_data(&header, &ladder, 0));
+    let mut jupiter_phoenix = JupiterPhoenix::new_from_keyed_account(&keyed_account).unwrap();
+
+    let other_account = HashMap::from([(
+        Pubkey::new_unique(),
+        PartialAccount::from(keyed_account.account.clone()),
+    )]);
+    for accounts_map in [HashMap::new(), other_account] {
+        let err = jupiter_phoenix.update(&accounts_map).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<PhoenixAmmError>(),
+            Some(&PhoenixAmmError::MissingAccountUpdate)
+        );
+    }
+    for len in [0, size_of::<MarketHeader>() - 1, size_of::<MarketHeader>()] {
+        let mut account = keyed_account.account.clone();
+        account.data.truncate(len);
+        let accounts_map = HashMap::from([(keyed_account.key, PartialAccount::from(account))]);
+        let err = jupiter_phoenix.update(&accounts_map).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<PhoenixAmmError>(),
+            Some(&PhoenixAmmError::MalformedMarketAccount)
+        );
+    }
+    assert_eq!(jupiter_phoenix.consecutive_update_failures(), 5);
+    assert!(jupiter_phoenix.quote_side(Side::Ask, 1_000_000_000).is_ok());
+}
+
+#[test]
+fn test_two_way_quote_spread_is_positive() {
+    use crate::test_utils::sol_usdc_market;
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let ladder = Ladder {
+        bids: vec![
+            LadderOrder {
+                price_in_ticks: 19_999,
+                size_in_base_lots: 500,
+            },
+            LadderOrder {
+                price_in_ticks: 19_998,
+                size_in_base_lots: 500,
+            },
+        ],
+        asks: vec![
+            LadderOrder {
+                price_in_ticks: 20_001,
+                size_in_base_lots: 500,
+            },
+            LadderOrder {
+                price_in_ticks: 20_002,
+                size_in_base_lots: 500,
+            },
+        ],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 0);
+
+    let (sell, buy) = jupiter_phoenix.two_way_quote(1_000).unwrap();
+    // Both sides trade 1 SOL of base
+    assert_eq!(sell.in_amount, 1_000_000_000);
+    assert_eq!(buy.out_amount, 1_000_000_000);
+    // 9.9995 + 9.9990 USDC received for selling, 10.0005 + 10.0010 USDC spent buying
+    assert_eq!(sell.out_amount, 19_998_500);
+    assert_eq!(buy.in_amount, 20_001_500);
+    assert!(buy.in_amount > sell.out_amount);
+
+    // Buys pay the taker fee out of their input, so the buy input grows by the fee
+    let jupiter_phoenix = sol_usdc_market(&ladder, 5);
+    let (base_mint, quote_mint) = (jupiter_phoenix.base_mint, jupiter_phoenix.quote_mint);
+    let buy_base = |in_amount| {
+        jupiter_phoenix
+            .quote(&QuoteParams {
+                in_amount,
+                input_mint: quote_mint,
+                output_mint: base_mint,
+            })
+            .unwrap()
+            .out_amount
+    };
+    for size_in_base_lots in [1, 700, 1_000] {
+        let (sell, buy) = jupiter_phoenix.two_way_quote(size_in_base_lots).unwrap();
+        let base_atoms = size_in_base_lots * 1_000_000;
+        assert_eq!(sell.in_amount, base_atoms);
+        assert_eq!(buy.out_amount, base_atoms);
+        assert!(!buy.not_enough_liquidity);
+        // Swapping the buy input fills exactly the size, and one quote atom less falls short
+        assert_eq!(buy_base(buy.in_amount), base_atoms);
+        assert!(buy_base(buy.in_amount - 1) < base_atoms);
+    }
+    let (_, buy) = jupiter_phoenix.two_way_quote(1_000).unwrap();
+    // 20.0015 USDC of asks plus a 5 bps fee, rounded up
+    assert_eq!(buy.in_amount, 20_011_501);
+    assert_eq!(buy.fee_amount, 10_001);
+
+    // More than the asks hold
+    let (_, buy) = jupiter_phoenix.two_way_quote(1_001).unwrap();
+    assert!(buy.not_enough_liquidity);
+    assert_eq!(buy.out_amount, 1_000_000_000);
+
+    assert_eq!(
+        jupiter_phoenix
+            .two_way_quote(u64::MAX)
+            .unwrap_err()
+            .downcast_ref::<PhoenixAmmError>(),
+        Some(&PhoenixAmmError::AmountOverflow)
+    );
+}
+
+#[test]
+fn test_update_rejects_changed_metadata() {
+    use crate::test_utils::{keyed_account, market_data, market_header};
+    use phoenix::state::markets::Ladder;
+
+    let (base_mint, quote_mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let ladder = Ladder {
+        bids: vec![],
+        asks: vec![],
+    };
+    let header = market_header(base_mint, quote_mint, 1_000_000, 1, 1_000);
+    let keyed_account = keyed_account(market_data(&header, &ladder, 0));
+    let mut jupiter_phoenix = JupiterPhoenix::new_from_keyed_account(&keyed_account).unwrap();
+
+    let accounts_map = HashMap::from([(
+        keyed_account.key,
+        PartialAccount::from(keyed_account.account.clone()),
+    )]);
+    assert!(jupiter_phoenix.update(&accounts_map).is_ok());
+
+    // Same market, but the tick size has changed underneath the cached metadata
+    let header = market_header(base_mint, quote_mint, 1_000_000, 1, 100);
+    let mut account = keyed_account.account;
+    account.data = market_data(&header, &ladder, 0);
+    let accounts_map = HashMap::from([(keyed_account.key, PartialAccount::from(account))]);
+    let err = jupiter_phoenix.update(&accounts_map).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<PhoenixAmmError>(),
+        Some(&PhoenixAmmError::MetadataChanged)
+    );
+}
+
+#[test]
+fn test_ui_unit_conversions() {
+    use crate::test_utils::{market_fixtures, BONK_USDC_MARKET};
+
+    let [_, (_, bonk_usdc)] = market_fixtures();
+    let jupiter_phoenix =
+        JupiterPhoenix::from_parts(BONK_USDC_MARKET, &phoenix::id(), &bonk_usdc).unwrap();
+    assert_eq!(jupiter_phoenix.get_base_decimals(), 5);
+
+    // 1.5 BONK and 2.25 USDC
+    assert_eq!(jupiter_phoenix.base_atoms_to_ui(150_000), 1.5);
+    assert_eq!(jupiter_phoenix.ui_to_base_atoms(1.5), 150_000);
+    assert_eq!(jupiter_phoenix.quote_atoms_to_ui(2_250_000), 2.25);
+    assert_eq!(jupiter_phoenix.ui_to_quote_atoms(2.25), 2_250_000);
+    assert_eq!(jupiter_phoenix.ui_to_quote_atoms(0.1), 100_000);
+    assert_eq!(jupiter_phoenix.ui_to_base_atoms(-1.0), 0);
+    assert_eq!(jupiter_phoenix.ui_to_base_atoms(f64::NAN), 0);
+    assert_eq!(jupiter_phoenix.ui_to_base_atoms(f64::INFINITY), u64::MAX);
+
+    // About 90 billion BONK, past 2^53 atoms, where the f64 path drops the last atom
+    let atoms = (1u64 << 53) + 1;
+    assert_ne!(
+        jupiter_phoenix.ui_to_base_atoms(jupiter_phoenix.base_atoms_to_ui(atoms)),
+        atoms
+    );
+    let (whole, fractional) = jupiter_phoenix.base_atoms_to_ui_parts(atoms);
+    assert_eq!((whole, fractional), (90_071_992_547, 40_993));
+    assert_eq!(
+        jupiter_phoenix.ui_parts_to_base_atoms(whole, fractional),
+        Some(atoms)
+    );
+    for atoms in [0, 1, 99_999, 100_000, u64::MAX] {
+        let (whole, fractional) = jupiter_phoenix.base_atoms_to_ui_parts(atoms);
+        assert_eq!(
+            jupiter_phoenix.ui_parts_to_base_atoms(whole, fractional),
+            Some(atoms)
+        );
+        let (whole, fractional) = jupiter_phoenix.quote_atoms_to_ui_parts(atoms);
+        assert_eq!(
+            jupiter_phoenix.ui_parts_to_quote_atoms(whole, fractional),
+            Some(atoms)
+        );
+    }
+    assert_eq!(jupiter_phoenix.ui_parts_to_base_atoms(1, 100_000), None);
+    assert_eq!(jupiter_phoenix.ui_parts_to_quote_atoms(u64::MAX, 0), None);
+}
+
+#[test]
+fn test_quote_human_matches_atom_quote() {
+    use crate::test_utils::sol_usdc_market;
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 19_999,
+            size_in_base_lots: 100_000,
+        }],
+        asks: vec![LadderOrder {
+            price_in_ticks: 20_001,
+            size_in_base_lots: 100_000,
+        }],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 2);
+    let [base_mint, quote_mint] =
+        <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+
+    for (input_mint, output_mint, input_amount, in_amount) in [
+        (base_mint, quote_mint, 0.1, 100_000_000),
+        (base_mint, quote_mint, 1.0, 1_000_000_000),
+        (base_mint, quote_mint, 42.5, 42_500_000_000),
+        (quote_mint, base_mint, 0.3, 300_000),
+        (quote_mint, base_mint, 20.001, 20_001_000),
+        (quote_mint, base_mint, 1234.5678, 1_234_567_800),
+    ] {
+        let (input_decimals, output_decimals) = if input_mint == base_mint {
+            (9, 6)
+        } else {
+            (6, 9)
+        };
+        let quote = jupiter_phoenix
+            .quote(&QuoteParams {
+                in_amount,
+                input_mint,
+                output_mint,
+            })
+            .unwrap();
+        assert_eq!(
+            (input_amount * 10f64.powi(input_decimals)).round() as u64,
+            in_amount
+        );
+        assert_eq!(
+            jupiter_phoenix
+                .quote_human(input_mint, input_amount)
+                .unwrap(),
+            quote.out_amount as f64 / 10f64.powi(output_decimals)
+        );
+    }
+
+    assert!(jupiter_phoenix
+        .quote_human(Pubkey::new_unique(), 1.0)
+        .is_err());
+    assert!(jupiter_phoenix.quote_human(base_mint, -1.0).is_err());
+    assert!(jupiter_phoenix.quote_human(base_mint, f64::NAN).is_err());
+}
+
+#[test]
+fn test_quote_no_fee_matches_quote_before_fee() {
+    use crate::test_utils::sol_usdc_market;
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 19_999,
+            size_in_base_lots: 10_000,
+        }],
+        asks: vec![LadderOrder {
+            price_in_ticks: 20_001,
+            size_in_base_lots: 10_000,
+        }],
+    };
+    let taker_fee_bps = 5;
+    let jupiter_phoenix = sol_usdc_market(&ladder, taker_fee_bps);
+    let [base_mint, quote_mint] =
+        <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+
+    for quote_params in [
+        QuoteParams {
+            in_amount: 3_000_000_000,
+            input_mint: base_mint,
+            output_mint: quote_mint,
+        },
+        QuoteParams {
+            in_amount: 77_777_777,
+            input_mint: quote_mint,
+            output_mint: base_mint,
+        },
+    ] {
+        let quote = jupiter_phoenix.quote(&quote_params).unwrap();
+        let no_fee = jupiter_phoenix.quote_no_fee(&quote_params).unwrap();
+        assert!(no_fee.out_amount > quote.out_amount);
+        if quote_params.input_mint == base_mint {
+            assert_eq!(
+                no_fee.out_amount * (10000 - taker_fee_bps) / 10000,
+                quote.out_amount
+            );
+        } else {
+            // Buys only spend what is left of their budget after the fee
+            let budget_after_fee = quote_params.in_amount * 10000 / (10000 + taker_fee_bps);
+            assert_eq!(
+                jupiter_phoenix
+                    .quote_no_fee(&QuoteParams {
+                        in_amount: budget_after_fee,
+                        ..quote_params
+                    })
+                    .unwrap()
+                    .out_amount,
+                quote.out_amount
+            );
+        }
+        assert_eq!(no_fee.not_enough_liquidity, quote.not_enough_liquidity);
+        // Every field, consumed input included, is that of the same market without its fee
+        let mut fee_free = jupiter_phoenix.clone();
+        fee_free.taker_fee_bps = 0;
+        assert_eq!(
+            format!("{no_fee:?}"),
+            format!("{:?}", fee_free.quote(&quote_params).unwrap())
+        );
+        assert_eq!(no_fee.fee_mint, quote_mint);
+    }
+}
+
+#[test]
+fn test_ladder_possibly_truncated() {
+    use crate::test_utils::sol_usdc_market;
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let level = |price_in_ticks| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots: 1_000,
+    };
+    let ladder = Ladder {
+        bids: vec![level(19_999), level(19_998)],
+        asks: vec![level(20_001), level(20_002)],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 0);
+    let [base_mint, quote_mint] =
+        <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+    let sell = |in_amount| QuoteParams {
+        in_amount,
+        input_mint: base_mint,
+        output_mint: quote_mint,
+    };
+    let buy = |in_amount| QuoteParams {
+        in_amount,
+        input_mint: quote_mint,
+        output_mint: base_mint,
+    };
+
+    // Fills that stay within the first level
+    assert!(!jupiter_phoenix.ladder_possibly_truncated(&sell(1_000_000_000)));
+    assert!(!jupiter_phoenix.ladder_possibly_truncated(&buy(20_001_000)));
+    // Fills that reach into the last captured level
+    assert!(jupiter_phoenix.ladder_possibly_truncated(&sell(1_001_000_000)));
+    assert!(jupiter_phoenix.ladder_possibly_truncated(&buy(20_001_001)));
+    assert!(jupiter_phoenix.ladder_possibly_truncated(&sell(10_000_000_000)));
+    // Swaps with a mint outside the market have no fill to be truncated
+    let other_mint = Pubkey::new_unique();
+    assert!(!jupiter_phoenix.ladder_possibly_truncated(&QuoteParams {
+        input_mint: other_mint,
+        ..buy(20_001_001)
+    }));
+    assert!(!jupiter_phoenix.ladder_possibly_truncated(&QuoteParams {
+        output_mint: other_mint,
+        ..sell(10_000_000_000)
+    }));
+}
+
+#[test]
+fn test_swap_account_metas_order() {
+    use crate::test_utils::{sol_usdc_market, swap_params};
+    use phoenix::state::markets::Ladder;
+
+    let ladder = Ladder {
+        bids: vec![],
+        asks: vec![],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 0);
+    let [base_mint, quote_mint] =
+        <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+    let market_key = jupiter_phoenix.key();
+    let program_id = phoenix::id();
+    let log_authority = Pubkey::find_program_address(&[b"log"], &program_id).0;
+    let vault = |mint: &Pubkey| {
+        Pubkey::find_program_address(&[b"vault", market_key.as_ref(), mint.as_ref()], &program_id).0
+    };
+
+    for (source_mint, destination_mint) in [(base_mint, quote_mint), (quote_mint, base_mint)] {
+        let params = swap_params(source_mint, destination_mint);
+        let (base_account, quote_account) = if source_mint == base_mint {
+            (
+                params.user_source_token_account,
+                params.user_destination_token_account,
+            )
+        } else {
+            (
+                params.user_destination_token_account,
+                params.user_source_token_account,
+            )
+        };
+        let account_metas = jupiter_phoenix
+            .get_swap_leg_and_account_metas(&params)
+            .unwrap()
+            .account_metas;
+        assert_eq!(
+            account_metas,
+            vec![
+                AccountMeta::new(market_key, false),
+                AccountMeta::new(params.user_transfer_authority, true),
+                AccountMeta::new_readonly(log_authority, false),
+                AccountMeta::new_readonly(program_id, false),
+                AccountMeta::new(base_account, false),
+                AccountMeta::new(quote_account, false),
+                AccountMeta::new(vault(&base_mint), false),
+                AccountMeta::new(vault(&quote_mint), false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+            ]
+        );
+        assert_eq!(account_metas.len(), SWAP_ACCOUNT_META_COUNT);
+        assert_eq!(
+            account_metas.len(),
+            jupiter_phoenix.swap_account_meta_count()
+        );
+    }
+}
+
+#[test]
+fn test_quote_with_free_funds() {
+    use crate::test_utils::sol_usdc_market;
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 20_000,
+            size_in_base_lots: 1_000,
+        }],
+        asks: vec![LadderOrder {
+            price_in_ticks: 20_000,
+            size_in_base_lots: 1_000,
+        }],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 0);
+
+    // Less than one 0.001 SOL base lot cannot be sold
+    let sell = jupiter_phoenix
+        .quote_with_free_funds(Side::Ask, 999_999, 1_000_000_000)
+        .unwrap();
+    assert_eq!(sell.out_amount, 0);
+    // 2.5 base lots sells two of them, the quote balance is ignored
+    let sell = jupiter_phoenix
+        .quote_with_free_funds(Side::Ask, 2_500_000, 0)
+        .unwrap();
+    assert_eq!(sell.out_amount, 40_000);
+    assert!(!sell.not_enough_liquidity);
+
+    // Less than the 20_000 quote atoms one base lot costs cannot buy anything
+    let buy = jupiter_phoenix
+        .quote_with_free_funds(Side::Bid, 1_000_000_000, 19_999)
+        .unwrap();
+    assert_eq!(buy.out_amount, 0);
+    // 50_000 quote atoms buys two base lots
+    let buy = jupiter_phoenix
+        .quote_with_free_funds(Side::Bid, 0, 50_000)
+        .unwrap();
+    assert_eq!(buy.out_amount, 2_000_000);
+}
+
+#[test]
+fn test_clear_ladder() {
+    use crate::test_utils::sol_usdc_market;
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let level = |price_in_ticks| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots: 1_000,
+    };
+    let ladder = Ladder {
+        bids: vec![level(19_999)],
+        asks: vec![level(20_001)],
+    };
+    let mut jupiter_phoenix = sol_usdc_market(&ladder, 0);
+    assert!(jupiter_phoenix.quote_side(Side::Ask, 1_000_000_000).is_ok());
+    assert!(jupiter_phoenix.quote_side(Side::Bid, 20_001_000).is_ok());
+
+    jupiter_phoenix.clear_ladder();
+    for side in [Side::Ask, Side::Bid] {
+        let err = jupiter_phoenix.quote_side(side, 1_000_000_000).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<PhoenixAmmError>(),
+            Some(&PhoenixAmmError::EmptyBook)
+        );
+    }
+}
+
+#[test]
+fn test_zero_in_amount() {
+    use crate::test_utils::sol_usdc_market;
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 19_999,
+            size_in_base_lots: 1_000,
+        }],
+        asks: vec![],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 2);
+    let [base_mint, quote_mint] =
+        <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+
+    for (input_mint, output_mint) in [(base_mint, quote_mint), (quote_mint, base_mint)] {
+        let quote_params = QuoteParams {
+            in_amount: 0,
+            input_mint,
+            output_mint,
+        };
+        // Even against the empty ask side, zero input is a zero quote rather than an error
+        let quote = jupiter_phoenix.quote(&quote_params).unwrap();
+        assert_eq!(quote.out_amount, 0);
+        assert!(!quote.not_enough_liquidity);
+        assert_eq!(quote.fee_amount, 0);
+        assert_eq!(quote.fee_pct, Decimal::ZERO);
+        assert_eq!(quote.price_impact_pct, Decimal::ZERO);
+        let quote = jupiter_phoenix.quote_no_fee(&quote_params).unwrap();
+        assert_eq!(quote.out_amount, 0);
+        assert!(!quote.not_enough_liquidity);
+
+        // Every other quote path agrees
+        let (quote, fills) = jupiter_phoenix.quote_with_fills(&quote_params).unwrap();
+        assert_eq!(format!("{:?}", quote), format!("{:?}", Quote::default()));
+        assert!(fills.is_empty());
+        let details = jupiter_phoenix.quote_detailed(&quote_params).unwrap();
+        assert_eq!(
+            (
+                details.out_amount,
+                details.fee_amount,
+                details.in_amount_consumed
+            ),
+            (0, 0, 0)
+        );
+        let quotes = jupiter_phoenix
+            .quote_batch(&[0, 0], input_mint, output_mint)
+            .unwrap();
+        assert!(quotes.iter().all(|quote| quote.out_amount == 0));
+        let side = jupiter_phoenix.side_for_input_mint(&input_mint).unwrap();
+        let slices = jupiter_phoenix.quote_slice(side, 0, 3).unwrap();
+        assert!(slices
+            .iter()
+            .all(|slice| slice.out_amount == 0 && slice.fee_amount == 0));
+    }
+}
+
+#[test]
+fn test_input_to_move_price() {
+    use crate::test_utils::sol_usdc_market;
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let level = |price_in_ticks| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots: 1_000,
+    };
+    // Levels 0, 10, and 50 bps away from the top of book on each side
+    let ladder = Ladder {
+        bids: vec![level(20_000), level(19_980), level(19_900)],
+        asks: vec![level(20_000), level(20_020), level(20_100)],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 0);
+
+    // The top of book is already 0 bps away
+    assert_eq!(jupiter_phoenix.input_to_move_price(Side::Ask, 0), 0);
+    assert_eq!(jupiter_phoenix.input_to_move_price(Side::Bid, 0), 0);
+    // The second level is exactly 10 bps away, so only the first is consumed
+    assert_eq!(
+        jupiter_phoenix.input_to_move_price(Side::Ask, 10),
+        1_000_000_000
+    );
+    assert_eq!(
+        jupiter_phoenix.input_to_move_price(Side::Bid, 10),
+        20_000_000
+    );
+    assert_eq!(
+        jupiter_phoenix.input_to_move_price(Side::Ask, 11),
+        2_000_000_000
+    );
+    assert_eq!(
+        jupiter_phoenix.input_to_move_price(Side::Bid, 11),
+        40_020_000
+    );
+    // Beyond the deepest level, the whole side is consumed
+    assert_eq!(
+        jupiter_phoenix.input_to_move_price(Side::Ask, 10_000),
+        3_000_000_000
+    );
+    assert_eq!(
+        jupiter_phoenix.input_to_move_price(Side::Bid, 10_000),
+        60_120_000
+    );
+
+    // Inputs past a u64 saturate instead of wrapping
+    let ladder = Ladder {
+        bids: vec![
+            LadderOrder {
+                price_in_ticks: 20_000,
+                size_in_base_lots: u64::MAX / 1_000_000,
+            },
+            LadderOrder {
+                price_in_ticks: 20_000,
+                size_in_base_lots: u64::MAX / 1_000_000,
+            },
+        ],
+        asks: vec![LadderOrder {
+            price_in_ticks: 20_000,
+            size_in_base_lots: u64::MAX,
+        }],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 0);
+    for side in [Side::Ask, Side::Bid] {
+        assert_eq!(jupiter_phoenix.input_to_move_price(side, 10_000), u64::MAX);
+    }
+}
+
+#[test]
+fn test_is_base() {
+    use crate::test_utils::sol_usdc_market;
+    use phoenix::state::markets::Ladder;
+
+    let ladder = Ladder {
+        bids: vec![],
+        asks: vec![],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 0);
+    let [base_mint, quote_mint] =
+        <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+
+    assert_eq!(jupiter_phoenix.is_base(&base_mint), Some(true));
+    assert_eq!(jupiter_phoenix.is_base(&quote_mint), Some(false));
+    assert_eq!(jupiter_phoenix.is_base(&Pubkey::new_unique()), None);
+}
+
+#[test]
+fn test_update_failure_policy() {
+    use crate::test_utils::{keyed_account, market_data, market_header};
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 19_999,
+            size_in_base_lots: 1_000,
+        }],
+        asks: vec![],
+    };
+    let header = market_header(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        1_000_000,
+        1,
+        1_000,
+    );
+    let keyed_account = keyed_account(market_data(&header, &ladder, 0));
+    let good_accounts_map = HashMap::from([(
+        keyed_account.key,
+        PartialAccount::from(keyed_account.account.clone()),
+    )]);
+    let mut corrupt_account = keyed_account.account.clone();
+    corrupt_account.data.truncate(size_of::<MarketHeader>() + 1);
+    let corrupt_accounts_map =
+        HashMap::from([(keyed_account.key, PartialAccount::from(corrupt_account))]);
+
+    // By default the last good ladder keeps quoting
+    let mut jupiter_phoenix = JupiterPhoenix::new_from_keyed_account(&keyed_account).unwrap();
+    assert!(jupiter_phoenix.update(&corrupt_accounts_map).is_err());
+    assert!(jupiter_phoenix.update(&corrupt_accounts_map).is_err());
+    assert_eq!(jupiter_phoenix.consecutive_update_failures(), 2);
+    assert!(jupiter_phoenix.quote_side(Side::Ask, 1_000_000_000).is_ok());
+    jupiter_phoenix.update(&good_accounts_map).unwrap();
+    assert_eq!(jupiter_phoenix.consecutive_update_failures(), 0);
+
+    // Clearing the ladder makes quotes fail until the next good update
+    let mut jupiter_phoenix = JupiterPhoenix::new_from_keyed_account(&keyed_account).unwrap();
+    jupiter_phoenix.set_update_failure_policy(UpdateFailurePolicy::ClearLadder);
+    assert!(jupiter_phoenix.update(&corrupt_accounts_map).is_err());
+    assert_eq!(jupiter_phoenix.consecutive_update_failures(), 1);
+    let err = jupiter_phoenix
+        .quote_side(Side::Ask, 1_000_000_000)
+        .unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<PhoenixAmmError>(),
+        Some(&PhoenixAmmError::EmptyBook)
+    );
+    jupiter_phoenix.update(&good_accounts_map).unwrap();
+    assert_eq!(jupiter_phoenix.consecutive_update_failures(), 0);
+    assert!(jupiter_phoenix.quote_side(Side::Ask, 1_000_000_000).is_ok());
+}
+
+#[test]
+fn test_ladder_serialization_round_trip() {
+    use crate::test_utils::sol_usdc_market;
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let ladder = Ladder {
+        bids: vec![
+            LadderOrder {
+                price_in_ticks: 19_999,
+                size_in_base_lots: 1_000,
+            },
+            LadderOrder {
+                price_in_ticks: 19_998,
+                size_in_base_lots: u64::MAX,
+            },
+        ],
+        asks: vec![LadderOrder {
+            price_in_ticks: 20_001,
+            size_in_base_lots: 7,
+        }],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 0);
+    let bytes = jupiter_phoenix.serialize_ladder();
+    assert_eq!(bytes.len(), 8 + 16 * 3);
+    assert_eq!(&bytes[..8], &[2, 0, 0, 0, 1, 0, 0, 0]);
+    assert_eq!(JupiterPhoenix::deserialize_ladder(&bytes).unwrap(), ladder);
+
+    assert!(JupiterPhoenix::deserialize_ladder(&bytes[..7]).is_err());
+    assert!(JupiterPhoenix::deserialize_ladder(&bytes[..bytes.len() - 1]).is_err());
+}
+
+#[test]
+fn test_quote_slice() {
+    use crate::test_utils::sol_usdc_market;
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let level = |price_in_ticks| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots: 1_500,
+    };
+    let ladder = Ladder {
+        bids: vec![level(20_000), level(19_990), level(19_980)],
+        asks: vec![level(20_001), level(20_011), level(20_021)],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 3);
+
+    let side = |is_sell: bool| if is_sell { Side::Ask } else { Side::Bid };
+    for (is_sell, total_in) in [
+        (true, 4_000_000_000),
+        (true, 4_000_500_001),
+        (false, 80_000_000),
+        (false, 80_000_007),
+    ] {
+        let full = jupiter_phoenix.quote_side(side(is_sell), total_in).unwrap();
+        for slices in [1, 3, 7] {
+            let quotes = jupiter_phoenix
+                .quote_slice(side(is_sell), total_in, slices)
+                .unwrap();
+            assert_eq!(quotes.len(), slices as usize);
+            assert!(quotes.iter().map(|q| q.in_amount).sum::<u64>() <= total_in);
+            assert!(quotes.iter().map(|q| q.out_amount).sum::<u64>() <= full.out_amount);
+            // The first slice trades like a swap of just that slice, consumed input and fee
+            // included
+            let first = jupiter_phoenix
+                .quote_side(side(is_sell), total_in / slices as u64)
+                .unwrap();
+            assert_eq!(format!("{:?}", quotes[0]), format!("{:?}", first));
+        }
+    }
+
+    // The second of two 20 USDC buys trades like a swap of just that slice against what the
+    // first left of the 20.001 level, then into the 20.011 one
+    let quotes = jupiter_phoenix
+        .quote_slice(Side::Bid, 40_000_000, 2)
+        .unwrap();
+    let mut rest = ladder.clone();
+    rest.asks[0].size_in_base_lots -= quotes[0].out_amount / 1_000_000;
+    let second = jupiter_phoenix
+        .quote_on_ladder(&rest, &Side::Bid, 20_000_000)
+        .unwrap();
+    assert!(quotes[1].out_amount > rest.asks[0].size_in_base_lots * 1_000_000);
+    assert_eq!(format!("{:?}", quotes[1]), format!("{:?}", second));
+
+    // Without replenishment, each slice fills at a worse price than the one before it
+    let quotes = jupiter_phoenix
+        .quote_slice(Side::Ask, 4_500_000_000, 3)
+        .unwrap();
+    assert!(quotes[0].out_amount > quotes[1].out_amount);
+    assert!(quotes[1].out_amount > quotes[2].out_amount);
+
+    assert!(jupiter_phoenix.quote_slice(Side::Ask, 1, 0).is_err());
+
+    // With 10 atom quote lots, one base lot at 19.99 USDC matches 1999 quote lots. The program
+    // rounds their 3 bps fee of 0.6 lots up to a whole lot, where scaling the output by
+    // (10000 - 3) / 10000 would only take 6 atoms.
+    let header = crate::test_utils::market_header(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        1_000_000,
+        10,
+        1_000,
+    );
+    let one_lot = Ladder {
+        bids: vec![level(19_990)],
+        asks: vec![],
+    };
+    let jupiter_phoenix = JupiterPhoenix::new_from_keyed_account(
+        &crate::test_utils::keyed_account(crate::test_utils::market_data(&header, &one_lot, 3)),
+    )
+    .unwrap();
+    let slice = jupiter_phoenix
+        .quote_slice(Side::Ask, 1_000_000, 1)
+        .unwrap()[0];
+    let quote = jupiter_phoenix.quote_side(Side::Ask, 1_000_000).unwrap();
+    assert_eq!((slice.out_amount, slice.fee_amount), (19_980, 10));
+    assert_eq!(
+        (slice.out_amount, slice.fee_amount),
+        (quote.out_amount, quote.fee_amount)
+    );
+}
+
+#[test]
+fn test_quote_stress_extreme_ladders() {
+    use crate::test_utils::sol_usdc_market;
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let empty = Ladder {
+        bids: vec![],
+        asks: vec![],
+    };
+    let mut jupiter_phoenix = sol_usdc_market(&empty, 10);
+    let [base_mint, quote_mint] =
+        <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+
+    // Deep books at both ends of the price range, with sizes whose products overflow u64
+    let num_levels = 4_000;
+    for (top_bid, top_ask, size_in_base_lots) in [
+        (1, 1, u64::MAX / 2),
+        (u64::MAX / 2, u64::MAX / 2, 1),
+        (
+            u64::MAX / 1_000_000,
+            u64::MAX / 1_000_000,
+            u64::MAX / 1_000_000,
+        ),
+        (20_000, 20_001, u64::MAX / num_levels),
+    ] {
+        jupiter_phoenix.ladder = Arc::new(Ladder {
+            bids: (0..num_levels)
+                .map(|level| LadderOrder {
+                    price_in_ticks: top_bid.saturating_sub(level).max(1),
+                    size_in_base_lots,
+                })
+                .collect(),
+            asks: (0..num_levels)
+                .map(|level| LadderOrder {
+                    price_in_ticks: top_ask.saturating_add(level),
+                    size_in_base_lots,
+                })
+                .collect(),
+        });
+        jupiter_phoenix.cache_cumulative_liquidity();
+
+        for (input_mint, output_mint) in [(base_mint, quote_mint), (quote_mint, base_mint)] {
+            // Once an input is too large for the program to handle, every larger one is too
+            let mut last_out_amount = Some(0);
+            for in_amount in [
+                1,
+                1_000_000,
+                1_000_000_000,
+                u64::MAX / 1_000_000,
+                u64::MAX / 2,
+                u64::MAX,
+            ] {
+                let out_amount = match jupiter_phoenix.quote(&QuoteParams {
+                    in_amount,
+                    input_mint,
+                    output_mint,
+                }) {
+                    Ok(quote) => Some(quote.out_amount),
+                    Err(err) => {
+                        assert_eq!(
+                            err.downcast_ref::<PhoenixAmmError>(),
+                            Some(&PhoenixAmmError::AmountOverflow)
+                        );
+                        None
+                    }
+                };
+                match (last_out_amount, out_amount) {
+                    (Some(last), Some(out)) => assert!(out >= last),
+                    (None, Some(_)) => panic!("Quote recovered from an overflow"),
+                    _ => {}
+                }
+                last_out_amount = out_amount;
+
+                // ExactOut searches the same books without overflowing either
+                match jupiter_phoenix.quote_exact_out(&input_mint, in_amount) {
+                    Ok(quote) => assert!(
+                        quote.not_enough_liquidity || quote.out_amount >= in_amount,
+                        "{in_amount}"
+                    ),
+                    Err(err) => assert_eq!(
+                        err.downcast_ref::<PhoenixAmmError>(),
+                        Some(&PhoenixAmmError::AmountOverflow)
+                    ),
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_swap_instruction_client_order_id() {
+    use crate::test_utils::{sol_usdc_market, swap_params};
+    use borsh::BorshDeserialize;
+    use phoenix::program::PhoenixInstruction;
+    use phoenix::state::markets::Ladder;
+
+    let ladder = Ladder {
+        bids: vec![],
+        asks: vec![],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 0);
+    let [base_mint, quote_mint] =
+        <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+    let mut params = swap_params(base_mint, quote_mint);
+    params.in_amount = 2_500_000_000;
+
+    for (client_order_id, expected_client_order_id) in
+        [(Some(u128::MAX - 7), u128::MAX - 7), (None, 0)]
+    {
+        let instruction = jupiter_phoenix
+            .swap_instruction(&params, client_order_id)
+            .unwrap();
+        assert_eq!(instruction.program_id, phoenix::id());
+        assert_eq!(instruction.data[0], PhoenixInstruction::Swap as u8);
+        let order_packet = OrderPacket::try_from_slice(&instruction.data[1..]).unwrap();
+        assert_eq!(
+            order_packet,
+            OrderPacket::new_ioc(
+                phoenix::state::Side::Ask,
+                None,
+                2_500,
+                0,
+                0,
+                0,
+                SelfTradeBehavior::CancelProvide,
+                None,
+                expected_client_order_id,
+                false,
+                None,
+                None,
+            )
+        );
+    }
+}
+
+#[test]
+fn test_refresh_metadata_after_change() {
+    use crate::test_utils::{keyed_account, market_data, market_header};
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let (base_mint, quote_mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 19_999,
+            size_in_base_lots: 1_000,
+        }],
+        asks: vec![],
+    };
+    let header = market_header(base_mint, quote_mint, 1_000_000, 1, 1_000);
+    let keyed_account = keyed_account(market_data(&header, &ladder, 0));
+    let mut jupiter_phoenix = JupiterPhoenix::new_from_keyed_account(&keyed_account).unwrap();
+
+    // The tick size and taker fee change underneath the cached metadata
+    let header = market_header(base_mint, quote_mint, 1_000_000, 1, 100);
+    let mut account = keyed_account.account;
+    account.data = market_data(&header, &ladder, 5);
+    let accounts_map = HashMap::from([(keyed_account.key, PartialAccount::from(account.clone()))]);
+    let err = jupiter_phoenix.update(&accounts_map).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<PhoenixAmmError>(),
+        Some(&PhoenixAmmError::MetadataChanged)
+    );
+
+    jupiter_phoenix.refresh_metadata(&account).unwrap();
+    assert_eq!(
+        jupiter_phoenix.tick_size_in_quote_atoms_per_base_unit(),
+        100
+    );
+    assert_eq!(jupiter_phoenix.taker_fee_bps, 5);
+    jupiter_phoenix.update(&accounts_map).unwrap();
+    // 1 SOL at 19_999 ticks of 0.0001 USDC, less 5 bps
+    assert_eq!(
+        jupiter_phoenix
+            .quote_side(Side::Ask, 1_000_000_000)
+            .unwrap()
+            .out_amount,
+        1_999_900 * 9_995 / 10_000
+    );
+}
+
+#[cfg(feature = "decimal")]
+#[test]
+fn test_quote_decimal_is_exact() {
+    use crate::test_utils::sol_usdc_market;
+    use phoenix::state::markets::{Ladder, LadderOrder};
+    use rust_decimal::Decimal;
+
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 19_999,
+            size_in_base_lots: 100_000,
+        }],
+        asks: vec![LadderOrder {
+            price_in_ticks: 20_001,
+            size_in_base_lots: 100_000,
+        }],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 0);
+    let [base_mint, quote_mint] =
+        <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+
+    for (input_mint, output_mint, in_amount) in [
+        (base_mint, quote_mint, 1_000_000_000),
+        (base_mint, quote_mint, 3_001_000_000),
+        (quote_mint, base_mint, 20_001_000),
+        (quote_mint, base_mint, 60_003_000),
+    ] {
+        let quote_params = QuoteParams {
+            in_amount,
+            input_mint,
+            output_mint,
+        };
+        let out_amount = jupiter_phoenix.quote(&quote_params).unwrap().out_amount;
+        let quote = jupiter_phoenix.quote_decimal(&quote_params).unwrap();
+
+        // Cross-multiplying avoids any division: price * base atoms * 10^6 == quote atoms * 10^9
+        let (base_atoms, quote_atoms, expected_price) = if input_mint == base_mint {
+            (in_amount, out_amount, Decimal::new(19_999, 3))
+        } else {
+            (out_amount, in_amount, Decimal::new(20_001, 3))
+        };
+        assert_eq!(quote.price, Some(expected_price));
+        assert_eq!(
+            expected_price * Decimal::from(base_atoms) * Decimal::from(1_000_000),
+            Decimal::from(quote_atoms) * Decimal::from(1_000_000_000)
+        );
+    }
+}
+
+#[test]
+fn test_identical_token_accounts_are_rejected() {
+    use crate::test_utils::{sol_usdc_market, swap_params};
+    use phoenix::state::markets::Ladder;
+
+    let ladder = Ladder {
+        bids: vec![],
+        asks: vec![],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 0);
+    let [base_mint, quote_mint] =
+        <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+
+    for (source_mint, destination_mint) in [(base_mint, quote_mint), (quote_mint, base_mint)] {
+        let mut params = swap_params(source_mint, destination_mint);
+        assert!(jupiter_phoenix
+            .get_swap_leg_and_account_metas(&params)
+            .is_ok());
+        params.user_destination_token_account = params.user_source_token_account;
+        assert!(jupiter_phoenix
+            .get_swap_leg_and_account_metas(&params)
+            .is_err());
+        assert!(jupiter_phoenix.swap_instruction(&params, None).is_err());
+    }
+}
+
+#[test]
+fn test_taker_fee_overrides() {
+    use crate::test_utils::{keyed_account, market_data, market_header};
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 20_000,
+            size_in_base_lots: 1_000,
+        }],
+        asks: vec![],
+    };
+    let header = market_header(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        1_000_000,
+        1,
+        1_000,
+    );
+    let keyed_account = keyed_account(market_data(&header, &ladder, 10));
+    let sell = |jupiter_phoenix: &JupiterPhoenix| {
+        jupiter_phoenix
+            .quote_side(Side::Ask, 1_000_000_000)
+            .unwrap()
+            .out_amount
+    };
+
+    // Other markets in the map do not affect this one
+    let fees = HashMap::from([(Pubkey::new_unique(), 0)]);
+    let jupiter_phoenix =
+        JupiterPhoenix::new_from_keyed_account_with_fees(&keyed_account, &fees).unwrap();
+    assert_eq!(sell(&jupiter_phoenix), 19_980_000);
+
+    let fees = HashMap::from([(keyed_account.key, 2)]);
+    let jupiter_phoenix =
+        JupiterPhoenix::new_from_keyed_account_with_fees(&keyed_account, &fees).unwrap();
+    assert_eq!(sell(&jupiter_phoenix), 19_996_000);
+
+    let fees = HashMap::from([(keyed_account.key, 10_001)]);
+    assert!(JupiterPhoenix::new_from_keyed_account_with_fees(&keyed_account, &fees).is_err());
+}
+
+#[test]
+fn test_quote_matches_program_reference_fill() {
+    use crate::test_utils::{keyed_account, market_data, market_header, sol_usdc_market};
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    // Reference fill redone in lots the way the program's `FIFOMarket::match_order` does it,
+    // including its fee rounding, so that quotes can be compared atom for atom
+    fn reference_fill(
+        metadata: &MarketMetadata,
+        levels: &[LadderOrder],
+        taker_fee_bps: u64,
+        sell_base: bool,
+        in_amount: u64,
+    ) -> u64 {
+        let base_lots_per_base_unit = metadata.num_base_lots_per_base_unit as u128;
+        let tick_size_in_quote_lots_per_base_unit =
+            (metadata.tick_size_in_quote_atoms_per_base_unit / metadata.quote_atoms_per_quote_lot)
+                as u128;
+        // Fee on adjusted quote lots, rounded up to whole quote lots
+        let fee_in_quote_lots = |adjusted_quote_lots: u128| {
+            let fee = (adjusted_quote_lots * taker_fee_bps as u128).div_ceil(10_000);
+            fee.div_ceil(base_lots_per_base_unit)
+        };
+        if sell_base {
+            let mut base_lots = (in_amount / metadata.base_atoms_per_base_lot) as u128;
+            let mut adjusted_quote_lots = 0;
+            for level in levels {
+                let filled = base_lots.min(level.size_in_base_lots as u128);
+                adjusted_quote_lots +=
+                    filled * level.price_in_ticks as u128 * tick_size_in_quote_lots_per_base_unit;
+                base_lots -= filled;
+            }
+            let quote_lots = adjusted_quote_lots / base_lots_per_base_unit
+                - fee_in_quote_lots(adjusted_quote_lots);
+            (quote_lots * metadata.quote_atoms_per_quote_lot as u128) as u64
+        } else {
+            let quote_lots = (in_amount / metadata.quote_atoms_per_quote_lot) as u128;
+            let fee_adjustment = (u64::MAX as u128 * taker_fee_bps as u128).div_ceil(10_000);
+            let mut budget = quote_lots * base_lots_per_base_unit * u64::MAX as u128
+                / (u64::MAX as u128 + fee_adjustment);
+            let mut base_lots = 0;
+            for level in levels {
+                let lot_cost = level.price_in_ticks as u128 * tick_size_in_quote_lots_per_base_unit;
+                let filled = (level.size_in_base_lots as u128).min(budget / lot_cost);
+                base_lots += filled;
+                budget -= filled * lot_cost;
+                if filled < level.size_in_base_lots as u128 {
+                    break;
+                }
+            }
+            (base_lots * metadata.base_atoms_per_base_lot as u128) as u64
+        }
+    }
+
+    let level = |price_in_ticks, size_in_base_lots| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots,
+    };
+    // 1-atom quote lots, with 0.001 SOL base lots
+    let ladder = Ladder {
+        bids: vec![level(19_999, 700), level(19_990, 1_300), level(19_950, 50)],
+        asks: vec![level(20_001, 700), level(20_010, 1_300), level(20_050, 50)],
+    };
+    let fine_lots = (sol_usdc_market(&ladder, 3), ladder, 3);
+    // 100-atom quote lots, with 0.01 SOL base lots and 0.01 USDC ticks
+    let ladder = Ladder {
+        bids: vec![level(1_999, 70), level(1_990, 130), level(1_950, 5)],
+        asks: vec![level(2_001, 70), level(2_010, 130), level(2_050, 5)],
+    };
+    let header = market_header(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        10_000_000,
+        100,
+        10_000,
+    );
+    let coarse_lots = (
+        JupiterPhoenix::new_from_keyed_account(&keyed_account(market_data(&header, &ladder, 7)))
+            .unwrap(),
+        ladder,
+        7,
+    );
+    // 10-atom quote lots, with 0.001 SOL base lots and 0.01 USDC ticks, so one tick is 1000
+    // quote lots per SOL, or one quote lot per base lot
+    let ladder = Ladder {
+        bids: vec![level(1_999, 700), level(1_990, 1_300), level(1_950, 50)],
+        asks: vec![level(2_001, 700), level(2_010, 1_300), level(2_050, 50)],
+    };
+    let header = market_header(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        1_000_000,
+        10,
+        10_000,
+    );
+    let ten_atom_lots = (
+        JupiterPhoenix::new_from_keyed_account(&keyed_account(market_data(&header, &ladder, 5)))
+            .unwrap(),
+        ladder,
+        5,
+    );
+
+    for (jupiter_phoenix, ladder, taker_fee_bps) in [fine_lots, coarse_lots, ten_atom_lots] {
+        let [base_mint, quote_mint] =
+            <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+        for (sell_base, in_amount) in [
+            (true, 1),
+            (true, 9_999_999),
+            (true, 699_999_999),
+            (true, 700_000_000),
+            (true, 1_234_567_891),
+            (true, 1_999_999_999),
+            (true, 5_000_000_000),
+            (false, 1),
+            (false, 9),
+            (false, 199),
+            (false, 1_999_995),
+            (false, 14_000_699),
+            (false, 14_016_899),
+            (false, 14_016_900),
+            (false, 27_777_777),
+            (false, 40_123_457),
+            (false, 100_000_000),
+        ] {
+            let (input_mint, output_mint, levels) = if sell_base {
+                (base_mint, quote_mint, &ladder.bids)
+            } else {
+                (quote_mint, base_mint, &ladder.asks)
+            };
+            let quote = jupiter_phoenix
+                .quote(&QuoteParams {
+                    in_amount,
+                    input_mint,
+                    output_mint,
+                })
+                .unwrap();
+            assert_eq!(
+                quote.out_amount,
+                reference_fill(
+                    &jupiter_phoenix,
+                    levels,
+                    taker_fee_bps,
+                    sell_base,
+                    in_amount
+                ),
+                "quote lot: {}, sell_base: {sell_base}, in_amount: {in_amount}",
+                jupiter_phoenix.quote_atoms_per_quote_lot,
+            );
+        }
+    }
+}
+
+#[test]
+fn test_update_from_geyser() {
+    use crate::test_utils::{keyed_account, market_data, market_header};
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let header = market_header(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        1_000_000,
+        1,
+        1_000,
+    );
+    let ladder = |price_in_ticks| Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks,
+            size_in_base_lots: 1_000,
+        }],
+        asks: vec![],
+    };
+    let mut jupiter_phoenix = JupiterPhoenix::new_from_keyed_account(&keyed_account(market_data(
+        &header,
+        &ladder(20_000),
+        0,
+    )))
+    .unwrap();
+    assert_eq!(jupiter_phoenix.last_update_slot(), None);
+    let sell = |jupiter_phoenix: &JupiterPhoenix| {
+        jupiter_phoenix
+            .quote_side(Side::Ask, 1_000_000_000)
+            .unwrap()
+            .out_amount
+    };
+
+    // Each snapshot is a new state of the market, so it advances the sequence number
+    let snapshot = |market_sequence_number, price_in_ticks| {
+        let mut header = header;
+        header.market_sequence_number = market_sequence_number;
+        market_data(&header, &ladder(price_in_ticks), 0)
+    };
+    jupiter_phoenix
+        .update_from_geyser(&snapshot(2, 19_000), 100)
+        .unwrap();
+    assert_eq!(jupiter_phoenix.last_update_slot(), Some(100));
+    assert_eq!(sell(&jupiter_phoenix), 19_000_000);
+
+    // An older slot arriving late does not overwrite the newer book
+    jupiter_phoenix
+        .update_from_geyser(&snapshot(1, 18_000), 99)
+        .unwrap();
+    assert_eq!(jupiter_phoenix.last_update_slot(), Some(100));
+    assert_eq!(sell(&jupiter_phoenix), 19_000_000);
+
+    // A failed update keeps the last good slot
+    assert!(jupiter_phoenix.update_from_geyser(&[0; 16], 101).is_err());
+    assert_eq!(jupiter_phoenix.last_update_slot(), Some(100));
+    assert_eq!(jupiter_phoenix.consecutive_update_failures(), 1);
+}
+
+#[test]
+fn test_better_direction() {
+    use crate::test_utils::sol_usdc_market;
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let level = |price_in_ticks| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots: 1_000,
+    };
+    // 19.999 bid, 20.001 ask
+    let ladder = Ladder {
+        bids: vec![level(19_999)],
+        asks: vec![level(20_001)],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 0);
+
+    assert!(jupiter_phoenix.better_direction(20.0).is_none());
+    assert!(jupiter_phoenix.better_direction(19.999).is_none());
+    assert!(jupiter_phoenix.better_direction(20.001).is_none());
+    assert!(matches!(
+        jupiter_phoenix.better_direction(20.5),
+        Some(Side::Bid)
+    ));
+    assert!(matches!(
+        jupiter_phoenix.better_direction(19.5),
+        Some(Side::Ask)
+    ));
+
+    // With no asks, a high external price has nothing to buy
+    let ladder = Ladder {
+        bids: vec![level(19_999)],
+        asks: vec![],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 0);
+    assert!(jupiter_phoenix.better_direction(20.5).is_none());
+    assert!(matches!(
+        jupiter_phoenix.better_direction(19.5),
+        Some(Side::Ask)
+    ));
+}
+
+#[test]
+fn test_quote_detailed() {
+    use crate::test_utils::sol_usdc_market;
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let level = |price_in_ticks| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots: 1_000,
+    };
+    let ladder = Ladder {
+        bids: vec![level(20_000)],
+        asks: vec![level(20_000)],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 10);
+    let [base_mint, quote_mint] =
+        <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+    let sell = |in_amount| QuoteParams {
+        in_amount,
+        input_mint: base_mint,
+        output_mint: quote_mint,
+    };
+    let buy = |in_amount| QuoteParams {
+        in_amount,
+        input_mint: quote_mint,
+        output_mint: base_mint,
+    };
+
+    // Full fill of half a level, with a dust remainder below one base lot
+    let details = jupiter_phoenix.quote_detailed(&sell(500_000_001)).unwrap();
+    assert_eq!(
+        details,
+        QuoteDetails {
+            in_amount_consumed: 500_000_000,
+            in_amount_in_lots: 500_000_000,
+            lot_remainder: 1,
+            out_amount: 9_990_000,
+            fee_amount: 10_000,
+            in_mint: base_mint,
+            out_mint: quote_mint,
+        }
+    );
+    assert_eq!(
+        details.out_amount,
+        jupiter_phoenix
+            .quote(&sell(500_000_001))
+            .unwrap()
+            .out_amount
+    );
+
+    // Partial fills: the book only holds 1 SOL, or 20 USDC worth of it
+    let details = jupiter_phoenix
+        .quote_detailed(&sell(3_000_000_000))
+        .unwrap();
+    assert_eq!(details.in_amount_consumed, 1_000_000_000);
+    assert_eq!(details.out_amount + details.fee_amount, 20_000_000);
+    // Buys pay the fee in quote on top of what they match
+    let details = jupiter_phoenix.quote_detailed(&buy(50_000_000)).unwrap();
+    assert_eq!(details.in_amount_consumed, 20_020_000);
+    assert_eq!(details.out_amount, 1_000_000_000);
+    assert_eq!(details.fee_amount, 20_000);
+    assert_eq!((details.in_mint, details.out_mint), (quote_mint, base_mint));
+
+    // Inputs that buy a fraction of a base lot leave the remainder unconsumed
+    let details = jupiter_phoenix.quote_detailed(&buy(10_019_999)).unwrap();
+    assert_eq!(details.in_amount_consumed, 10_010_000);
+    assert_eq!(details.out_amount, 500_000_000);
+    assert_eq!(details.fee_amount, 10_000);
+
+    assert!(jupiter_phoenix
+        .quote_detailed(&QuoteParams {
+            in_amount: 1,
+            input_mint: Pubkey::new_unique(),
+            output_mint: quote_mint,
+        })
+        .is_err());
+}
+
+#[test]
+fn test_warm_update_matches_fresh_ladder() {
+    use crate::test_utils::{keyed_account, market_data, market_header};
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let level = |price_in_ticks, size_in_base_lots| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots,
+    };
+    let header = market_header(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        1_000_000,
+        1,
+        1_000,
+    );
+    let deep = Ladder {
+        bids: (0..50).map(|i| level(19_999 - i, 10 + i)).collect(),
+        asks: (0..50).map(|i| level(20_001 + i, 10 + i)).collect(),
+    };
+    let shallow = Ladder {
+        bids: vec![level(19_000, 1)],
+        asks: vec![level(21_000, 2), level(21_001, 3)],
+    };
+    let keyed_account = keyed_account(market_data(&header, &deep, 0));
+    let mut jupiter_phoenix = JupiterPhoenix::new_from_keyed_account(&keyed_account).unwrap();
+
+    // Shrinking and then regrowing the book reuses the same vectors
+    for (market_sequence_number, ladder) in [(1, &shallow), (2, &deep), (3, &shallow)] {
+        let mut header = header;
+        header.market_sequence_number = market_sequence_number;
+        let mut account = keyed_account.account.clone();
+        account.data = market_data(&header, ladder, 0);
+        let fresh = JupiterPhoenix::new_from_keyed_account(&KeyedAccount {
+            key: keyed_account.key,
+            account: account.clone(),
+            params: None,
+        })
+        .unwrap();
+        let accounts_map = HashMap::from([(keyed_account.key, PartialAccount::from(account))]);
+        jupiter_phoenix.update(&accounts_map).unwrap();
+        assert_eq!(jupiter_phoenix.ladder, fresh.ladder);
+        assert_eq!(&*jupiter_phoenix.ladder, ladder);
+    }
+}
+
+#[test]
+fn test_zero_lot_size_is_rejected() {
+    use crate::test_utils::{keyed_account, market_data, market_header};
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 20_000,
+            size_in_base_lots: 1_000,
+        }],
+        asks: vec![],
+    };
+    for (base_atoms_per_base_lot, quote_atoms_per_quote_lot) in [(0, 1), (1_000_000, 0), (0, 0)] {
+        let header = market_header(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            base_atoms_per_base_lot,
+            quote_atoms_per_quote_lot,
+            1_000,
+        );
+        let keyed_account = keyed_account(market_data(&header, &ladder, 0));
+        let err = JupiterPhoenix::new_from_keyed_account(&keyed_account).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<PhoenixAmmError>(),
+            Some(&PhoenixAmmError::ZeroLotSize)
+        );
+    }
+}
+
+#[test]
+fn test_quote_curve_csv() {
+    use crate::test_utils::sol_usdc_market;
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let level = |price_in_ticks| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots: 1_000,
+    };
+    let ladder = Ladder {
+        bids: vec![level(20_000), level(19_990)],
+        asks: vec![],
+    };
+    let mut jupiter_phoenix = sol_usdc_market(&ladder, 0);
+
+    // 1 SOL steps into 1 SOL levels at 20.000 and 19.990, stopping once the book runs out
+    let csv = jupiter_phoenix.quote_curve_csv(Side::Ask, 1_000_000_000, 5);
+    assert_eq!(
+        csv.lines().collect::<Vec<_>>(),
+        vec![
+            "in_human,out_human,avg_price,marginal_price,impact_bps",
+            "1.000000000,20.000000,20.000000,20.000000,0.00",
+            "2.000000000,39.990000,19.995000,19.990000,2.50",
+            "3.000000000,39.990000,19.995000,19.990000,2.50",
+        ]
+    );
+
+    assert_eq!(
+        jupiter_phoenix.quote_curve_csv(Side::Bid, 1_000_000, 5),
+        "in_human,out_human,avg_price,marginal_price,impact_bps\n"
+    );
+    // The marginal price is the last level filled, not counting levels the walk skips. The
+    // market account cannot hold zero-priced orders, so one is injected directly.
+    Arc::make_mut(&mut jupiter_phoenix.ladder)
+        .bids
+        .insert(1, level(0));
+    jupiter_phoenix.cache_cumulative_liquidity();
+    assert_eq!(
+        jupiter_phoenix.quote_curve_csv(Side::Ask, 1_000_000_000, 5),
+        csv
+    );
+
+    // 0.01 USDC steps cannot buy a single 0.001 SOL lot at 20.001 until the third
+    Arc::make_mut(&mut jupiter_phoenix.ladder).asks = vec![level(20_001)];
+    jupiter_phoenix.cache_cumulative_liquidity();
+    assert_eq!(
+        jupiter_phoenix
+            .quote_curve_csv(Side::Bid, 10_000, 3)
+            .lines()
+            .collect::<Vec<_>>(),
+        vec![
+            "in_human,out_human,avg_price,marginal_price,impact_bps",
+            "0.030000,0.001000000,20.001000,20.001000,0.00",
+        ]
+    );
+
+    // Every row of a curve across several levels, with a fee, outputs what `quote` does
+    let mut jupiter_phoenix = sol_usdc_market(
+        &Ladder {
+            bids: vec![],
+            asks: vec![level(20_001), level(20_011), level(20_021)],
+        },
+        3,
+    );
+    jupiter_phoenix.cache_cumulative_liquidity();
+    let csv = jupiter_phoenix.quote_curve_csv(Side::Bid, 7_000_000, 8);
+    let rows = csv.lines().skip(1).collect::<Vec<_>>();
+    assert_eq!(rows.len(), 8);
+    for (row, line) in (1..).zip(rows) {
+        let quote = jupiter_phoenix
+            .quote_side(Side::Bid, row * 7_000_000)
+            .unwrap();
+        let out_human = line.split(',').nth(1).unwrap();
+        assert_eq!(
+            out_human,
+            format!("{:.9}", atoms_to_ui(quote.out_amount, 9)),
+            "row {row}"
+        );
+    }
+}
+
+#[test]
+fn test_validate_swap() {
+    use crate::test_utils::{sol_usdc_market, swap_params};
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let level = |price_in_ticks| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots: 1_000,
+    };
+    let ladder = Ladder {
+        bids: vec![level(19_999)],
+        asks: vec![level(20_001)],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 0);
+    let [base_mint, quote_mint] =
+        <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+    let quote_and_params = |source_mint, destination_mint, in_amount| {
+        let quote = jupiter_phoenix
+            .quote(&QuoteParams {
+                in_amount,
+                input_mint: source_mint,
+                output_mint: destination_mint,
+            })
+            .unwrap();
+        let mut params = swap_params(source_mint, destination_mint);
+        params.in_amount = in_amount;
+        (quote, params)
+    };
+
+    // Matching quotes and swaps in both directions
+    let (quote, params) = quote_and_params(base_mint, quote_mint, 1_000_000_000);
+    jupiter_phoenix.validate_swap(&quote, &params).unwrap();
+    let (quote, params) = quote_and_params(quote_mint, base_mint, 20_001_000);
+    jupiter_phoenix.validate_swap(&quote, &params).unwrap();
+    // Quotes report the input that trades, which leaves out dust below one lot
+    let (quote, params) = quote_and_params(base_mint, quote_mint, 999_999_999);
+    assert_eq!(quote.in_amount, 999_000_000);
+    jupiter_phoenix.validate_swap(&quote, &params).unwrap();
+
+    // Destination mints that are not traded on this market
+    let (quote, mut params) = quote_and_params(base_mint, quote_mint, 1_000_000_000);
+    params.destination_mint = Pubkey::new_unique();
+    let err = jupiter_phoenix.validate_swap(&quote, &params).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<PhoenixAmmError>(),
+        Some(&PhoenixAmmError::InvalidQuoteMint)
+    );
+    let (quote, mut params) = quote_and_params(quote_mint, base_mint, 20_001_000);
+    params.destination_mint = Pubkey::new_unique();
+    let err = jupiter_phoenix.validate_swap(&quote, &params).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<PhoenixAmmError>(),
+        Some(&PhoenixAmmError::InvalidBaseMint)
+    );
+
+    // Unset accounts
+    let (quote, mut params) = quote_and_params(base_mint, quote_mint, 1_000_000_000);
+    params.user_transfer_authority = Pubkey::default();
+    assert!(jupiter_phoenix.validate_swap(&quote, &params).is_err());
+
+    // A swap that differs from the quoted input
+    let (mut quote, mut params) = quote_and_params(base_mint, quote_mint, 1_000_000_000);
+    quote.in_amount = 1_000_000_000;
+    params.in_amount = 999_000_000;
+    assert!(jupiter_phoenix.validate_swap(&quote, &params).is_err());
+
+    // A swap deeper than the book
+    let (quote, params) = quote_and_params(base_mint, quote_mint, 2_000_000_000);
+    let err = jupiter_phoenix.validate_swap(&quote, &params).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<PhoenixAmmError>(),
+        Some(&PhoenixAmmError::NotEnoughLiquidity)
+    );
+}
+
+#[test]
+fn test_sell_fee_matches_program_rounding() {
+    use crate::test_utils::{keyed_account, market_data, market_header};
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    // 10 atom quote lots so truncation to whole lots is visible in the fee
+    let header = market_header(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        1_000_000,
+        10,
+        1_000,
+    );
+    let ladder = Ladder {
+        bids: vec![
+            LadderOrder {
+                price_in_ticks: 20_003,
+                size_in_base_lots: 7,
+            },
+            LadderOrder {
+                price_in_ticks: 19_991,
+                size_in_base_lots: 1_000,
+            },
+        ],
+        asks: vec![],
+    };
+    let taker_fee_bps = 3;
+    let jupiter_phoenix = JupiterPhoenix::new_from_keyed_account(&keyed_account(market_data(
+        &header,
+        &ladder,
+        taker_fee_bps,
+    )))
+    .unwrap();
+    let [base_mint, quote_mint] =
+        <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+
+    let base_lots_per_base_unit = 1_000;
+    let tick_size_in_quote_lots_per_base_unit = 100;
+    for base_lots in [1, 3, 7, 8, 333, 1_007] {
+        // Mirrors `FIFOMarket::match_order` and `compute_fee` for a sell of `base_lots`
+        let adjusted_quote_lots: u64 = ladder
+            .bids
+            .iter()
+            .scan(base_lots, |remaining, order| {
+                let filled = order.size_in_base_lots.min(*remaining);
+                *remaining -= filled;
+                Some(filled * order.price_in_ticks * tick_size_in_quote_lots_per_base_unit)
+            })
+            .sum();
+        let fee_in_adjusted_quote_lots = (adjusted_quote_lots * taker_fee_bps).div_ceil(10_000);
+        let fee_in_quote_lots = fee_in_adjusted_quote_lots.div_ceil(base_lots_per_base_unit);
+        let received_quote_lots = adjusted_quote_lots / base_lots_per_base_unit - fee_in_quote_lots;
+
+        let details = jupiter_phoenix
+            .quote_detailed(&QuoteParams {
+                in_amount: base_lots * 1_000_000,
+                input_mint: base_mint,
+                output_mint: quote_mint,
+            })
+            .unwrap();
+        assert_eq!(details.fee_amount, fee_in_quote_lots * 10, "{base_lots}");
+        assert_eq!(details.out_amount, received_quote_lots * 10, "{base_lots}");
+    }
+}
+
+#[test]
+fn test_try_from_keyed_account() {
+    use crate::test_utils::{keyed_account, market_data, market_header};
+    use phoenix::state::markets::Ladder;
+
+    let ladder = Ladder {
+        bids: vec![],
+        asks: vec![],
+    };
+    let header = market_header(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        1_000_000,
+        1,
+        1_000,
+    );
+    let keyed_account = keyed_account(market_data(&header, &ladder, 0));
+    let jupiter_phoenix = JupiterPhoenix::try_from(&keyed_account).unwrap();
+    assert_eq!(jupiter_phoenix.key(), keyed_account.key);
+    assert_eq!(
+        jupiter_phoenix.get_reserve_mints(),
+        JupiterPhoenix::new_from_keyed_account(&keyed_account)
+            .unwrap()
+            .get_reserve_mints()
+    );
+
+    let mint = Pubkey::new_unique();
+    let header = market_header(mint, mint, 1_000_000, 1, 1_000);
+    let err = JupiterPhoenix::try_from(&crate::test_utils::keyed_account(market_data(
+        &header, &ladder, 0,
+    )))
+    .unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<PhoenixAmmError>(),
+        Some(&PhoenixAmmError::DegenerateMarket)
+    );
+}
+
+#[test]
+fn test_rounding_modes() {
+    use crate::test_utils::{keyed_account, market_data, market_header};
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    // 1000 atom base lots, so the lot conversion does not divide evenly
+    let header = market_header(Pubkey::new_unique(), Pubkey::new_unique(), 1_000, 1, 1_000);
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 20_200,
+            size_in_base_lots: 1_000,
+        }],
+        asks: vec![LadderOrder {
+            price_in_ticks: 20_000,
+            size_in_base_lots: 1_000,
+        }],
+    };
+    let mut jupiter_phoenix =
+        JupiterPhoenix::new_from_keyed_account(&keyed_account(market_data(&header, &ladder, 3)))
+            .unwrap();
+    let [base_mint, quote_mint] =
+        <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+
+    // 3 lots at 20.2 are worth 60.6 quote atoms, less a fee of 1 atom
+    let sell = QuoteParams {
+        in_amount: 3_000,
+        input_mint: base_mint,
+        output_mint: quote_mint,
+    };
+    // 21 quote atoms buy 1 lot of 1000 base atoms and pay the fee in quote, so there is
+    // nothing to round
+    let buy = QuoteParams {
+        in_amount: 21,
+        input_mint: quote_mint,
+        output_mint: base_mint,
+    };
+    for (rounding_mode, sell_out_amount, buy_out_amount) in [
+        (RoundingMode::Floor, 59, 1_000),
+        (RoundingMode::Ceil, 60, 1_000),
+        (RoundingMode::Nearest, 60, 1_000),
+    ] {
+        jupiter_phoenix.set_rounding_mode(rounding_mode);
+        assert_eq!(
+            jupiter_phoenix.quote(&sell).unwrap().out_amount,
+            sell_out_amount,
+            "{rounding_mode:?}"
+        );
+        assert_eq!(
+            jupiter_phoenix.quote(&buy).unwrap().out_amount,
+            buy_out_amount,
+            "{rounding_mode:?}"
+        );
+    }
+}
+
+#[test]
+fn test_input_rounding_modes() {
+    use crate::test_utils::{keyed_account, market_data, market_header};
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    // 1000 atom base lots and 10 atom quote lots, where a base lot costs 20 quote atoms
+    let header = market_header(Pubkey::new_unique(), Pubkey::new_unique(), 1_000, 10, 1_000);
+    let level = LadderOrder {
+        price_in_ticks: 20_000,
+        size_in_base_lots: 1_000,
+    };
+    let ladder = Ladder {
+        bids: vec![level],
+        asks: vec![level],
+    };
+    let mut jupiter_phoenix =
+        JupiterPhoenix::new_from_keyed_account(&keyed_account(market_data(&header, &ladder, 0)))
+            .unwrap();
+    let (base_mint, quote_mint) = (jupiter_phoenix.base_mint, jupiter_phoenix.quote_mint);
+
+    // (mode, sells of 2.4, 2.5 and 2.6 base lots, buys of 5.3 and 5.8 quote lots), each
+    // quoted at the number of base lots the rounded budget fills
+    for (input_rounding_mode, sell_lots, buy_lots) in [
+        (RoundingMode::Floor, [2, 2, 2], [2, 2]),
+        (RoundingMode::Ceil, [3, 3, 3], [3, 3]),
+        (RoundingMode::Nearest, [2, 3, 3], [2, 3]),
+    ] {
+        jupiter_phoenix.set_input_rounding_mode(input_rounding_mode);
+        for (input_mint, output_mint, in_amounts, filled_lots, out_per_lot) in [
+            (
+                base_mint,
+                quote_mint,
+                &[2_400, 2_500, 2_600][..],
+                &sell_lots[..],
+                20,
+            ),
+            (quote_mint, base_mint, &[53, 58][..], &buy_lots[..], 1_000),
+        ] {
+            let quotes = jupiter_phoenix
+                .quote_batch(in_amounts, input_mint, output_mint)
+                .unwrap();
+            for ((in_amount, filled_lots), batch_quote) in
+                in_amounts.iter().zip(filled_lots).zip(quotes)
+            {
+                let quote = jupiter_phoenix
+                    .quote(&QuoteParams {
+                        in_amount: *in_amount,
+                        input_mint,
+                        output_mint,
+                    })
+                    .unwrap();
+                assert_eq!(
+                    quote.out_amount,
+                    filled_lots * out_per_lot,
+                    "{input_rounding_mode:?} {in_amount}"
+                );
+                assert_eq!(format!("{batch_quote:?}"), format!("{quote:?}"));
+            }
+        }
+        // Whole lots are never rounded
+        assert_eq!(
+            jupiter_phoenix
+                .quote(&QuoteParams {
+                    in_amount: 2_000,
+                    input_mint: base_mint,
+                    output_mint: quote_mint,
+                })
+                .unwrap()
+                .out_amount,
+            40
+        );
+    }
+}
+
+#[test]
+fn test_quote_with_price_shift() {
+    use crate::test_utils::sol_usdc_market;
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let level = |price_in_ticks| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots: 1_000,
+    };
+    let ladder = Ladder {
+        bids: vec![level(20_000), level(19_990)],
+        asks: vec![level(20_010), level(20_020)],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 0);
+    let [base_mint, quote_mint] =
+        <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+    let sell = QuoteParams {
+        in_amount: 1_500_000_000,
+        input_mint: base_mint,
+        output_mint: quote_mint,
+    };
+    let buy = QuoteParams {
+        in_amount: 30_000_000,
+        input_mint: quote_mint,
+        output_mint: base_mint,
+    };
+
+    let sell_out = jupiter_phoenix.quote(&sell).unwrap().out_amount;
+    assert_eq!(
+        jupiter_phoenix
+            .quote_with_price_shift(&sell, 0)
+            .unwrap()
+            .out_amount,
+        sell_out
+    );
+    // 1.5 SOL at 10 ticks (0.01 USDC) worse
+    assert_eq!(
+        jupiter_phoenix
+            .quote_with_price_shift(&sell, 10)
+            .unwrap()
+            .out_amount,
+        sell_out - 15_000
+    );
+    assert!(
+        jupiter_phoenix
+            .quote_with_price_shift(&sell, -10)
+            .unwrap()
+            .out_amount
+            > sell_out
+    );
+    assert!(
+        jupiter_phoenix
+            .quote_with_price_shift(&buy, 10)
+            .unwrap()
+            .out_amount
+            < jupiter_phoenix.quote(&buy).unwrap().out_amount
+    );
+
+    // Every bid is shifted to zero and dropped
+    assert_eq!(
+        jupiter_phoenix
+            .quote_with_price_shift(&sell, 20_000)
+            .unwrap_err()
+            .downcast_ref::<PhoenixAmmError>(),
+        Some(&PhoenixAmmError::EmptyBook)
+    );
+    // The cached ladder is untouched
+    assert_eq!(jupiter_phoenix.quote(&sell).unwrap().out_amount, sell_out);
+}
+
+#[test]
+fn test_is_likely_stable_pair() {
+    use crate::test_utils::{keyed_account, market_data, market_header, sol_usdc_market};
+    use phoenix::state::markets::Ladder;
+
+    let ladder = Ladder {
+        bids: vec![],
+        asks: vec![],
+    };
+    let usdt_mint = Pubkey::new_unique();
+    let usdc_mint = Pubkey::new_unique();
+    let stable_mints = HashSet::from([usdt_mint, usdc_mint]);
+
+    let header = market_header(usdt_mint, usdc_mint, 1_000_000, 1, 1_000);
+    let stable_market =
+        JupiterPhoenix::new_from_keyed_account(&keyed_account(market_data(&header, &ladder, 0)))
+            .unwrap();
+    assert!(stable_market.is_likely_stable_pair(&stable_mints));
+    assert!(!stable_market.is_likely_stable_pair(&HashSet::from([usdc_mint])));
+
+    let header = market_header(Pubkey::new_unique(), usdc_mint, 1_000_000, 1, 1_000);
+    let volatile_market =
+        JupiterPhoenix::new_from_keyed_account(&keyed_account(market_data(&header, &ladder, 0)))
+            .unwrap();
+    assert!(!volatile_market.is_likely_stable_pair(&stable_mints));
+    assert!(!sol_usdc_market(&ladder, 0).is_likely_stable_pair(&HashSet::new()));
+}
+
+#[test]
+fn test_market_not_swappable() {
+    use crate::test_utils::{keyed_account, market_data, market_header, swap_params};
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 20_000,
+            size_in_base_lots: 1_000,
+        }],
+        asks: vec![],
+    };
+    let mut header = market_header(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        1_000_000,
+        1,
+        1_000,
+    );
+    let sell = QuoteParams {
+        in_amount: 1_000_000_000,
+        input_mint: header.base_params.mint_key,
+        output_mint: header.quote_params.mint_key,
+    };
+    let params = swap_params(header.base_params.mint_key, header.quote_params.mint_key);
+
+    for status in [
+        MarketStatus::PostOnly,
+        MarketStatus::Paused,
+        MarketStatus::Closed,
+        MarketStatus::Tombstoned,
+    ] {
+        header.status = status as u64;
+        let jupiter_phoenix = JupiterPhoenix::new_from_keyed_account(&keyed_account(market_data(
+            &header, &ladder, 0,
+        )))
+        .unwrap();
+        assert!(!jupiter_phoenix.is_swappable(), "{status}");
+        assert_eq!(
+            jupiter_phoenix
+                .quote(&sell)
+                .unwrap_err()
+                .downcast_ref::<PhoenixAmmError>(),
+            Some(&PhoenixAmmError::MarketNotSwappable)
+        );
+        assert_eq!(
+            jupiter_phoenix
+                .get_swap_leg_and_account_metas(&params)
+                .err()
+                .unwrap()
+                .downcast_ref::<PhoenixAmmError>(),
+            Some(&PhoenixAmmError::MarketNotSwappable)
+        );
+        // Nor do the other quoting APIs fill against the book
+        let not_swappable = |err: Error| {
+            assert_eq!(
+                err.downcast_ref::<PhoenixAmmError>(),
+                Some(&PhoenixAmmError::MarketNotSwappable),
+                "{status}"
+            )
+        };
+        not_swappable(
+            jupiter_phoenix
+                .quote_with_price_shift(&sell, 1)
+                .unwrap_err(),
+        );
+        not_swappable(jupiter_phoenix.quote_delta(&ladder, &sell).unwrap_err());
+        not_swappable(jupiter_phoenix.quote_no_fee(&sell).unwrap_err());
+        not_swappable(jupiter_phoenix.quote_detailed(&sell).unwrap_err());
+        not_swappable(
+            jupiter_phoenix
+                .quote_slice(Side::Ask, sell.in_amount, 2)
+                .unwrap_err(),
+        );
+        not_swappable(jupiter_phoenix.vwap(&sell).unwrap_err());
+        not_swappable(jupiter_phoenix.top_of_book_after(&sell).unwrap_err());
+    }
+
+    // A market closing mid-session stops quoting on the next update
+    header.status = MarketStatus::Active as u64;
+    let keyed_account = keyed_account(market_data(&header, &ladder, 0));
+    let mut jupiter_phoenix = JupiterPhoenix::new_from_keyed_account(&keyed_account).unwrap();
+    assert!(jupiter_phoenix.is_swappable());
+    assert!(jupiter_phoenix.quote(&sell).is_ok());
+    header.status = MarketStatus::Closed as u64;
+    let accounts_map = HashMap::from([(
+        keyed_account.key,
+        PartialAccount::from(Account {
+            data: market_data(&header, &ladder, 0),
+            ..keyed_account.account.clone()
+        }),
+    )]);
+    jupiter_phoenix.update(&accounts_map).unwrap();
+    assert!(!jupiter_phoenix.is_swappable());
+}
+
+#[test]
+fn test_quote_delta() {
+    use crate::test_utils::sol_usdc_market;
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let level = |price_in_ticks, size_in_base_lots| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots,
+    };
+    let ladder = Ladder {
+        bids: vec![level(20_000, 1_000), level(19_990, 1_000)],
+        asks: vec![level(20_010, 1_000)],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 0);
+    let [base_mint, quote_mint] =
+        <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+    let sell = QuoteParams {
+        in_amount: 1_500_000_000,
+        input_mint: base_mint,
+        output_mint: quote_mint,
+    };
+
+    assert_eq!(jupiter_phoenix.quote_delta(&ladder, &sell).unwrap(), 0);
+
+    // The current book lost half of its best bid, so half a SOL more fills 0.01 USDC lower
+    let deeper_ladder = Ladder {
+        bids: vec![level(20_000, 1_500), level(19_990, 1_000)],
+        asks: ladder.asks.clone(),
+    };
+    assert_eq!(
+        jupiter_phoenix.quote_delta(&deeper_ladder, &sell).unwrap(),
+        -5_000
+    );
+}
+
+#[test]
+fn test_quote_exact_out() {
+    use crate::test_utils::sol_usdc_market;
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let level = |price_in_ticks| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots: 1_000,
+    };
+    let ladder = Ladder {
+        bids: vec![level(20_000), level(19_990), level(19_950)],
+        asks: vec![level(20_010), level(20_020), level(20_060)],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 10);
+    assert!(jupiter_phoenix.supports_exact_out());
+    let [base_mint, quote_mint] =
+        <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+
+    // The last amount in each list is all the side can produce, for the input after it
+    for (input_mint, output_mint, in_atoms_per_lot, out_amounts, side_in_amount) in [
+        (
+            base_mint,
+            quote_mint,
+            1_000_000,
+            [1, 19_980, 10_000_000, 35_123_457, 59_880_060],
+            3_000_000_000,
+        ),
+        (
+            quote_mint,
+            base_mint,
+            1,
+            [1, 1_000_000, 1_000_000_000, 1_700_000_000, 3_000_000_000],
+            // 60.09 USDC of asks plus the 10 bps fee
+            60_150_090,
+        ),
+    ] {
+        let quote_in = |in_amount| {
+            jupiter_phoenix
+                .quote(&QuoteParams {
+                    in_amount,
+                    input_mint,
+                    output_mint,
+                })
+                .unwrap()
+        };
+        for out_amount in out_amounts {
+            let quote = jupiter_phoenix
+                .quote_exact_out(&input_mint, out_amount)
+                .unwrap();
+            assert!(!quote.not_enough_liquidity);
+            assert!(quote.out_amount >= out_amount, "{out_amount}");
+            // in -> out -> in round trips: the quoted input is exactly enough
+            assert_eq!(quote_in(quote.in_amount).out_amount, quote.out_amount);
+            assert!(
+                quote_in(quote.in_amount - in_atoms_per_lot).out_amount < out_amount,
+                "{out_amount}"
+            );
+        }
+
+        // More than the whole side can produce
+        let max_out_amount = out_amounts[out_amounts.len() - 1];
+        let quote = jupiter_phoenix
+            .quote_exact_out(&input_mint, max_out_amount + 1_000_000)
+            .unwrap();
+        assert!(quote.not_enough_liquidity);
+        assert_eq!(quote.out_amount, max_out_amount);
+        // The input is what the book takes, not the search's padded upper bound
+        assert_eq!(quote.in_amount, side_in_amount);
+    }
+
+    assert_eq!(
+        jupiter_phoenix
+            .quote_exact_out(&base_mint, 0)
+            .unwrap()
+            .in_amount,
+        0
+    );
+
+    // Mints outside the market are rejected rather than quoted as the quote mint
+    let other_mint = Pubkey::new_unique();
+    for out_amount in [0, 1_000_000] {
+        assert_eq!(
+            jupiter_phoenix
+                .quote_exact_out(&other_mint, out_amount)
+                .unwrap_err()
+                .downcast_ref::<PhoenixAmmError>(),
+            Some(&PhoenixAmmError::MintNotInMarket)
+        );
+    }
+}
+
+#[test]
+fn test_multi_level_buy_with_quote_lots() {
+    use crate::test_utils::{keyed_account, market_data, market_header};
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    // 0.001 SOL base lots, 0.01 USDC quote lots, 0.01 USDC ticks
+    let header = market_header(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        1_000_000,
+        10_000,
+        10_000,
+    );
+    let level = |price_in_ticks, size_in_base_lots| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots,
+    };
+    let ladder = Ladder {
+        bids: vec![],
+        // 1 SOL at 20 USDC, 2 SOL at 20.5 USDC and 1 SOL at 21 USDC
+        asks: vec![
+            level(2_000, 1_000),
+            level(2_050, 2_000),
+            level(2_100, 1_000),
+        ],
+    };
+    let jupiter_phoenix =
+        JupiterPhoenix::new_from_keyed_account(&keyed_account(market_data(&header, &ladder, 0)))
+            .unwrap();
+    let buy = |in_amount| {
+        jupiter_phoenix
+            .quote(&QuoteParams {
+                in_amount,
+                input_mint: header.quote_params.mint_key,
+                output_mint: header.base_params.mint_key,
+            })
+            .unwrap()
+    };
+
+    // Within the first level
+    let quote = buy(10_000_000);
+    assert_eq!(quote.out_amount, 500_000_000);
+    assert!(!quote.not_enough_liquidity);
+    // 20 USDC for the first level, then 30.75 USDC buys 1.5 SOL at 20.5
+    assert_eq!(buy(50_750_000).out_amount, 2_500_000_000);
+    // 20 + 41 USDC for the first two levels, then 10.5 USDC buys 0.5 SOL at 21, ignoring the
+    // dust below one quote lot
+    assert_eq!(buy(71_509_999).out_amount, 3_500_000_000);
+    // The whole side costs 82 USDC
+    let quote = buy(100_000_000);
+    assert_eq!(quote.out_amount, 4_000_000_000);
+    assert!(quote.not_enough_liquidity);
+    assert!(!buy(82_000_000).not_enough_liquidity);
+}
+
+#[test]
+fn test_quote_fee_fields() {
+    use crate::test_utils::sol_usdc_market;
+
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 19_999,
+            size_in_base_lots: 1_000,
+        }],
+        asks: vec![LadderOrder {
+            price_in_ticks: 20_001,
+            size_in_base_lots: 1_000,
+        }],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 3);
+    let [base_mint, quote_mint] =
+        <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+
+    for in_amount in [123_000_000, 1_000_000] {
+        let quote_params = QuoteParams {
+            in_amount,
+            input_mint: base_mint,
+            output_mint: quote_mint,
+        };
+        let quote = jupiter_phoenix.quote(&quote_params).unwrap();
+        let gross = jupiter_phoenix
+            .quote_no_fee(&quote_params)
+            .unwrap()
+            .out_amount;
+        assert!(quote.fee_amount > 0);
+        assert_eq!(quote.fee_mint, quote_mint);
+        // The proceeds of a sell truncate to whole quote lots, which can only lose dust
+        assert!(quote.out_amount + quote.fee_amount <= gross);
+        assert!(quote.out_amount + quote.fee_amount + 1 >= gross);
+        assert_eq!(
+            quote.fee_pct,
+            Decimal::from(quote.fee_amount) / Decimal::from(quote.out_amount + quote.fee_amount)
+        );
+    }
+
+    // Buys pay the fee in quote too, on top of the quote they match
+    let quote_params = QuoteParams {
+        in_amount: 7_654_321,
+        input_mint: quote_mint,
+        output_mint: base_mint,
+    };
+    let quote = jupiter_phoenix.quote(&quote_params).unwrap();
+    let details = jupiter_phoenix.quote_detailed(&quote_params).unwrap();
+    assert_eq!(quote.fee_mint, quote_mint);
+    assert_eq!(quote.fee_amount, details.fee_amount);
+    assert!(quote.fee_amount > 0);
+    assert_eq!(
+        quote.fee_pct,
+        Decimal::from(quote.fee_amount)
+            / Decimal::from(details.in_amount_consumed - quote.fee_amount)
+    );
+
+    // The fee on a 0.001 SOL sell rounds 5.9997 atoms up to 6, slightly above 3 bps
+    let quote = jupiter_phoenix.quote_side(Side::Ask, 1_000_000).unwrap();
+    assert_eq!(quote.fee_amount, 6);
+    assert_eq!(quote.out_amount, 19_993);
+    assert!(quote.fee_pct > Decimal::new(3, 4));
+}
+
+#[test]
+fn test_buy_fee_is_charged_in_quote() {
+    use crate::test_utils::{keyed_account, market_data, market_header};
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    // 10 atom quote lots, so the tick size is 100 quote lots per base unit
+    let header = market_header(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        1_000_000,
+        10,
+        1_000,
+    );
+    let level = |price_in_ticks, size_in_base_lots| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots,
+    };
+    let ladder = Ladder {
+        bids: vec![level(19_990, 1_000)],
+        asks: vec![level(20_003, 7), level(20_010, 1_000), level(20_500, 500)],
+    };
+    let taker_fee_bps = 3;
+    let jupiter_phoenix = JupiterPhoenix::new_from_keyed_account(&keyed_account(market_data(
+        &header,
+        &ladder,
+        taker_fee_bps,
+    )))
+    .unwrap();
+    let [base_mint, quote_mint] =
+        <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+
+    let base_lots_per_base_unit: u128 = 1_000;
+    let tick_size_in_quote_lots_per_base_unit: u128 = 100;
+    for in_amount in [100, 140_030, 140_049, 1_000_000, 20_154_321, 40_000_000] {
+        // Mirrors `FIFOMarket::match_order` and `compute_fee` for a buy with a quote budget
+        let fee = |adjusted_quote_lots: u128| (adjusted_quote_lots * 3).div_ceil(10_000);
+        let mut budget = (in_amount / 10) as u128 * base_lots_per_base_unit * u64::MAX as u128
+            / (u64::MAX as u128 + fee(u64::MAX as u128));
+        let mut base_lots = 0;
+        let mut adjusted_quote_lots = 0;
+        for order in ladder.asks.iter() {
+            let lot_cost = order.price_in_ticks as u128 * tick_size_in_quote_lots_per_base_unit;
+            let level_cost = lot_cost * order.size_in_base_lots as u128;
+            let filled = if level_cost <= budget {
+                order.size_in_base_lots as u128
+            } else {
+                budget / lot_cost
+            };
+            base_lots += filled;
+            adjusted_quote_lots += filled * lot_cost;
+            if filled < order.size_in_base_lots as u128 {
+                break;
+            }
+            budget -= level_cost;
+        }
+        let round_up = |adjusted_quote_lots: u128| {
+            adjusted_quote_lots.div_ceil(base_lots_per_base_unit) as u64
+        };
+        let fee_in_quote_lots = round_up(fee(adjusted_quote_lots));
+        let spent_quote_lots = round_up(adjusted_quote_lots) + fee_in_quote_lots;
+
+        let quote_params = QuoteParams {
+            in_amount,
+            input_mint: quote_mint,
+            output_mint: base_mint,
+        };
+        let details = jupiter_phoenix.quote_detailed(&quote_params).unwrap();
+        assert_eq!(
+            details.out_amount,
+            base_lots as u64 * 1_000_000,
+            "{in_amount}"
+        );
+        assert_eq!(details.fee_amount, fee_in_quote_lots * 10, "{in_amount}");
+        assert_eq!(
+            details.in_amount_consumed,
+            spent_quote_lots * 10,
+            "{in_amount}"
+        );
+        assert!(details.in_amount_consumed <= in_amount);
+        assert_eq!(
+            jupiter_phoenix.quote(&quote_params).unwrap().fee_mint,
+            quote_mint
+        );
+    }
+
+    // Sells take the fee out of the quote they receive
+    let quote = jupiter_phoenix
+        .quote(&QuoteParams {
+            in_amount: 1_000_000_000,
+            input_mint: base_mint,
+            output_mint: quote_mint,
+        })
+        .unwrap();
+    assert_eq!(quote.fee_mint, quote_mint);
+    assert_eq!(quote.out_amount + quote.fee_amount, 19_990_000);
+}
+
+#[test]
+fn test_cached_pdas_match_derived() {
+    use crate::test_utils::sol_usdc_market;
+
+    let ladder = Ladder {
+        bids: vec![],
+        asks: vec![],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 0);
+    let market_key = jupiter_phoenix.key();
+    let program_id = phoenix::id();
+    let vault = |mint: &Pubkey| {
+        Pubkey::find_program_address(&[b"vault", market_key.as_ref(), mint.as_ref()], &program_id).0
+    };
+
+    assert_eq!(
+        jupiter_phoenix.log_authority,
+        Pubkey::find_program_address(&[b"log"], &program_id).0
+    );
+    assert_eq!(
+        jupiter_phoenix.base_vault(),
+        vault(&jupiter_phoenix.base_mint)
+    );
+    assert_eq!(
+        jupiter_phoenix.quote_vault(),
+        vault(&jupiter_phoenix.quote_mint)
+    );
+    assert_ne!(jupiter_phoenix.base_vault, jupiter_phoenix.quote_vault);
+}
+
+#[test]
+fn test_vaults_in_accounts_to_update() {
+    use crate::test_utils::sol_usdc_market;
+
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 19_999,
+            size_in_base_lots: 1_000,
+        }],
+        asks: vec![],
+    };
+    let mut jupiter_phoenix = sol_usdc_market(&ladder, 0);
+    let market_key = jupiter_phoenix.key();
+    assert_eq!(
+        jupiter_phoenix.get_accounts_to_update(),
+        vec![market_key, clock::id()]
+    );
+
+    jupiter_phoenix.set_vaults_in_accounts_to_update(true);
+    let accounts_to_update = jupiter_phoenix.get_accounts_to_update();
+    assert_eq!(
+        accounts_to_update,
+        vec![
+            market_key,
+            clock::id(),
+            jupiter_phoenix.base_vault(),
+            jupiter_phoenix.quote_vault(),
+        ]
+    );
+    // Updates with the vaults in the map read only the market
+    let data = crate::test_utils::market_data(
+        &crate::test_utils::market_header(
+            jupiter_phoenix.base_mint,
+            jupiter_phoenix.quote_mint,
+            1_000_000,
+            1,
+            1_000,
+        ),
+        &ladder,
+        0,
+    );
+    let vault_account = PartialAccount {
+        owner: spl_token::id(),
+        data: vec![0; 165],
+    };
+    let accounts_map = HashMap::from([
+        (
+            market_key,
+            PartialAccount {
+                owner: phoenix::id(),
+                data,
+            },
+        ),
+        (jupiter_phoenix.base_vault(), vault_account.clone()),
+        (jupiter_phoenix.quote_vault(), vault_account),
+    ]);
+    jupiter_phoenix.clear_ladder();
+    jupiter_phoenix.update(&accounts_map).unwrap();
+    assert_eq!(*jupiter_phoenix.ladder, ladder);
+
+    jupiter_phoenix.set_vaults_in_accounts_to_update(false);
+    assert_eq!(jupiter_phoenix.get_accounts_to_update().len(), 2);
+}
+
+#[test]
+fn test_top_of_book_prices() {
+    use crate::test_utils::sol_usdc_market;
+
+    let level = |price_in_ticks| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots: 1_000,
+    };
+    let ladder = Ladder {
+        bids: vec![level(19_995), level(19_990)],
+        asks: vec![level(20_005), level(20_010)],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 0);
+    assert_eq!(jupiter_phoenix.best_bid(), Some(19.995));
+    assert_eq!(jupiter_phoenix.best_ask(), Some(20.005));
+    assert_eq!(jupiter_phoenix.mid_price(), Some(20.0));
+
+    let one_sided = sol_usdc_market(
+        &Ladder {
+            bids: ladder.bids.clone(),
+            asks: vec![],
+        },
+        0,
+    );
+    assert_eq!(one_sided.best_bid(), Some(19.995));
+    assert_eq!(one_sided.best_ask(), None);
+    assert_eq!(one_sided.mid_price(), None);
+}
+
+#[test]
+fn test_level_counts() {
+    use crate::test_utils::{keyed_account, market_data, market_header};
+
+    let level = |price_in_ticks| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots: 1_000,
+    };
+    let ladder = Ladder {
+        bids: vec![level(19_999), level(19_998), level(19_997)],
+        asks: vec![],
+    };
+    let data = market_data(
+        &market_header(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            1_000_000,
+            1,
+            1_000,
+        ),
+        &ladder,
+        0,
+    );
+    for (ladder_depth, bid_levels) in [(64, 3), (2, 2)] {
+        let jupiter_phoenix = JupiterPhoenix::new_from_keyed_account_with_ladder_depth(
+            &keyed_account(data.clone()),
+            ladder_depth,
+        )
+        .unwrap();
+        assert_eq!(jupiter_phoenix.bid_levels(), bid_levels);
+        assert_eq!(jupiter_phoenix.ask_levels(), 0);
+    }
+}
+
+#[test]
+fn test_top_of_book_after() {
+    use crate::test_utils::sol_usdc_market;
+
+    let level = |price_in_ticks| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots: 1_000,
+    };
+    let ladder = Ladder {
+        bids: vec![level(20_000), level(19_990), level(19_980)],
+        asks: vec![level(20_010), level(20_020), level(20_030)],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 2);
+    let (base_mint, quote_mint) = (jupiter_phoenix.base_mint, jupiter_phoenix.quote_mint);
+    let after = |in_amount, input_mint, output_mint| {
+        jupiter_phoenix
+            .top_of_book_after(&QuoteParams {
+                in_amount,
+                input_mint,
+                output_mint,
+            })
+            .unwrap()
+    };
+
+    // Selling 2 SOL takes exactly the first two bids, leaving the third on top
+    assert_eq!(
+        after(2_000_000_000, base_mint, quote_mint),
+        (Some(19.98), Some(20.01))
+    );
+    // A partial fill leaves what is left of the level on top
+    assert_eq!(
+        after(1_500_000_000, base_mint, quote_mint),
+        (Some(19.99), Some(20.01))
+    );
+    // Buying 2 SOL takes the first two asks. The program's fee adjustment of the budget rounds
+    // down, so it takes one atom more than the 2 bps fee on top.
+    let two_levels = 20_010_000 + 20_020_000 + 8_006 + 1;
+    assert_eq!(
+        after(two_levels, quote_mint, base_mint),
+        (Some(20.0), Some(20.03))
+    );
+    // One atom short of the second level's last lot leaves it on top
+    assert_eq!(
+        after(two_levels - 1, quote_mint, base_mint),
+        (Some(20.0), Some(20.02))
+    );
+    // Clearing a side leaves nothing there
+    assert_eq!(
+        after(u64::MAX / 2, base_mint, quote_mint),
+        (None, Some(20.01))
+    );
+    assert_eq!(after(0, base_mint, quote_mint), (Some(20.0), Some(20.01)));
+}
+
+#[test]
+fn test_quote_eq() {
+    use crate::test_utils::{keyed_account, market_data, market_header};
+
+    let header = market_header(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        1_000_000,
+        1,
+        1_000,
+    );
+    let level = |price_in_ticks| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots: 1_000,
+    };
+    let market = |bid, taker_fee_bps| {
+        JupiterPhoenix::new_from_keyed_account(&keyed_account(market_data(
+            &header,
+            &Ladder {
+                bids: vec![level(bid), level(19_990)],
+                asks: vec![level(20_010)],
+            },
+            taker_fee_bps,
+        )))
+        .unwrap()
+    };
+    let same_market = |mut jupiter_phoenix: JupiterPhoenix, other: &JupiterPhoenix| {
+        jupiter_phoenix.market_key = other.market_key;
+        jupiter_phoenix
+    };
+
+    let jupiter_phoenix = market(20_000, 2);
+    let mut identical = same_market(market(20_000, 2), &jupiter_phoenix);
+    // Bookkeeping does not matter
+    identical.market_sequence_number = Some(7);
+    identical.consecutive_update_failures = 3;
+    assert!(jupiter_phoenix.quote_eq(&identical));
+
+    assert!(!jupiter_phoenix.quote_eq(&same_market(market(20_001, 2), &jupiter_phoenix)));
+    assert!(!jupiter_phoenix.quote_eq(&same_market(market(20_000, 3), &jupiter_phoenix)));
+    assert!(!jupiter_phoenix.quote_eq(&market(20_000, 2)));
+    identical.set_rounding_mode(RoundingMode::Nearest);
+    assert!(!jupiter_phoenix.quote_eq(&identical));
+}
+
+#[test]
+fn test_spread_bps() {
+    use crate::test_utils::sol_usdc_market;
+
+    let level = |price_in_ticks| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots: 1_000,
+    };
+    let spread_bps = |bid: Option<u64>, ask: Option<u64>| {
+        sol_usdc_market(
+            &Ladder {
+                bids: bid.into_iter().map(level).collect(),
+                asks: ask.into_iter().map(level).collect(),
+            },
+            0,
+        )
+        .spread_bps()
+    };
+    // 19.99 / 20.01: 0.02 over a 20.00 mid is exactly 10 bps
+    assert_eq!(spread_bps(Some(19_990), Some(20_010)), Some(10));
+    assert_eq!(spread_bps(Some(19_999), Some(20_001)), Some(1));
+    // 19.995 / 20.002 is just over 3.5 bps, which a floor would report as 3
+    assert_eq!(spread_bps(Some(19_995), Some(20_002)), Some(4));
+    // 1 / 2 ticks is 6666.67 bps and 3 / 4 ticks 2857.14 bps
+    assert_eq!(spread_bps(Some(1), Some(2)), Some(6_667));
+    assert_eq!(spread_bps(Some(3), Some(4)), Some(2_857));
+    // Wide ticks do not overflow
+    assert_eq!(spread_bps(Some(u64::MAX - 1), Some(u64::MAX)), Some(0));
+    assert_eq!(spread_bps(Some(19_990), None), None);
+    assert_eq!(spread_bps(None, Some(20_010)), None);
+}
+
+#[test]
+fn test_summary_and_display() {
+    use crate::test_utils::sol_usdc_market;
+
+    let level = |price_in_ticks| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots: 1_000,
+    };
+    let jupiter_phoenix = sol_usdc_market(
+        &Ladder {
+            bids: vec![level(19_995)],
+            asks: vec![],
+        },
+        2,
+    );
+    let summary = jupiter_phoenix.summary();
+    assert_eq!(summary.market_key, jupiter_phoenix.key());
+    assert_eq!(summary.taker_fee_bps, 2);
+    assert_eq!(summary.best_bid, Some(19.995));
+    assert_eq!(summary.best_ask, None);
+
+    let display = jupiter_phoenix.to_string();
+    let market_key = jupiter_phoenix.key().to_string();
+    assert!(display.starts_with(&format!("Phoenix {}..", &market_key[..4])));
+    assert!(display.contains("fee 2 bps"));
+    assert!(display.ends_with("bid 19.995 ask -"));
+}
+
+#[test]
+fn test_price_impact() {
+    use crate::test_utils::sol_usdc_market;
+
+    let level = |price_in_ticks, size_in_base_lots| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots,
+    };
+    // A thin top of book with much worse liquidity behind it
+    let ladder = Ladder {
+        bids: vec![level(20_000, 100), level(16_000, 10_000)],
+        asks: vec![level(20_000, 100), level(25_000, 10_000)],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 10);
+
+    // Within the top of book there is no impact
+    let quote = jupiter_phoenix.quote_side(Side::Ask, 100_000_000).unwrap();
+    assert_eq!(quote.price_impact_pct, Decimal::ZERO);
+    let quote = jupiter_phoenix.quote_side(Side::Bid, 1_000_000).unwrap();
+    assert_eq!(quote.price_impact_pct, Decimal::ZERO);
+
+    // 0.1 SOL at 20 and 0.9 SOL at 16 average 16.4, 18% below the top of book
+    let quote = jupiter_phoenix
+        .quote_side(Side::Ask, 1_000_000_000)
+        .unwrap();
+    assert_eq!(quote.price_impact_pct, Decimal::new(18, 2));
+    // Buying 2.1 SOL: 0.1 SOL at 20 and 2 SOL at 25 average 24.7619..., 23.8% above
+    let quote = jupiter_phoenix.quote_side(Side::Bid, 52_052_052).unwrap();
+    assert_eq!(quote.out_amount, 2_100_000_000);
+    assert!(quote.price_impact_pct > Decimal::new(238, 3));
+    assert!(quote.price_impact_pct < Decimal::new(239, 3));
+}
+
+#[test]
+fn test_ladder_depth() {
+    use crate::test_utils::{keyed_account, market_data, market_header};
+
+    let header = market_header(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        1_000_000,
+        1,
+        1_000,
+    );
+    let ladder = Ladder {
+        bids: (0..100)
+            .map(|level| LadderOrder {
+                price_in_ticks: 20_000 - level,
+                size_in_base_lots: 1_000,
+            })
+            .collect(),
+        asks: vec![LadderOrder {
+            price_in_ticks: 20_001,
+            size_in_base_lots: 1_000,
+        }],
+    };
+    let keyed_account = keyed_account(market_data(&header, &ladder, 0));
+    let sell = |in_amount| QuoteParams {
+        in_amount,
+        input_mint: header.base_params.mint_key,
+        output_mint: header.quote_params.mint_key,
+    };
+
+    let mut jupiter_phoenix = JupiterPhoenix::new_from_keyed_account(&keyed_account).unwrap();
+    assert_eq!(
+        jupiter_phoenix.ladder.bids.len() as u64,
+        DEFAULT_LADDER_DEPTH
+    );
+    assert_eq!(jupiter_phoenix.ladder.asks.len(), 1);
+    // Updates keep the same depth
+    let accounts_map = HashMap::from([(
+        keyed_account.key,
+        PartialAccount::from(keyed_account.account.clone()),
+    )]);
+    jupiter_phoenix.update(&accounts_map).unwrap();
+    assert_eq!(
+        jupiter_phoenix.ladder.bids.len() as u64,
+        DEFAULT_LADDER_DEPTH
+    );
+    // A sell of 70 SOL runs past the cached depth
+    assert!(
+        jupiter_phoenix
+            .quote(&sell(70_000_000_000))
+            .unwrap()
+            .not_enough_liquidity
+    );
+    assert!(jupiter_phoenix.ladder_possibly_truncated(&sell(70_000_000_000)));
+    assert!(!jupiter_phoenix.ladder_possibly_truncated(&sell(10_000_000_000)));
+    assert_eq!(jupiter_phoenix.ladder_depth_limited(), (true, false));
+    jupiter_phoenix.clear_ladder();
+    assert_eq!(jupiter_phoenix.ladder_depth_limited(), (false, false));
+
+    let mut jupiter_phoenix =
+        JupiterPhoenix::new_from_keyed_account_with_ladder_depth(&keyed_account, 200).unwrap();
+    assert_eq!(jupiter_phoenix.ladder.bids.len(), 100);
+    jupiter_phoenix.update(&accounts_map).unwrap();
+    assert_eq!(jupiter_phoenix.ladder.bids.len(), 100);
+    assert_eq!(jupiter_phoenix.ladder_depth_limited(), (false, false));
+    assert!(
+        !jupiter_phoenix
+            .quote(&sell(70_000_000_000))
+            .unwrap()
+            .not_enough_liquidity
+    );
+
+    let mut jupiter_phoenix =
+        JupiterPhoenix::new_from_keyed_account_with_ladder_depth(&keyed_account, 3).unwrap();
+    jupiter_phoenix.update(&accounts_map).unwrap();
+    assert_eq!(jupiter_phoenix.ladder.bids, ladder.bids[..3]);
+    assert_eq!(jupiter_phoenix.ladder_depth_limited(), (true, false));
+
+    // A book exactly as deep as the ladder is cached whole
+    let jupiter_phoenix =
+        JupiterPhoenix::new_from_keyed_account_with_ladder_depth(&keyed_account, 100).unwrap();
+    assert_eq!(jupiter_phoenix.ladder.bids, ladder.bids);
+    assert_eq!(jupiter_phoenix.ladder_depth_limited(), (false, false));
+
+    assert!(JupiterPhoenix::new_from_keyed_account_with_ladder_depth(&keyed_account, 0).is_err());
+}
+
+#[test]
+fn test_place_limit_order_metas() {
+    use crate::test_utils::sol_usdc_market;
+
+    let ladder = Ladder {
+        bids: vec![],
+        asks: vec![],
+    };
+    let mut jupiter_phoenix = sol_usdc_market(&ladder, 0);
+    let (base_mint, quote_mint) = (jupiter_phoenix.base_mint, jupiter_phoenix.quote_mint);
+    let trader = Pubkey::new_unique();
+    let mint_account = |owner| Account {
+        lamports: 0,
+        data: vec![],
+        owner,
+        executable: false,
+        rent_epoch: 0,
+    };
+
+    assert!(jupiter_phoenix
+        .place_limit_order_metas(0, 1, &trader)
+        .is_err());
+    assert!(jupiter_phoenix
+        .place_limit_order_metas(20_000, 0, &trader)
+        .is_err());
+
+    for token_program in [spl_token::id(), TOKEN_2022_PROGRAM_ID] {
+        jupiter_phoenix
+            .detect_token_program(&mint_account(token_program), &mint_account(token_program))
+            .unwrap();
+        let account_metas = jupiter_phoenix
+            .place_limit_order_metas(20_000, 1, &trader)
+            .unwrap();
+        assert_eq!(account_metas.len(), 10);
+        assert_eq!(account_metas[3], AccountMeta::new_readonly(trader, true));
+        assert_eq!(
+            account_metas[5],
+            AccountMeta::new(
+                get_associated_token_address_with_program_id(&trader, &base_mint, &token_program),
+                false
+            )
+        );
+        assert_eq!(
+            account_metas[6],
+            AccountMeta::new(
+                get_associated_token_address_with_program_id(&trader, &quote_mint, &token_program),
+                false
+            )
+        );
+        assert_eq!(
+            account_metas[9],
+            AccountMeta::new_readonly(token_program, false)
+        );
+    }
+}
+
+#[test]
+fn test_token_2022_market() {
+    use crate::test_utils::{sol_usdc_market, swap_params};
+
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 20_000,
+            size_in_base_lots: 1_000,
+        }],
+        asks: vec![],
+    };
+    let mut jupiter_phoenix = sol_usdc_market(&ladder, 0);
+    let [base_mint, quote_mint] =
+        <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+    assert_eq!(jupiter_phoenix.token_program(), spl_token::id());
+
+    let mint_account = |owner| Account {
+        lamports: 0,
+        data: vec![],
+        owner,
+        executable: false,
+        rent_epoch: 0,
+    };
+    let token_2022_mint = mint_account(TOKEN_2022_PROGRAM_ID);
+    assert!(jupiter_phoenix
+        .detect_token_program(&token_2022_mint, &mint_account(spl_token::id()))
+        .is_err());
+    assert!(jupiter_phoenix
+        .detect_token_program(
+            &mint_account(Pubkey::new_unique()),
+            &mint_account(Pubkey::new_unique())
+        )
+        .is_err());
+    assert_eq!(jupiter_phoenix.token_program(), spl_token::id());
+
+    jupiter_phoenix
+        .detect_token_program(&token_2022_mint, &token_2022_mint)
+        .unwrap();
+    assert_eq!(jupiter_phoenix.token_program(), TOKEN_2022_PROGRAM_ID);
+    let params = swap_params(base_mint, quote_mint);
+    let account_metas = jupiter_phoenix
+        .get_swap_leg_and_account_metas(&params)
+        .unwrap()
+        .account_metas;
+    assert_eq!(
+        account_metas.last(),
+        Some(&AccountMeta::new_readonly(TOKEN_2022_PROGRAM_ID, false))
+    );
+    let instruction = jupiter_phoenix.swap_instruction(&params, None).unwrap();
+    assert!(instruction
+        .accounts
+        .iter()
+        .any(|account_meta| account_meta.pubkey == TOKEN_2022_PROGRAM_ID));
+    assert!(instruction
+        .accounts
+        .iter()
+        .all(|account_meta| account_meta.pubkey != spl_token::id()));
+}
+
+#[test]
+fn test_quote_large_notional_without_overflow() {
+    use crate::test_utils::sol_usdc_market;
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    // 1e6 lots at 1e13 ticks is 1e19 quote atoms, which fits in a u64, but the lots times
+    // ticks times tick size product on the way there does not
+    let price_in_ticks = 10_000_000_000_000;
+    let ladder = Ladder {
+        bids: vec![
+            LadderOrder {
+                price_in_ticks: 0,
+                size_in_base_lots: u64::MAX,
+            },
+            LadderOrder {
+                price_in_ticks,
+                size_in_base_lots: u64::MAX,
+            },
+        ],
+        asks: vec![
+            LadderOrder {
+                price_in_ticks: 0,
+                size_in_base_lots: u64::MAX,
+            },
+            LadderOrder {
+                price_in_ticks,
+                size_in_base_lots: u64::MAX,
+            },
+        ],
+    };
+    let mut jupiter_phoenix = sol_usdc_market(&ladder, 0);
+    // The market account cannot hold zero-priced orders, so they are injected directly
+    jupiter_phoenix.ladder = Arc::new(ladder);
+    jupiter_phoenix.cache_cumulative_liquidity();
+    let [base_mint, quote_mint] =
+        <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+
+    let sell = jupiter_phoenix
+        .quote(&QuoteParams {
+            in_amount: 1_000_000_000_000,
+            input_mint: base_mint,
+            output_mint: quote_mint,
+        })
+        .unwrap();
+    assert_eq!(sell.out_amount, 10_000_000_000_000_000_000);
+    assert!(!sell.not_enough_liquidity);
+
+    let buy = jupiter_phoenix
+        .quote(&QuoteParams {
+            in_amount: 10_000_000_000_000_000,
+            input_mint: quote_mint,
+            output_mint: base_mint,
+        })
+        .unwrap();
+    assert_eq!(buy.out_amount, 1_000_000_000);
+    assert!(!buy.not_enough_liquidity);
+
+    // Sell proceeds past u64::MAX, and buy budgets past the program's u64 of adjusted quote
+    // lots, are rejected rather than wrapped
+    for (in_amount, input_mint, output_mint) in [
+        (2_000_000_000_000, base_mint, quote_mint),
+        (10_000_000_000_000_000_000, quote_mint, base_mint),
+    ] {
+        let err = jupiter_phoenix
+            .quote(&QuoteParams {
+                in_amount,
+                input_mint,
+                output_mint,
+            })
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<PhoenixAmmError>(),
+            Some(&PhoenixAmmError::AmountOverflow)
+        );
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_round_trip() {
+    use crate::test_utils::sol_usdc_market;
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let level = |price_in_ticks| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots: 1_000,
+    };
+    let ladder = Ladder {
+        bids: vec![level(19_999), level(19_998)],
+        asks: vec![level(20_001), level(20_002)],
+    };
+    let mut jupiter_phoenix = sol_usdc_market(&ladder, 5);
+    jupiter_phoenix.set_rounding_mode(RoundingMode::Nearest);
+    let [base_mint, quote_mint] =
+        <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+
+    let json = serde_json::to_string(&jupiter_phoenix).unwrap();
+    let restored: JupiterPhoenix = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored.key(), jupiter_phoenix.key());
+    assert_eq!(
+        restored.get_reserve_mints(),
+        jupiter_phoenix.get_reserve_mints()
+    );
+    assert_eq!(restored.ladder, jupiter_phoenix.ladder);
+    assert!(restored.is_swappable());
+    assert_eq!(serde_json::to_string(&restored).unwrap(), json);
+
+    for (input_mint, output_mint, in_amount) in [
+        (base_mint, quote_mint, 1_500_000_000),
+        (quote_mint, base_mint, 30_000_000),
+    ] {
+        let quote_params = QuoteParams {
+            in_amount,
+            input_mint,
+            output_mint,
+        };
+        let (quote, restored_quote) = (
+            jupiter_phoenix.quote(&quote_params).unwrap(),
+            restored.quote(&quote_params).unwrap(),
+        );
+        assert_eq!(restored_quote.out_amount, quote.out_amount);
+        assert_eq!(restored_quote.fee_amount, quote.fee_amount);
+        assert_eq!(restored_quote.price_impact_pct, quote.price_impact_pct);
+    }
+
+    // The running totals are rebuilt from the ladder, never taken from the payload
+    assert!(!json.contains("cumulative_liquidity"));
+    let cumulative_levels = |jupiter_phoenix: &JupiterPhoenix| {
+        jupiter_phoenix
+            .cumulative_liquidity
+            .get()
+            .map(|cumulative| cumulative.bids.num_levels())
+    };
+    assert_eq!(cumulative_levels(&restored), Some(2));
+    let tampered = json.replacen(
+        '{',
+        r#"{"cumulative_liquidity":{"bids":{"base_lots":[0,0,0],"lot_ticks":[0,0,0],"quote_atoms":[0,0,0],"priced_levels":[0,0,0]}},"#,
+        1,
+    );
+    let tampered: JupiterPhoenix = serde_json::from_str(&tampered).unwrap();
+    assert_eq!(cumulative_levels(&tampered), None);
+    let sell = QuoteParams {
+        in_amount: 1_500_000_000,
+        input_mint: base_mint,
+        output_mint: quote_mint,
+    };
+    assert_eq!(
+        format!("{:?}", tampered.quote(&sell).unwrap()),
+        format!("{:?}", jupiter_phoenix.quote(&sell).unwrap())
+    );
+    assert_eq!(cumulative_levels(&tampered), Some(2));
+
+    let invalid_status = json.replace("\"market_status\":1", "\"market_status\":6");
+    assert_ne!(invalid_status, json);
+    assert!(serde_json::from_str::<JupiterPhoenix>(&invalid_status).is_err());
+}
+
+#[test]
+fn test_from_parts() {
+    use crate::test_utils::{keyed_account, market_data, market_header};
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let header = market_header(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        1_000_000,
+        1,
+        1_000,
+    );
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 19_999,
+            size_in_base_lots: 1_000,
+        }],
+        asks: vec![LadderOrder {
+            price_in_ticks: 20_001,
+            size_in_base_lots: 1_000,
+        }],
+    };
+    let keyed_account = keyed_account(market_data(&header, &ladder, 5));
+    let from_keyed_account = JupiterPhoenix::new_from_keyed_account(&keyed_account).unwrap();
+    let from_parts = JupiterPhoenix::from_parts(
+        keyed_account.key,
+        &phoenix::id(),
+        &keyed_account.account.data,
+    )
+    .unwrap();
+    assert_eq!(format!("{from_parts:?}"), format!("{from_keyed_account:?}"));
+
+    let mut invalid_status = header;
+    invalid_status.status = MarketStatus::Tombstoned as u64 + 1;
+    let err = JupiterPhoenix::from_parts(
+        keyed_account.key,
+        &phoenix::id(),
+        &market_data(&invalid_status, &ladder, 5),
+    )
+    .err()
+    .unwrap();
+    assert_eq!(
+        err.downcast_ref::<PhoenixAmmError>(),
+        Some(&PhoenixAmmError::MalformedMarketAccount)
+    );
+}
+
+#[test]
+fn test_builder() {
+    use crate::test_utils::{keyed_account, market_data, market_header, swap_params};
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let header = market_header(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        1_000_000,
+        1,
+        1_000,
+    );
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 19_999,
+            size_in_base_lots: 1_000,
+        }],
+        asks: vec![],
+    };
+    let keyed_account = keyed_account(market_data(&header, &ladder, 5));
+    assert_eq!(
+        format!(
+            "{:?}",
+            JupiterPhoenix::builder()
+                .build_from_keyed_account(&keyed_account)
+                .unwrap()
+        ),
+        format!(
+            "{:?}",
+            JupiterPhoenix::new_from_keyed_account(&keyed_account).unwrap()
+        )
+    );
+
+    // A localnet deployment owns its markets, and derives its PDAs from its own program id
+    let program_id = Pubkey::new_unique();
+    let jupiter_phoenix = JupiterPhoenix::builder()
+        .program_id(program_id)
+        .taker_fee_override(0)
+        .ladder_depth(1)
+        .build_from_parts(keyed_account.key, &program_id, &keyed_account.account.data)
+        .unwrap();
+    let vault = |mint: &Pubkey| {
+        Pubkey::find_program_address(
+            &[b"vault", keyed_account.key.as_ref(), mint.as_ref()],
+            &program_id,
+        )
+        .0
+    };
+    let log_authority = Pubkey::find_program_address(&[b"log"], &program_id).0;
+    assert_eq!(jupiter_phoenix.program_id(), program_id);
+    assert_eq!(jupiter_phoenix.log_authority, log_authority);
+    assert_eq!(
+        jupiter_phoenix.base_vault,
+        vault(&jupiter_phoenix.base_mint)
+    );
+    assert_eq!(
+        jupiter_phoenix.quote_vault,
+        vault(&jupiter_phoenix.quote_mint)
+    );
+    assert_eq!(jupiter_phoenix.ladder_depth, 1);
+    assert_eq!(
+        jupiter_phoenix
+            .quote_side(Side::Ask, 1_000_000_000)
+            .unwrap()
+            .fee_amount,
+        0
+    );
+
+    let params = swap_params(jupiter_phoenix.base_mint, jupiter_phoenix.quote_mint);
+    let account_metas = jupiter_phoenix
+        .get_swap_leg_and_account_metas(&params)
+        .unwrap()
+        .account_metas;
+    let instruction = jupiter_phoenix.swap_instruction(&params, None).unwrap();
+    assert_eq!(instruction.program_id, program_id);
+    for pubkey in [
+        program_id,
+        log_authority,
+        jupiter_phoenix.base_vault,
+        jupiter_phoenix.quote_vault,
+    ] {
+        assert!(account_metas
+            .iter()
+            .any(|account_meta| account_meta.pubkey == pubkey));
+        assert!(instruction
+            .accounts
+            .iter()
+            .any(|account_meta| account_meta.pubkey == pubkey));
+    }
+    assert!(instruction
+        .accounts
+        .iter()
+        .all(|account_meta| account_meta.pubkey != phoenix::id()));
+
+    // The label only names the pair when asked to
+    let (base_mint, quote_mint) = (header.base_params.mint_key, header.quote_params.mint_key);
+    assert_eq!(jupiter_phoenix.label(), "Phoenix");
+    let label = JupiterPhoenix::builder()
+        .pair_label()
+        .build_from_keyed_account(&keyed_account)
+        .unwrap()
+        .label();
+    assert_eq!(
+        label,
+        format!(
+            "Phoenix {}/{}",
+            short_key(&base_mint),
+            short_key(&quote_mint)
+        )
+    );
+    assert!(label.contains(&base_mint.to_string()[..4]));
+    assert!(label.contains(&quote_mint.to_string()[..4]));
+    let label = JupiterPhoenix::builder()
+        .symbols("SOL", "USDC")
+        .build_from_keyed_account(&keyed_account)
+        .unwrap()
+        .label();
+    assert_eq!(label, "Phoenix SOL/USDC");
+
+    assert!(JupiterPhoenix::builder()
+        .taker_fee_override(10_001)
+        .build_from_keyed_account(&keyed_account)
+        .is_err());
+    assert!(JupiterPhoenix::builder()
+        .ladder_depth(0)
+        .build_from_keyed_account(&keyed_account)
+        .is_err());
+}
+
+#[test]
+fn test_depth() {
+    use crate::test_utils::sol_usdc_market;
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let ladder = Ladder {
+        bids: vec![
+            LadderOrder {
+                price_in_ticks: 19_999,
+                size_in_base_lots: 1_000,
+            },
+            LadderOrder {
+                price_in_ticks: 19_990,
+                size_in_base_lots: 500,
+            },
+        ],
+        asks: vec![],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 0);
+    let [base_mint, quote_mint] =
+        <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+
+    let depth = jupiter_phoenix.depth(Side::Ask, 10);
+    assert_eq!(
+        depth,
+        vec![
+            (19.999, 1_000_000_000, 19_999_000),
+            (19.99, 1_500_000_000, 29_994_000)
+        ]
+    );
+    // Selling everything at a depth gets exactly the quote atoms it reports
+    for (_, base_atoms, quote_atoms) in depth.iter() {
+        let quote = jupiter_phoenix
+            .quote(&QuoteParams {
+                in_amount: *base_atoms,
+                input_mint: base_mint,
+                output_mint: quote_mint,
+            })
+            .unwrap();
+        assert_eq!(quote.out_amount, *quote_atoms);
+    }
+
+    assert_eq!(jupiter_phoenix.depth(Side::Ask, 1), depth[..1]);
+    assert!(jupiter_phoenix.depth(Side::Ask, 0).is_empty());
+    assert!(jupiter_phoenix.depth(Side::Bid, 10).is_empty());
+}
+
+#[test]
+fn test_unchanged_sequence_number_skips_reload() {
+    use crate::test_utils::{keyed_account, market_data, market_header};
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let mut header = market_header(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        1_000_000,
+        1,
+        1_000,
+    );
+    header.market_sequence_number = 7;
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 19_999,
+            size_in_base_lots: 1_000,
+        }],
+        asks: vec![],
+    };
+    let keyed_account = keyed_account(market_data(&header, &ladder, 0));
+    let mut jupiter_phoenix = JupiterPhoenix::new_from_keyed_account(&keyed_account).unwrap();
+    assert_eq!(jupiter_phoenix.market_sequence_number(), Some(7));
+
+    // Marks the cached ladder, so a rebuild from the account would be visible
+    let marker = LadderOrder {
+        price_in_ticks: 1,
+        size_in_base_lots: 1,
+    };
+    Arc::make_mut(&mut jupiter_phoenix.ladder).asks.push(marker);
+    let accounts_map = HashMap::from([(
+        keyed_account.key,
+        PartialAccount::from(keyed_account.account.clone()),
+    )]);
+    for _ in 0..2 {
+        jupiter_phoenix.update(&accounts_map).unwrap();
+        assert_eq!(jupiter_phoenix.ladder.asks, vec![marker]);
+    }
+
+    header.market_sequence_number = 8;
+    let mut account = keyed_account.account.clone();
+    account.data = market_data(&header, &ladder, 0);
+    let accounts_map = HashMap::from([(keyed_account.key, PartialAccount::from(account))]);
+    jupiter_phoenix.update(&accounts_map).unwrap();
+    assert_eq!(*jupiter_phoenix.ladder, ladder);
+    assert_eq!(jupiter_phoenix.market_sequence_number(), Some(8));
+
+    // A cleared ladder is reloaded even if the market has not moved
+    jupiter_phoenix.clear_ladder();
+    assert_eq!(jupiter_phoenix.market_sequence_number(), None);
+    jupiter_phoenix.update(&accounts_map).unwrap();
+    assert_eq!(*jupiter_phoenix.ladder, ladder);
+}
+
+#[test]
+fn test_vwap() {
+    use crate::test_utils::sol_usdc_market;
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let level = |price_in_ticks| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots: 1_000,
+    };
+    let ladder = Ladder {
+        bids: vec![level(19_999), level(19_990)],
+        asks: vec![level(20_001), level(20_010)],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 0);
+    let [base_mint, quote_mint] =
+        <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+    let vwap = |input_mint, output_mint, in_amount| {
+        jupiter_phoenix
+            .vwap(&QuoteParams {
+                in_amount,
+                input_mint,
+                output_mint,
+            })
+            .unwrap()
+    };
+
+    // 1 SOL at 19.999 and half of the second level at 19.99
+    let sell = vwap(base_mint, quote_mint, 1_500_000_000).unwrap();
+    assert!((sell - (19.999 + 0.5 * 19.99) / 1.5).abs() < 1e-9);
+    // 1 SOL at 20.001 and half of the second level at 20.01
+    let buy = vwap(quote_mint, base_mint, 30_006_000).unwrap();
+    assert!((buy - (20.001 + 0.5 * 20.01) / 1.5).abs() < 1e-9);
+    // A fill at the top of book only
+    let top = vwap(base_mint, quote_mint, 500_000_000).unwrap();
+    assert!((top - 19.999).abs() < 1e-9);
+
+    assert_eq!(vwap(base_mint, quote_mint, 0), None);
+    let empty = Ladder {
+        bids: vec![],
+        asks: vec![],
+    };
+    let jupiter_phoenix = sol_usdc_market(&empty, 0);
+    let [base_mint, quote_mint] =
+        <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+    for (input_mint, output_mint) in [(base_mint, quote_mint), (quote_mint, base_mint)] {
+        assert_eq!(
+            jupiter_phoenix
+                .vwap(&QuoteParams {
+                    in_amount: 1_000_000_000,
+                    input_mint,
+                    output_mint,
+                })
+                .unwrap(),
+            None
+        );
+    }
+}
+
+#[test]
+fn test_split_input_at_lots() {
+    use crate::test_utils::{keyed_account, market_data, market_header};
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    // 0.001 SOL base lots and 0.01 USDC quote lots
+    let header = market_header(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        1_000_000,
+        10_000,
+        10_000,
+    );
+    let level = |price_in_ticks| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots: 1_000,
+    };
+    let ladder = Ladder {
+        bids: vec![level(2_000)],
+        asks: vec![level(2_000)],
+    };
+    let jupiter_phoenix =
+        JupiterPhoenix::new_from_keyed_account(&keyed_account(market_data(&header, &ladder, 0)))
+            .unwrap();
+    let [base_mint, quote_mint] =
+        <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+
+    for (input_mint, output_mint, in_amount, in_amount_in_lots, lot_remainder) in [
+        (base_mint, quote_mint, 1_234_567, 1_000_000, 234_567),
+        (base_mint, quote_mint, 2_000_000, 2_000_000, 0),
+        (quote_mint, base_mint, 10_009_999, 10_000_000, 9_999),
+        (quote_mint, base_mint, 9_999, 0, 9_999),
+    ] {
+        assert_eq!(
+            jupiter_phoenix
+                .split_input_at_lots(&input_mint, in_amount)
+                .unwrap(),
+            (in_amount_in_lots, lot_remainder)
+        );
+        let quote_params = |in_amount| QuoteParams {
+            in_amount,
+            input_mint,
+            output_mint,
+        };
+        let details = jupiter_phoenix
+            .quote_detailed(&quote_params(in_amount))
+            .unwrap();
+        assert_eq!(details.in_amount_in_lots, in_amount_in_lots);
+        assert_eq!(details.lot_remainder, lot_remainder);
+        // The remainder does not change the output
+        assert_eq!(
+            jupiter_phoenix
+                .quote(&quote_params(in_amount))
+                .unwrap()
+                .out_amount,
+            jupiter_phoenix
+                .quote(&quote_params(in_amount_in_lots))
+                .unwrap()
+                .out_amount
+        );
+    }
+
+    let other_mint = Pubkey::new_unique();
+    assert_eq!(
+        jupiter_phoenix
+            .split_input_at_lots(&other_mint, 1_000_000)
+            .unwrap_err()
+            .downcast_ref::<PhoenixAmmError>(),
+        Some(&PhoenixAmmError::MintNotInMarket)
+    );
+}
+
+#[test]
+fn test_get_reserves() {
+    use crate::test_utils::sol_usdc_market;
+    use phoenix::state::markets::{Ladder, LadderOrder};
+
+    let level = |price_in_ticks, size_in_base_lots| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots,
+    };
+    let ladder = Ladder {
+        bids: vec![level(19_999, 1_000), level(19_000, 500)],
+        asks: vec![level(20_001, 2_000), level(21_000, 250)],
+    };
+    let mut jupiter_phoenix = sol_usdc_market(&ladder, 0);
+    // 2.25 SOL of asks, and 19.999 + 9.5 USDC of bids
+    assert_eq!(jupiter_phoenix.get_reserves(), (2_250_000_000, 29_499_000));
+
+    // Books whose notional overflows a u64 saturate instead of wrapping
+    jupiter_phoenix.ladder = Arc::new(Ladder {
+        bids: vec![level(u64::MAX, u64::MAX); 2],
+        asks: vec![level(u64::MAX, u64::MAX); 2],
+    });
+    assert_eq!(jupiter_phoenix.get_reserves(), (u64::MAX, u64::MAX));
+
+    jupiter_phoenix.clear_ladder();
+    assert_eq!(jupiter_phoenix.get_reserves(), (0, 0));
+}
+
+#[test]
+fn test_expired_orders_left_out_of_ladder() {
+    use crate::test_utils::{keyed_account, market_data_with_resting_orders, market_header};
+    use phoenix::state::markets::{FIFORestingOrder, LadderOrder};
+
+    let header = market_header(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        1_000_000,
+        1,
+        1_000,
+    );
+    let order = |num_base_lots, last_valid_slot, last_valid_unix_timestamp_in_seconds| {
+        FIFORestingOrder::new(
+            0,
+            phoenix::quantities::BaseLots::new(num_base_lots),
+            last_valid_slot,
+            last_valid_unix_timestamp_in_seconds,
+        )
+    };
+    let bids = [
+        (19_999, order(1_000, Some(99), None)),
+        (19_999, order(2_000, None, None)),
+        (19_998, order(500, Some(100), None)),
+    ];
+    let asks = [
+        (20_001, order(1_000, None, Some(1_000))),
+        (20_002, order(3_000, None, None)),
+    ];
+    let keyed_account = keyed_account(market_data_with_resting_orders(&header, &bids, &asks, 0));
+    let mut jupiter_phoenix = JupiterPhoenix::new_from_keyed_account(&keyed_account).unwrap();
+    assert_eq!(
+        jupiter_phoenix.get_accounts_to_update(),
+        vec![keyed_account.key, clock::id()]
+    );
+
+    let level = |price_in_ticks, size_in_base_lots| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots,
+    };
+    // Without a clock every order is live
+    assert_eq!(
+        jupiter_phoenix.ladder.bids,
+        vec![level(19_999, 3_000), level(19_998, 500)]
+    );
+    assert_eq!(
+        jupiter_phoenix.ladder.asks,
+        vec![level(20_001, 1_000), level(20_002, 3_000)]
+    );
+
+    // The market itself never changes, only the clock moves past each expiry
+    let mut update = |slot, unix_timestamp| {
+        let clock = Clock {
+            slot,
+            unix_timestamp,
+            ..Default::default()
+        };
+        let accounts_map = HashMap::from([
+            (
+                keyed_account.key,
+                PartialAccount::from(keyed_account.account.clone()),
+            ),
+            (
+                clock::id(),
+                PartialAccount::from(
+                    Account::new_data(1, &clock, &solana_sdk::sysvar::id()).unwrap(),
+                ),
+            ),
+        ]);
+        jupiter_phoenix.update(&accounts_map).unwrap();
+        jupiter_phoenix.ladder.clone()
+    };
+
+    // Orders still fill in their last valid slot and second
+    let ladder = update(100, 1_000);
+    assert_eq!(ladder.bids, vec![level(19_999, 2_000), level(19_998, 500)]);
+    assert_eq!(
+        ladder.asks,
+        vec![level(20_001, 1_000), level(20_002, 3_000)]
+    );
+
+    let ladder = update(100, 1_001);
+    assert_eq!(ladder.bids, vec![level(19_999, 2_000), level(19_998, 500)]);
+    assert_eq!(ladder.asks, vec![level(20_002, 3_000)]);
+
+    let ladder = update(101, 1_001);
+    assert_eq!(ladder.bids, vec![level(19_999, 2_000)]);
+    assert_eq!(ladder.asks, vec![level(20_002, 3_000)]);
+}
+
+#[test]
+fn test_phoenix_swap_leg() {
+    use crate::test_utils::{sol_usdc_market, swap_params};
+
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 19_999,
+            size_in_base_lots: 1_000,
+        }],
+        asks: vec![LadderOrder {
+            price_in_ticks: 20_001,
+            size_in_base_lots: 1_000,
+        }],
+    };
+    let mut jupiter_phoenix = sol_usdc_market(&ladder, 0);
+    let (base_mint, quote_mint) = (jupiter_phoenix.base_mint, jupiter_phoenix.quote_mint);
+    jupiter_phoenix.set_swap_leg(SwapLeg::Phoenix);
+
+    for (source_mint, destination_mint, in_amount) in [
+        (base_mint, quote_mint, 1_000_000_000),
+        (quote_mint, base_mint, 20_001_000),
+    ] {
+        let mut params = swap_params(source_mint, destination_mint);
+        params.in_amount = in_amount;
+        let SwapAndAccountMetas {
+            swap,
+            account_metas,
+        } = jupiter_phoenix
+            .get_swap_leg_and_account_metas(&params)
+            .unwrap();
+        if source_mint == base_mint {
+            assert!(matches!(swap, Swap::Phoenix { side: Side::Ask }));
+        } else {
+            assert!(matches!(swap, Swap::Phoenix { side: Side::Bid }));
+        }
+        // The leg's accounts are the program's own swap instruction's
+        let instruction = jupiter_phoenix.swap_instruction(&params, None).unwrap();
+        assert_eq!(account_metas, instruction.accounts);
+        assert_eq!(
+            account_metas.len(),
+            jupiter_phoenix.swap_account_meta_count()
+        );
+    }
+
+    // The builder selects the leg too, and Serum stays the default
+    let keyed_account = crate::test_utils::keyed_account(crate::test_utils::market_data(
+        &crate::test_utils::market_header(base_mint, quote_mint, 1_000_000, 1, 1_000),
+        &ladder,
+        0,
+    ));
+    for (builder, is_phoenix) in [
+        (JupiterPhoenix::builder(), false),
+        (JupiterPhoenix::builder().swap_leg(SwapLeg::Phoenix), true),
+    ] {
+        let swap = builder
+            .build_from_keyed_account(&keyed_account)
+            .unwrap()
+            .get_swap_leg_and_account_metas(&swap_params(base_mint, quote_mint))
+            .unwrap()
+            .swap;
+        assert_eq!(matches!(swap, Swap::Phoenix { .. }), is_phoenix);
+    }
+}
+
+#[test]
+fn test_extra_swap_account_metas() {
+    use crate::test_utils::{sol_usdc_market, swap_params};
+
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 19_999,
+            size_in_base_lots: 1_000,
+        }],
+        asks: vec![],
+    };
+    let mut jupiter_phoenix = sol_usdc_market(&ladder, 0);
+    let params = swap_params(jupiter_phoenix.base_mint, jupiter_phoenix.quote_mint);
+    let seat = jupiter_phoenix.seat_address(&params.user_transfer_authority);
+    assert_eq!(
+        seat,
+        phoenix::program::get_seat_address(&jupiter_phoenix.key(), &params.user_transfer_authority)
+            .0
+    );
+
+    // Swaps take no seat by default
+    let account_metas = jupiter_phoenix
+        .get_swap_leg_and_account_metas(&params)
+        .unwrap()
+        .account_metas;
+    assert!(account_metas.iter().all(|meta| meta.pubkey != seat));
 
-    println!(
-        "Getting quote for buying BONK with {} USDC",
-        in_amount as f64 / 10.0_f64.powf(jupiter_phoenix.get_quote_decimals() as f64)
+    // A caller-supplied seat goes after the swap's own accounts, and is counted
+    jupiter_phoenix.set_extra_swap_account_metas(vec![AccountMeta::new_readonly(seat, false)]);
+    let with_seat = jupiter_phoenix
+        .get_swap_leg_and_account_metas(&params)
+        .unwrap()
+        .account_metas;
+    assert_eq!(with_seat[..SWAP_ACCOUNT_META_COUNT], account_metas[..]);
+    assert_eq!(
+        with_seat[SWAP_ACCOUNT_META_COUNT..],
+        [AccountMeta::new_readonly(seat, false)]
+    );
+    assert_eq!(with_seat.len(), jupiter_phoenix.swap_account_meta_count());
+}
+
+#[test]
+fn test_fee_recipient() {
+    use crate::test_utils::{keyed_account, market_data, market_header, swap_params};
+
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 19_999,
+            size_in_base_lots: 1_000,
+        }],
+        asks: vec![],
+    };
+    let header = market_header(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        1_000_000,
+        1,
+        1_000,
+    );
+    let keyed_account = keyed_account(market_data(&header, &ladder, 0));
+    let fee_recipient = Pubkey::new_unique();
+    let seat = Pubkey::new_unique();
+    let params = swap_params(header.base_params.mint_key, header.quote_params.mint_key);
+
+    for swap_leg in [SwapLeg::Serum, SwapLeg::Phoenix] {
+        let mut without = JupiterPhoenix::builder()
+            .swap_leg(swap_leg)
+            .build_from_keyed_account(&keyed_account)
+            .unwrap();
+        let mut with = JupiterPhoenix::builder()
+            .swap_leg(swap_leg)
+            .fee_recipient(fee_recipient)
+            .build_from_keyed_account(&keyed_account)
+            .unwrap();
+        assert_eq!(without.fee_recipient(), None);
+        assert_eq!(with.fee_recipient(), Some(fee_recipient));
+        let metas = |jupiter_phoenix: &JupiterPhoenix| {
+            jupiter_phoenix
+                .get_swap_leg_and_account_metas(&params)
+                .unwrap()
+                .account_metas
+        };
+
+        // Without a fee recipient the layout is the plain nine accounts
+        let default_metas = metas(&without);
+        assert_eq!(default_metas.len(), SWAP_ACCOUNT_META_COUNT);
+        assert_eq!(without.get_accounts_len(), SWAP_ACCOUNT_META_COUNT);
+        assert!(default_metas
+            .iter()
+            .all(|meta| meta.pubkey != fee_recipient));
+
+        // With one, it follows them unchanged, read-only so it takes no write lock
+        let with_metas = metas(&with);
+        assert_eq!(with_metas[..SWAP_ACCOUNT_META_COUNT], default_metas[..]);
+        assert_eq!(
+            with_metas[SWAP_ACCOUNT_META_COUNT..],
+            [AccountMeta::new_readonly(fee_recipient, false)]
+        );
+        assert_eq!(with.get_accounts_len(), with_metas.len());
+
+        // Caller-supplied accounts still go last
+        for jupiter_phoenix in [&mut without, &mut with] {
+            jupiter_phoenix
+                .set_extra_swap_account_metas(vec![AccountMeta::new_readonly(seat, false)]);
+        }
+        assert_eq!(
+            metas(&with)[SWAP_ACCOUNT_META_COUNT..],
+            [
+                AccountMeta::new_readonly(fee_recipient, false),
+                AccountMeta::new_readonly(seat, false)
+            ]
+        );
+        assert_eq!(with.get_accounts_len(), SWAP_ACCOUNT_META_COUNT + 2);
+
+        // Clearing the fee recipient restores the default layout
+        with.set_fee_recipient(None);
+        assert_eq!(metas(&with), metas(&without));
+        assert_eq!(with.get_accounts_len(), without.get_accounts_len());
+    }
+}
+
+#[test]
+fn test_informational_hooks() {
+    use crate::test_utils::{sol_usdc_market, swap_params};
+
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 19_999,
+            size_in_base_lots: 1_000,
+        }],
+        asks: vec![],
+    };
+    let mut jupiter_phoenix = sol_usdc_market(&ladder, 0);
+    let params = swap_params(jupiter_phoenix.base_mint, jupiter_phoenix.quote_mint);
+    let metas_len = |jupiter_phoenix: &JupiterPhoenix| {
+        jupiter_phoenix
+            .get_swap_leg_and_account_metas(&params)
+            .unwrap()
+            .account_metas
+            .len()
+    };
+    assert_eq!(jupiter_phoenix.get_accounts_len(), 9);
+    assert_eq!(
+        jupiter_phoenix.get_accounts_len(),
+        metas_len(&jupiter_phoenix)
+    );
+    assert!(!jupiter_phoenix.requires_update_for_reserve_mints());
+    assert!(jupiter_phoenix.program_dependencies().is_empty());
+
+    jupiter_phoenix.set_swap_leg(SwapLeg::Phoenix);
+    jupiter_phoenix
+        .set_extra_swap_account_metas(vec![AccountMeta::new_readonly(Pubkey::new_unique(), false)]);
+    jupiter_phoenix.token_program = TOKEN_2022_PROGRAM_ID;
+    assert_eq!(jupiter_phoenix.get_accounts_len(), 10);
+    assert_eq!(
+        jupiter_phoenix.get_accounts_len(),
+        metas_len(&jupiter_phoenix)
+    );
+    assert_eq!(
+        jupiter_phoenix.program_dependencies(),
+        vec![(TOKEN_2022_PROGRAM_ID, "spl-token-2022".to_string())]
+    );
+}
+
+#[test]
+fn test_user_setup_instructions() {
+    use crate::test_utils::{sol_usdc_market, swap_params};
+
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 19_999,
+            size_in_base_lots: 1_000,
+        }],
+        asks: vec![],
+    };
+    let mut jupiter_phoenix = sol_usdc_market(&ladder, 0);
+    let (base_mint, quote_mint) = (jupiter_phoenix.base_mint, jupiter_phoenix.quote_mint);
+    let payer = Pubkey::new_unique();
+    let mint_account = |owner| Account {
+        lamports: 0,
+        data: vec![],
+        owner,
+        executable: false,
+        rent_epoch: 0,
+    };
+
+    for token_program in [spl_token::id(), TOKEN_2022_PROGRAM_ID] {
+        jupiter_phoenix
+            .detect_token_program(&mint_account(token_program), &mint_account(token_program))
+            .unwrap();
+        let mut params = swap_params(base_mint, quote_mint);
+        let user = params.user_transfer_authority;
+        params.user_source_token_account =
+            get_associated_token_address_with_program_id(&user, &base_mint, &token_program);
+        params.user_destination_token_account =
+            get_associated_token_address_with_program_id(&user, &quote_mint, &token_program);
+
+        // Neither account exists yet
+        let instructions = jupiter_phoenix
+            .user_setup_instructions(&params, &payer, &HashMap::new())
+            .unwrap();
+        assert_eq!(
+            instructions,
+            vec![
+                create_associated_token_account_idempotent(
+                    &payer,
+                    &user,
+                    &base_mint,
+                    &token_program
+                ),
+                create_associated_token_account_idempotent(
+                    &payer,
+                    &user,
+                    &quote_mint,
+                    &token_program
+                ),
+            ]
+        );
+        assert_eq!(
+            instructions[1].accounts[1].pubkey,
+            params.user_destination_token_account
+        );
+        assert_eq!(instructions[1].accounts[5].pubkey, token_program);
+
+        // Only the missing destination account is created, and nothing once both exist
+        let token_account = PartialAccount::from(mint_account(token_program));
+        let mut accounts_map =
+            HashMap::from([(params.user_source_token_account, token_account.clone())]);
+        let instructions = jupiter_phoenix
+            .user_setup_instructions(&params, &payer, &accounts_map)
+            .unwrap();
+        assert_eq!(instructions.len(), 1);
+        assert_eq!(instructions[0].accounts[3].pubkey, quote_mint);
+        accounts_map.insert(params.user_destination_token_account, token_account);
+        assert!(jupiter_phoenix
+            .user_setup_instructions(&params, &payer, &accounts_map)
+            .unwrap()
+            .is_empty());
+    }
+
+    // A missing account that is not the user's associated token account cannot be created
+    let params = swap_params(base_mint, quote_mint);
+    assert!(jupiter_phoenix
+        .user_setup_instructions(&params, &payer, &HashMap::new())
+        .is_err());
+    let params = swap_params(base_mint, Pubkey::new_unique());
+    assert!(jupiter_phoenix
+        .user_setup_instructions(&params, &payer, &HashMap::new())
+        .is_err());
+}
+
+#[test]
+fn test_quote_against_ladder() {
+    use crate::test_utils::sol_usdc_market;
+
+    let level = |price_in_ticks, size_in_base_lots| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots,
+    };
+    let ladder = Ladder {
+        bids: vec![level(20_000, 1_000), level(19_990, 2_000)],
+        asks: vec![level(20_010, 1_000)],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 2);
+    let sell = QuoteParams {
+        in_amount: 1_500_000_000,
+        input_mint: jupiter_phoenix.base_mint,
+        output_mint: jupiter_phoenix.quote_mint,
+    };
+    // Against the cached ladder it is exactly `quote`
+    assert_eq!(
+        format!(
+            "{:?}",
+            jupiter_phoenix
+                .quote_against_ladder(&ladder, &sell)
+                .unwrap()
+        ),
+        format!("{:?}", jupiter_phoenix.quote(&sell).unwrap())
     );
-    let quote_in = in_amount as f64 / 10.0_f64.powf(jupiter_phoenix.get_quote_decimals() as f64);
+
+    // With the top bid taken, 1.5 SOL all fills at 19.99, less 2 bps
+    let without_top = Ladder {
+        bids: ladder.bids[1..].to_vec(),
+        asks: ladder.asks.clone(),
+    };
     let quote = jupiter_phoenix
-        .quote(&QuoteParams {
-            in_amount,
-            input_mint: jupiter_phoenix.quote_mint,
-            output_mint: jupiter_phoenix.base_mint,
+        .quote_against_ladder(&without_top, &sell)
+        .unwrap();
+    assert_eq!(quote.out_amount, 29_985_000 - 5_997);
+    assert_eq!(quote.fee_amount, 5_997);
+    assert!(!quote.not_enough_liquidity);
+    // The cached ladder is left alone
+    assert_eq!(*jupiter_phoenix.ladder, ladder);
+
+    assert_eq!(
+        jupiter_phoenix
+            .quote_against_ladder(
+                &Ladder {
+                    bids: vec![],
+                    asks: vec![],
+                },
+                &sell
+            )
+            .unwrap_err()
+            .downcast_ref::<PhoenixAmmError>(),
+        Some(&PhoenixAmmError::EmptyBook)
+    );
+}
+
+#[test]
+fn test_quote_with_fills() {
+    use crate::test_utils::sol_usdc_market;
+
+    let level = |price_in_ticks, size_in_base_lots| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots,
+    };
+    let ladder = Ladder {
+        bids: vec![
+            level(20_000, 1_000),
+            level(19_990, 2_000),
+            level(19_980, 500),
+        ],
+        asks: vec![
+            level(20_010, 1_000),
+            level(20_020, 2_000),
+            level(20_030, 500),
+        ],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 3);
+    let (base_mint, quote_mint) = (jupiter_phoenix.base_mint, jupiter_phoenix.quote_mint);
+    let fill = |price_in_ticks, base_lots, partial| FillLevel {
+        price_in_ticks,
+        base_lots,
+        quote_atoms: price_in_ticks * base_lots,
+        partial,
+    };
+
+    // 2.5 SOL takes the whole first bid and half of the second
+    let sell = QuoteParams {
+        in_amount: 2_500_000_000,
+        input_mint: base_mint,
+        output_mint: quote_mint,
+    };
+    let (quote, fills) = jupiter_phoenix.quote_with_fills(&sell).unwrap();
+    assert_eq!(
+        format!("{:?}", quote),
+        format!("{:?}", jupiter_phoenix.quote(&sell).unwrap())
+    );
+    assert_eq!(
+        fills,
+        vec![fill(20_000, 1_000, false), fill(19_990, 1_500, true)]
+    );
+    assert_eq!(
+        fills.iter().map(|fill| fill.quote_atoms).sum::<u64>(),
+        quote.out_amount + quote.fee_amount
+    );
+
+    // 65 USDC buys the first two asks and stops partway into the third
+    let buy = QuoteParams {
+        in_amount: 65_000_000,
+        input_mint: quote_mint,
+        output_mint: base_mint,
+    };
+    let (quote, fills) = jupiter_phoenix.quote_with_fills(&buy).unwrap();
+    assert_eq!(
+        format!("{:?}", quote),
+        format!("{:?}", jupiter_phoenix.quote(&buy).unwrap())
+    );
+    assert_eq!(fills.len(), 3);
+    assert_eq!(
+        fills[..2],
+        [fill(20_010, 1_000, false), fill(20_020, 2_000, false)]
+    );
+    assert!(fills[2].partial && fills[2].price_in_ticks == 20_030);
+    assert_eq!(
+        fills.iter().map(|fill| fill.base_lots).sum::<u64>() * 1_000_000,
+        quote.out_amount
+    );
+
+    let (quote, fills) = jupiter_phoenix
+        .quote_with_fills(&QuoteParams {
+            in_amount: 0,
+            ..sell
         })
         .unwrap();
+    assert_eq!(quote.out_amount, 0);
+    assert!(fills.is_empty());
+}
 
-    let Quote { out_amount, .. } = quote;
+#[test]
+fn test_quote_round_trip() {
+    use crate::test_utils::sol_usdc_market;
 
-    let quote_out = out_amount as f64 / 10.0_f64.powf(jupiter_phoenix.get_base_decimals() as f64);
-    println!(
-        "Quote result: {:?} ({})",
-        out_amount as f64 / 10.0_f64.powf(jupiter_phoenix.get_base_decimals() as f64),
-        quote_in / quote_out
+    let level = |price_in_ticks| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots: 10_000,
+    };
+    let ladder = Ladder {
+        bids: vec![level(19_999), level(19_990)],
+        asks: vec![level(20_001), level(20_010)],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 5);
+    let base_in = 1_500_000_000;
+    let (sell, buy) = jupiter_phoenix.quote_round_trip(base_in).unwrap();
+    let sell_params = QuoteParams {
+        in_amount: base_in,
+        input_mint: jupiter_phoenix.base_mint,
+        output_mint: jupiter_phoenix.quote_mint,
+    };
+    assert_eq!(
+        format!("{:?}", sell),
+        format!("{:?}", jupiter_phoenix.quote(&sell_params).unwrap())
+    );
+    let buy_params = QuoteParams {
+        in_amount: sell.out_amount,
+        input_mint: jupiter_phoenix.quote_mint,
+        output_mint: jupiter_phoenix.base_mint,
+    };
+    assert_eq!(
+        format!("{:?}", buy),
+        format!("{:?}", jupiter_phoenix.quote(&buy_params).unwrap())
+    );
+    // The buy can leave quote atoms below one base lot's price unspent
+    assert!(buy.in_amount <= sell.out_amount);
+    // The spread and two taker fees come out of the base
+    assert!(buy.out_amount < base_in);
+    assert!(buy.out_amount > 0);
+}
+
+#[test]
+fn test_quote_batch_matches_quote() {
+    use crate::test_utils::{keyed_account, market_data, market_header};
+
+    let ladder = Ladder {
+        bids: (0..20)
+            .map(|level| LadderOrder {
+                price_in_ticks: 19_999 - level * 7,
+                size_in_base_lots: 100 + level * 37,
+            })
+            .collect(),
+        asks: (0..20)
+            .map(|level| LadderOrder {
+                price_in_ticks: 20_001 + level * 7,
+                size_in_base_lots: 100 + level * 37,
+            })
+            .collect(),
+    };
+    // A fixed xorshift, so failures reproduce
+    let mut state = 0x9E37_79B9_7F4A_7C15u64;
+    let mut next = move |bound: u64| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state % bound
+    };
+
+    for quote_atoms_per_quote_lot in [1, 10] {
+        let header = market_header(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            1_000_000,
+            quote_atoms_per_quote_lot,
+            1_000,
+        );
+        let jupiter_phoenix = JupiterPhoenix::new_from_keyed_account(&keyed_account(market_data(
+            &header, &ladder, 5,
+        )))
+        .unwrap();
+        let (base_mint, quote_mint) = (jupiter_phoenix.base_mint, jupiter_phoenix.quote_mint);
+        // Up to a little past the whole book on each side, including dust and repeats
+        for (input_mint, output_mint, max_in_amount) in [
+            (base_mint, quote_mint, 12_000_000_000),
+            (quote_mint, base_mint, 250_000_000),
+        ] {
+            let mut amounts: Vec<u64> = (0..200).map(|_| next(max_in_amount)).collect();
+            amounts.extend([0, 1, amounts[0], max_in_amount]);
+            let quotes = jupiter_phoenix
+                .quote_batch(&amounts, input_mint, output_mint)
+                .unwrap();
+            assert_eq!(quotes.len(), amounts.len());
+            for (in_amount, batch_quote) in amounts.iter().zip(quotes) {
+                let quote = jupiter_phoenix
+                    .quote(&QuoteParams {
+                        in_amount: *in_amount,
+                        input_mint,
+                        output_mint,
+                    })
+                    .unwrap();
+                assert_eq!(format!("{batch_quote:?}"), format!("{quote:?}"));
+            }
+        }
+    }
+
+    // Errors surface like they do from `quote`, and an empty batch is fine
+    let jupiter_phoenix = crate::test_utils::sol_usdc_market(
+        &Ladder {
+            bids: ladder.bids.clone(),
+            asks: vec![],
+        },
+        0,
+    );
+    let (base_mint, quote_mint) = (jupiter_phoenix.base_mint, jupiter_phoenix.quote_mint);
+    assert!(jupiter_phoenix
+        .quote_batch(&[0], quote_mint, base_mint)
+        .is_ok());
+    assert_eq!(
+        jupiter_phoenix
+            .quote_batch(&[0, 1_000_000], quote_mint, base_mint)
+            .unwrap_err()
+            .downcast_ref::<PhoenixAmmError>(),
+        Some(&PhoenixAmmError::EmptyBook)
+    );
+    assert!(jupiter_phoenix
+        .quote_batch(&[], base_mint, quote_mint)
+        .unwrap()
+        .is_empty());
+}
+
+/// Captures the mainnet SOL/USDC and BONK/USDC market accounts into `tests/fixtures`, each
+/// next to a file with its pubkey and the slot it was read at
+#[test]
+#[ignore = "hits mainnet RPC and rewrites the captured market fixtures"]
+fn capture_market_fixtures() {
+    use crate::test_utils::{CAPTURED_MARKET_FIXTURES, MARKET_FIXTURES_DIR};
+    use solana_client::rpc_client::RpcClient;
+    use solana_sdk::commitment_config::CommitmentConfig;
+
+    let rpc = RpcClient::new("https://api.mainnet-beta.solana.com/");
+    for (name, market_key) in CAPTURED_MARKET_FIXTURES {
+        let response = rpc
+            .get_account_with_commitment(&market_key, CommitmentConfig::finalized())
+            .unwrap();
+        let account = response.value.unwrap();
+        assert_eq!(account.owner, phoenix::id());
+        std::fs::write(format!("{MARKET_FIXTURES_DIR}/{name}.bin"), account.data).unwrap();
+        std::fs::write(
+            format!("{MARKET_FIXTURES_DIR}/{name}.txt"),
+            format!("{market_key}\n{}\n", response.context.slot),
+        )
+        .unwrap();
+    }
+}
+
+#[test]
+#[ignore = "needs the mainnet accounts capture_market_fixtures writes"]
+fn test_captured_market_fixtures() {
+    use crate::test_utils::{swap_params, CAPTURED_MARKET_FIXTURES, MARKET_FIXTURES_DIR};
+    use std::str::FromStr;
+
+    for (name, market_key) in CAPTURED_MARKET_FIXTURES {
+        let data = std::fs::read(format!("{MARKET_FIXTURES_DIR}/{name}.bin")).unwrap();
+        let provenance =
+            std::fs::read_to_string(format!("{MARKET_FIXTURES_DIR}/{name}.txt")).unwrap();
+        let mut provenance = provenance.lines();
+        assert_eq!(
+            Pubkey::from_str(provenance.next().unwrap()).unwrap(),
+            market_key
+        );
+        let slot: u64 = provenance.next().unwrap().parse().unwrap();
+        let jupiter_phoenix =
+            JupiterPhoenix::from_parts(market_key, &phoenix::id(), &data).unwrap();
+
+        // The header fields are read back against their offsets in the on-chain layout
+        let pubkey_at = |offset: usize| Pubkey::new(&data[offset..offset + 32]);
+        assert_eq!(
+            jupiter_phoenix.authority(),
+            pubkey_at(208),
+            "{name} at {slot}"
+        );
+        assert_eq!(jupiter_phoenix.market_fee_recipient(), pubkey_at(240));
+        assert_ne!(jupiter_phoenix.authority(), Pubkey::default());
+
+        let (base_mint, quote_mint) = (jupiter_phoenix.base_mint, jupiter_phoenix.quote_mint);
+        for (input_mint, output_mint) in [(base_mint, quote_mint), (quote_mint, base_mint)] {
+            jupiter_phoenix
+                .quote(&QuoteParams {
+                    in_amount: 1_000_000,
+                    input_mint,
+                    output_mint,
+                })
+                .unwrap();
+        }
+        jupiter_phoenix
+            .get_swap_leg_and_account_metas(&swap_params(base_mint, quote_mint))
+            .unwrap();
+    }
+}
+
+#[test]
+fn test_market_fixtures() {
+    use crate::test_utils::{swap_params, BONK_USDC_MARKET, SOL_USDC_MARKET};
+
+    const SOL_USDC_DATA: &[u8] = include_bytes!("../tests/fixtures/sol_usdc_market.bin");
+    const BONK_USDC_DATA: &[u8] = include_bytes!("../tests/fixtures/bonk_usdc_market.bin");
+
+    // (market, data, base decimals, sell in, sell out, sell fee, buy in, buy out, buy fee)
+    let cases = [
+        (
+            SOL_USDC_MARKET,
+            SOL_USDC_DATA,
+            9,
+            // 10 SOL: 8 SOL at 20 USDC and 2 SOL at 19.99 USDC, less 2 bps
+            10_000_000_000,
+            199_980_000 - 39_996,
+            39_996,
+            // 100 USDC: 4 SOL at 20.01 USDC and 0.996 SOL at 20.02 USDC, 2 bps included
+            100_000_000,
+            4_996_000_000,
+            19_996,
+        ),
+        (
+            BONK_USDC_MARKET,
+            BONK_USDC_DATA,
+            5,
+            // 80M BONK: 50M at 10 USDC and 30M at 9.99 USDC per million, less 2 bps
+            8_000_000_000_000,
+            799_700_000 - 159_940,
+            159_940,
+            // 1000 USDC: 30M BONK at 10.01 USDC and 69,810,383 at 10.02 USDC per million
+            1_000_000_000,
+            9_981_038_300_000,
+            199_961,
+        ),
+    ];
+    for (
+        market_key,
+        data,
+        base_decimals,
+        sell_in_amount,
+        sell_out_amount,
+        sell_fee_amount,
+        buy_in_amount,
+        buy_out_amount,
+        buy_fee_amount,
+    ) in cases
+    {
+        // `include_bytes!` is only byte aligned, unlike account data
+        let data: Vec<u8> = data.to_vec();
+        let jupiter_phoenix =
+            JupiterPhoenix::from_parts(market_key, &phoenix::id(), &data).unwrap();
+        assert_eq!(jupiter_phoenix.get_base_decimals(), base_decimals);
+        assert_eq!(jupiter_phoenix.get_quote_decimals(), 6);
+        let (base_mint, quote_mint) = (jupiter_phoenix.base_mint, jupiter_phoenix.quote_mint);
+
+        let sell = jupiter_phoenix
+            .quote(&QuoteParams {
+                in_amount: sell_in_amount,
+                input_mint: base_mint,
+                output_mint: quote_mint,
+            })
+            .unwrap();
+        assert_eq!(
+            (sell.out_amount, sell.fee_amount, sell.not_enough_liquidity),
+            (sell_out_amount, sell_fee_amount, false)
+        );
+        let buy = jupiter_phoenix
+            .quote(&QuoteParams {
+                in_amount: buy_in_amount,
+                input_mint: quote_mint,
+                output_mint: base_mint,
+            })
+            .unwrap();
+        assert_eq!(
+            (buy.out_amount, buy.fee_amount, buy.not_enough_liquidity),
+            (buy_out_amount, buy_fee_amount, false)
+        );
+
+        let SwapAndAccountMetas {
+            swap,
+            account_metas,
+        } = jupiter_phoenix
+            .get_swap_leg_and_account_metas(&swap_params(base_mint, quote_mint))
+            .unwrap();
+        assert!(matches!(swap, Swap::Serum { side: Side::Ask }));
+        assert_eq!(
+            account_metas
+                .iter()
+                .filter(|account_meta| [
+                    market_key,
+                    phoenix::id(),
+                    JupiterPhoenix::derive_vault(&phoenix::id(), &market_key, &base_mint),
+                    JupiterPhoenix::derive_vault(&phoenix::id(), &market_key, &quote_mint),
+                    spl_token::id(),
+                ]
+                .contains(&account_meta.pubkey))
+                .count(),
+            5
+        );
+    }
+}
+
+#[test]
+fn test_header_authority_and_fee_recipient() {
+    use crate::test_utils::{keyed_account, market_data, market_header, SOL_USDC_MARKET};
+
+    // The header fields are read back against their offsets in the on-chain layout: the
+    // discriminant, status and size params take 40 bytes, each token's params 72 and the lot
+    // and tick sizes 8 each, so the authority starts at byte 208
+    let data = include_bytes!("../tests/fixtures/sol_usdc_market.bin").to_vec();
+    let jupiter_phoenix =
+        JupiterPhoenix::from_parts(SOL_USDC_MARKET, &phoenix::id(), &data).unwrap();
+    let pubkey_at = |offset: usize| Pubkey::new(&data[offset..offset + 32]);
+    assert_ne!(pubkey_at(208), Pubkey::default());
+    assert_ne!(pubkey_at(208), pubkey_at(240));
+    assert_eq!(jupiter_phoenix.authority(), pubkey_at(208));
+    assert_eq!(jupiter_phoenix.market_fee_recipient(), pubkey_at(240));
+    assert_eq!(
+        jupiter_phoenix.market_sequence_number(),
+        Some(u64::from_le_bytes(data[272..280].try_into().unwrap()))
+    );
+    // The market's fee recipient has nothing to do with the referral one in the swap metas
+    assert_eq!(jupiter_phoenix.fee_recipient(), None);
+
+    // A new authority or fee recipient shows up on the next update, even with an unchanged book
+    let mut header = market_header(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        1_000_000,
+        1,
+        1_000,
+    );
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 19_999,
+            size_in_base_lots: 1_000,
+        }],
+        asks: vec![],
+    };
+    let keyed_account = keyed_account(market_data(&header, &ladder, 0));
+    let mut jupiter_phoenix = JupiterPhoenix::new_from_keyed_account(&keyed_account).unwrap();
+    assert_eq!(jupiter_phoenix.authority(), Pubkey::default());
+    let (authority, fee_recipient) = (Pubkey::new_unique(), Pubkey::new_unique());
+    header.authority = authority;
+    header.fee_recipient = fee_recipient;
+    let mut account = keyed_account.account.clone();
+    account.data[..size_of::<MarketHeader>()].copy_from_slice(bytemuck::bytes_of(&header));
+    jupiter_phoenix
+        .update(&HashMap::from([(
+            keyed_account.key,
+            PartialAccount::from(account),
+        )]))
+        .unwrap();
+    assert_eq!(jupiter_phoenix.authority(), authority);
+    assert_eq!(jupiter_phoenix.market_fee_recipient(), fee_recipient);
+}
+
+#[test]
+fn test_min_out_with_slippage() {
+    use crate::test_utils::{keyed_account, market_data, market_header, swap_params};
+    use borsh::BorshDeserialize;
+
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 19_999,
+            size_in_base_lots: 1_000,
+        }],
+        asks: vec![LadderOrder {
+            price_in_ticks: 20_001,
+            size_in_base_lots: 1_000,
+        }],
+    };
+    // 10 atom quote lots, so the minimum has to round to whole quote lots
+    let header = market_header(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        1_000_000,
+        10,
+        1_000,
+    );
+    let jupiter_phoenix =
+        JupiterPhoenix::new_from_keyed_account(&keyed_account(market_data(&header, &ladder, 0)))
+            .unwrap();
+    let (base_mint, quote_mint) = (jupiter_phoenix.base_mint, jupiter_phoenix.quote_mint);
+
+    let quote = Quote {
+        out_amount: 19_999_000,
+        ..Quote::default()
+    };
+    assert_eq!(jupiter_phoenix.min_out_with_slippage(&quote, 0), 19_999_000);
+    // 19_999_000 * 0.995 = 19_899_005
+    assert_eq!(
+        jupiter_phoenix.min_out_with_slippage(&quote, 50),
+        19_899_005
+    );
+    assert_eq!(jupiter_phoenix.min_out_with_slippage(&quote, 10_000), 0);
+    assert_eq!(jupiter_phoenix.min_out_with_slippage(&quote, u16::MAX), 0);
+    // Rounded down: 999 * 0.995 = 994.005
+    let quote = Quote {
+        out_amount: 999,
+        ..Quote::default()
+    };
+    assert_eq!(jupiter_phoenix.min_out_with_slippage(&quote, 50), 994);
+    let quote = Quote {
+        out_amount: u64::MAX,
+        ..Quote::default()
+    };
+    assert_eq!(jupiter_phoenix.min_out_with_slippage(&quote, 0), u64::MAX);
+
+    // The minimum lands in the packet rounded up to whole lots of the output mint
+    let min_fills = |source_mint, destination_mint, in_amount, min_out_amount| {
+        let mut params = swap_params(source_mint, destination_mint);
+        params.in_amount = in_amount;
+        let instruction = jupiter_phoenix
+            .swap_instruction_with_min_out(&params, None, min_out_amount)
+            .unwrap();
+        match OrderPacket::try_from_slice(&instruction.data[1..]).unwrap() {
+            OrderPacket::ImmediateOrCancel {
+                min_base_lots_to_fill,
+                min_quote_lots_to_fill,
+                ..
+            } => (
+                min_base_lots_to_fill.as_u64(),
+                min_quote_lots_to_fill.as_u64(),
+            ),
+            _ => panic!("Swaps are immediate-or-cancel orders"),
+        }
+    };
+    assert_eq!(
+        min_fills(base_mint, quote_mint, 1_000_000_000, 19_899_005),
+        (0, 1_989_901)
+    );
+    assert_eq!(
+        min_fills(quote_mint, base_mint, 20_001_000, 995_000_001),
+        (996, 0)
+    );
+    assert_eq!(min_fills(base_mint, quote_mint, 1_000_000_000, 0), (0, 0));
+}
+
+#[test]
+fn test_conversion_getters() {
+    use crate::test_utils::{keyed_account, market_fixtures, BONK_USDC_MARKET, SOL_USDC_MARKET};
+
+    // (market, base atoms per lot, quote atoms per lot, tick size, base lots per unit, ticks)
+    let cases = [
+        (SOL_USDC_MARKET, 1_000_000, 1, 1_000, 1_000, 20_000),
+        (BONK_USDC_MARKET, 100_000, 1, 1_000, 1_000_000, 10_000),
+    ];
+    for ((_, data), (market_key, base_lot, quote_lot, tick_size, lots_per_unit, price_in_ticks)) in
+        market_fixtures().into_iter().zip(cases)
+    {
+        let mut keyed_account = keyed_account(data);
+        keyed_account.key = market_key;
+        let jupiter_phoenix = JupiterPhoenix::new_from_keyed_account(&keyed_account).unwrap();
+        assert_eq!(jupiter_phoenix.base_atoms_per_base_lot(), base_lot);
+        assert_eq!(jupiter_phoenix.base_lot_size(), base_lot);
+        assert_eq!(jupiter_phoenix.quote_atoms_per_quote_lot(), quote_lot);
+        assert_eq!(jupiter_phoenix.quote_lot_size(), quote_lot);
+        assert_eq!(
+            jupiter_phoenix.tick_size_in_quote_atoms_per_base_unit(),
+            tick_size
+        );
+        assert_eq!(jupiter_phoenix.num_base_lots_per_base_unit(), lots_per_unit);
+
+        // A base unit's worth of lots at the top bid sells for the price of a base unit, 20 USDC
+        // per SOL and 10 USDC per million BONK, before fees
+        let base_lots = jupiter_phoenix.num_base_lots_per_base_unit();
+        let quote_atoms =
+            base_lots * price_in_ticks * jupiter_phoenix.tick_size_in_quote_atoms_per_base_unit()
+                / jupiter_phoenix.num_base_lots_per_base_unit();
+        let walk = jupiter_phoenix
+            .walk_ladder(
+                &Side::Ask,
+                base_lots * jupiter_phoenix.base_atoms_per_base_lot(),
+            )
+            .unwrap();
+        assert_eq!(walk.out_amount, quote_atoms);
+        assert_eq!(quote_atoms, price_in_ticks * tick_size);
+    }
+}
+
+#[test]
+fn test_quote_rejects_mints_outside_the_market() {
+    use crate::test_utils::{sol_usdc_market, swap_params};
+
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 19_999,
+            size_in_base_lots: 1_000,
+        }],
+        asks: vec![LadderOrder {
+            price_in_ticks: 20_001,
+            size_in_base_lots: 1_000,
+        }],
+    };
+    let jupiter_phoenix = sol_usdc_market(&ladder, 0);
+    let (base_mint, quote_mint) = (jupiter_phoenix.base_mint, jupiter_phoenix.quote_mint);
+    let other_mint = Pubkey::new_unique();
+
+    for (input_mint, output_mint, error) in [
+        (other_mint, base_mint, PhoenixAmmError::MintNotInMarket),
+        (other_mint, quote_mint, PhoenixAmmError::MintNotInMarket),
+        (base_mint, other_mint, PhoenixAmmError::InvalidQuoteMint),
+        (base_mint, base_mint, PhoenixAmmError::InvalidQuoteMint),
+        (quote_mint, other_mint, PhoenixAmmError::InvalidBaseMint),
+        (quote_mint, quote_mint, PhoenixAmmError::InvalidBaseMint),
+    ] {
+        let quote_params = QuoteParams {
+            in_amount: 1_000_000_000,
+            input_mint,
+            output_mint,
+        };
+        let downcast = |result: Result<_>| result.err().unwrap().downcast::<PhoenixAmmError>().ok();
+        assert_eq!(
+            downcast(jupiter_phoenix.quote(&quote_params).map(|_| ())),
+            Some(error)
+        );
+        assert_eq!(
+            downcast(jupiter_phoenix.quote_detailed(&quote_params).map(|_| ())),
+            Some(error)
+        );
+        assert_eq!(
+            downcast(
+                jupiter_phoenix
+                    .quote_batch(&[0, 1_000_000_000], input_mint, output_mint)
+                    .map(|_| ())
+            ),
+            Some(error)
+        );
+        #[cfg(feature = "decimal")]
+        assert_eq!(
+            downcast(jupiter_phoenix.quote_decimal(&quote_params).map(|_| ())),
+            Some(error)
+        );
+        // `quote_human` only takes the input mint and swaps it for the other one
+        if input_mint == other_mint {
+            assert_eq!(
+                downcast(jupiter_phoenix.quote_human(input_mint, 1.0).map(|_| ())),
+                Some(error)
+            );
+        }
+        // Even an empty swap from a foreign mint is rejected rather than quoted as zero
+        assert!(jupiter_phoenix
+            .quote(&QuoteParams {
+                in_amount: 0,
+                ..quote_params
+            })
+            .is_err());
+        if input_mint != output_mint {
+            assert_eq!(
+                downcast(
+                    jupiter_phoenix
+                        .get_swap_leg_and_account_metas(&swap_params(input_mint, output_mint))
+                        .map(|_| ())
+                ),
+                Some(error)
+            );
+        }
+    }
+
+    // The market's own pair still quotes in both directions
+    for (input_mint, output_mint) in [(base_mint, quote_mint), (quote_mint, base_mint)] {
+        assert!(
+            jupiter_phoenix
+                .quote(&QuoteParams {
+                    in_amount: 1_000_000,
+                    input_mint,
+                    output_mint,
+                })
+                .unwrap()
+                .out_amount
+                > 0
+        );
+    }
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn test_tracing_spans() {
+    use crate::test_utils::{keyed_account, market_data, market_header};
+    use phoenix::state::markets::{Ladder, LadderOrder};
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+    use tracing_core::span::Current;
+
+    /// Spans and their fields, formatted with `Debug`
+    type Spans = Arc<Mutex<Vec<(&'static Metadata<'static>, HashMap<&'static str, String>)>>>;
+
+    struct Fields<'a>(&'a mut HashMap<&'static str, String>);
+
+    impl Visit for Fields<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0.insert(field.name(), format!("{:?}", value));
+        }
+    }
+
+    /// Also tracks the entered spans, which `Span::current` asks the subscriber for
+    #[derive(Default)]
+    struct RecordingSubscriber {
+        spans: Spans,
+        entered: Mutex<Vec<Id>>,
+    }
+
+    impl Subscriber for RecordingSubscriber {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut spans = self.spans.lock().unwrap();
+            let mut fields = HashMap::new();
+            span.record(&mut Fields(&mut fields));
+            spans.push((span.metadata(), fields));
+            Id::from_u64(spans.len() as u64)
+        }
+        fn record(&self, span: &Id, values: &Record<'_>) {
+            let mut spans = self.spans.lock().unwrap();
+            values.record(&mut Fields(&mut spans[span.into_u64() as usize - 1].1));
+        }
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, _: &Event<'_>) {}
+        fn enter(&self, span: &Id) {
+            self.entered.lock().unwrap().push(span.clone());
+        }
+        fn exit(&self, _: &Id) {
+            self.entered.lock().unwrap().pop();
+        }
+        fn current_span(&self) -> Current {
+            match self.entered.lock().unwrap().last() {
+                Some(span) => {
+                    let (metadata, _) = self.spans.lock().unwrap()[span.into_u64() as usize - 1];
+                    Current::new(span.clone(), metadata)
+                }
+                None => Current::none(),
+            }
+        }
+    }
+
+    let level = |price_in_ticks| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots: 1_000,
+    };
+    let ladder = Ladder {
+        bids: vec![level(19_999), level(19_998)],
+        asks: vec![level(20_001), level(20_002), level(20_003)],
+    };
+    let header = market_header(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        1_000_000,
+        1,
+        1_000,
+    );
+    let keyed_account = keyed_account(market_data(&header, &ladder, 0));
+    let accounts_map = HashMap::from([(
+        keyed_account.key,
+        PartialAccount::from(keyed_account.account.clone()),
+    )]);
+    let mut jupiter_phoenix = JupiterPhoenix::new_from_keyed_account(&keyed_account).unwrap();
+    let [base_mint, quote_mint] =
+        <[Pubkey; 2]>::try_from(jupiter_phoenix.get_reserve_mints()).unwrap();
+
+    let subscriber = RecordingSubscriber::default();
+    let spans = subscriber.spans.clone();
+    tracing::subscriber::with_default(subscriber, || {
+        jupiter_phoenix.update(&accounts_map).unwrap();
+        // 1.5 SOL walks the first two bids
+        jupiter_phoenix
+            .quote(&QuoteParams {
+                in_amount: 1_500_000_000,
+                input_mint: base_mint,
+                output_mint: quote_mint,
+            })
+            .unwrap();
+    });
+
+    let spans = spans.lock().unwrap();
+    let market = keyed_account.key.to_string();
+    let [(update_metadata, update), (quote_metadata, quote)] =
+        <[_; 2]>::try_from(spans.clone()).unwrap();
+    assert_eq!(update_metadata.name(), "update");
+    assert_eq!(update["market"], market);
+    assert_eq!(update["levels"], "5");
+    assert!(update.contains_key("elapsed_us"));
+    assert_eq!(quote_metadata.name(), "quote");
+    assert_eq!(quote["market"], market);
+    assert_eq!(quote["direction"], "\"sell\"");
+    assert_eq!(quote["in_amount"], "1500000000");
+    assert_eq!(quote["levels_walked"], "2");
+    assert!(quote.contains_key("elapsed_us"));
+}
+
+#[test]
+fn test_cumulative_walk_matches_linear_walk() {
+    use crate::test_utils::{keyed_account, market_data, market_header};
+
+    // A fixed xorshift, so failures reproduce
+    let mut state = 0x2545_F491_4F6C_DD1Du64;
+    let mut next = move |bound: u64| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state % bound
+    };
+    // Irregular sizes and price gaps, with some empty levels
+    let num_levels = 300;
+    let mut bids = vec![];
+    let mut asks = vec![];
+    let (mut bid, mut ask) = (19_999, 20_001);
+    for _ in 0..num_levels {
+        bids.push(LadderOrder {
+            price_in_ticks: bid,
+            size_in_base_lots: next(4) * next(2_000),
+        });
+        asks.push(LadderOrder {
+            price_in_ticks: ask,
+            size_in_base_lots: next(4) * next(2_000),
+        });
+        bid -= 1 + next(40);
+        ask += 1 + next(40);
+    }
+    let ladder = Ladder { bids, asks };
+
+    for (quote_atoms_per_quote_lot, taker_fee_bps) in [(1, 0), (1, 5), (10, 2)] {
+        let header = market_header(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            1_000_000,
+            quote_atoms_per_quote_lot,
+            1_000,
+        );
+        let mut jupiter_phoenix = JupiterPhoenix::new_from_keyed_account_with_ladder_depth(
+            &keyed_account(market_data(&header, &ladder, taker_fee_bps)),
+            u64::MAX,
+        )
+        .unwrap();
+        for zero_priced_levels in [false, true] {
+            if zero_priced_levels {
+                // The market account cannot hold zero-priced orders, so they are injected
+                // directly: at the worst end of the bids and the best end of the asks
+                let zero = LadderOrder {
+                    price_in_ticks: 0,
+                    size_in_base_lots: 1_000,
+                };
+                Arc::make_mut(&mut jupiter_phoenix.ladder).bids.push(zero);
+                Arc::make_mut(&mut jupiter_phoenix.ladder)
+                    .asks
+                    .insert(0, zero);
+                jupiter_phoenix.cache_cumulative_liquidity();
+            }
+            let linear_ladder = Ladder::clone(&jupiter_phoenix.ladder);
+            let (base_mint, quote_mint) = (jupiter_phoenix.base_mint, jupiter_phoenix.quote_mint);
+            for (side, input_mint, output_mint, max_in_amount) in [
+                (Side::Ask, base_mint, quote_mint, 700_000_000_000),
+                (Side::Bid, quote_mint, base_mint, 15_000_000_000),
+            ] {
+                let walks = |in_amount| {
+                    let walk = |linear: bool| {
+                        let walk = if linear {
+                            jupiter_phoenix.walk_ladder_with_fee(
+                                &linear_ladder,
+                                &side,
+                                in_amount,
+                                taker_fee_bps,
+                                None,
+                                None,
+                            )
+                        } else {
+                            jupiter_phoenix.walk_ladder(&side, in_amount)
+                        };
+                        walk.map_err(|err| err.to_string())
+                    };
+                    (walk(false), walk(true))
+                };
+                // Up to past the whole book, plus the exact size of each partial walk and its
+                // neighbours, which land on level boundaries
+                for in_amount in (0..300).map(|_| next(max_in_amount)) {
+                    let (cumulative, linear) = walks(in_amount);
+                    assert_eq!(format!("{cumulative:?}"), format!("{linear:?}"));
+                    let consumed = linear.unwrap().in_amount_consumed;
+                    for in_amount in [consumed.saturating_sub(1), consumed, consumed + 1] {
+                        let (cumulative, linear) = walks(in_amount);
+                        assert_eq!(format!("{cumulative:?}"), format!("{linear:?}"));
+                    }
+
+                    let quote_params = QuoteParams {
+                        in_amount,
+                        input_mint,
+                        output_mint,
+                    };
+                    assert_eq!(
+                        format!("{:?}", jupiter_phoenix.quote(&quote_params).unwrap()),
+                        format!(
+                            "{:?}",
+                            jupiter_phoenix
+                                .quote_against_ladder(&linear_ladder, &quote_params)
+                                .unwrap()
+                        )
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_has_liquidity_and_is_quotable() {
+    use crate::test_utils::sol_usdc_market;
+
+    // Bids but no asks: base can be sold, but not bought
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 19_999,
+            size_in_base_lots: 1_000,
+        }],
+        asks: vec![],
+    };
+    let mut jupiter_phoenix = sol_usdc_market(&ladder, 0);
+    let (base_mint, quote_mint) = (jupiter_phoenix.base_mint, jupiter_phoenix.quote_mint);
+    assert!(jupiter_phoenix.has_liquidity(Side::Ask));
+    assert!(!jupiter_phoenix.has_liquidity(Side::Bid));
+    assert!(jupiter_phoenix.is_quotable(base_mint));
+    assert!(!jupiter_phoenix.is_quotable(quote_mint));
+    assert!(!jupiter_phoenix.is_quotable(Pubkey::new_unique()));
+
+    // The predicates agree with what quoting does
+    let quote = |input_mint, output_mint| {
+        jupiter_phoenix.quote(&QuoteParams {
+            in_amount: 1_000_000,
+            input_mint,
+            output_mint,
+        })
+    };
+    assert!(quote(base_mint, quote_mint).is_ok());
+    assert_eq!(
+        quote(quote_mint, base_mint)
+            .unwrap_err()
+            .downcast_ref::<PhoenixAmmError>(),
+        Some(&PhoenixAmmError::EmptyBook)
+    );
+
+    // Nothing is quotable on a market that rejects takers, whatever its book
+    jupiter_phoenix.market_status = MarketStatus::PostOnly;
+    assert!(jupiter_phoenix.has_liquidity(Side::Ask));
+    assert!(!jupiter_phoenix.is_quotable(base_mint));
+
+    jupiter_phoenix.market_status = MarketStatus::Active;
+    jupiter_phoenix.clear_ladder();
+    assert!(!jupiter_phoenix.has_liquidity(Side::Ask));
+    assert!(!jupiter_phoenix.is_quotable(base_mint));
+}
+
+#[test]
+fn test_market_owner_is_checked() {
+    use crate::test_utils::{keyed_account, market_data, market_header};
+
+    let header = market_header(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        1_000_000,
+        1,
+        1_000,
+    );
+    let ladder = Ladder {
+        bids: vec![LadderOrder {
+            price_in_ticks: 19_999,
+            size_in_base_lots: 1_000,
+        }],
+        asks: vec![],
+    };
+    let mut keyed_account = keyed_account(market_data(&header, &ladder, 0));
+    // Valid market data, but in an account the program does not own
+    keyed_account.account.owner = solana_sdk::system_program::id();
+    let owner_error = |result: Result<JupiterPhoenix>| {
+        result
+            .unwrap_err()
+            .downcast_ref::<PhoenixAmmError>()
+            .copied()
+    };
+    assert_eq!(
+        owner_error(JupiterPhoenix::new_from_keyed_account(&keyed_account)),
+        Some(PhoenixAmmError::InvalidMarketOwner)
+    );
+    assert_eq!(
+        owner_error(JupiterPhoenix::from_keyed_account(&keyed_account)),
+        Some(PhoenixAmmError::InvalidMarketOwner)
+    );
+    assert_eq!(
+        owner_error(JupiterPhoenix::from_parts(
+            keyed_account.key,
+            &keyed_account.account.owner,
+            &keyed_account.account.data
+        )),
+        Some(PhoenixAmmError::InvalidMarketOwner)
+    );
+    assert_eq!(
+        JupiterPhoenix::new_from_keyed_account(&keyed_account)
+            .unwrap_err()
+            .to_string(),
+        "Market account is not owned by the Phoenix program"
     );
+
+    // With a custom program id, only accounts of that program are markets
+    let program_id = Pubkey::new_unique();
+    let builder = JupiterPhoenix::builder().program_id(program_id);
+    keyed_account.account.owner = phoenix::id();
+    assert_eq!(
+        owner_error(builder.build_from_keyed_account(&keyed_account)),
+        Some(PhoenixAmmError::InvalidMarketOwner)
+    );
+    keyed_account.account.owner = program_id;
+    assert!(builder.build_from_keyed_account(&keyed_account).is_ok());
+    assert_eq!(
+        owner_error(JupiterPhoenix::new_from_keyed_account(&keyed_account)),
+        Some(PhoenixAmmError::InvalidMarketOwner)
+    );
+
+    // Refreshing from a system-owned account leaves the market as it was
+    keyed_account.account.owner = phoenix::id();
+    let mut jupiter_phoenix = JupiterPhoenix::new_from_keyed_account(&keyed_account).unwrap();
+    let mut account = keyed_account.account.clone();
+    account.owner = solana_sdk::system_program::id();
+    let refresh_error = jupiter_phoenix.refresh_metadata(&account).unwrap_err();
+    assert_eq!(
+        refresh_error.downcast_ref::<PhoenixAmmError>(),
+        Some(&PhoenixAmmError::InvalidMarketOwner)
+    );
+    assert_eq!(*jupiter_phoenix.ladder, ladder);
+    account.owner = phoenix::id();
+    jupiter_phoenix.refresh_metadata(&account).unwrap();
+}
+
+#[test]
+fn test_fillable_to_limit() {
+    use crate::test_utils::sol_usdc_market;
+
+    let level = |price_in_ticks, size_in_base_lots| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots,
+    };
+    let ladder = Ladder {
+        bids: vec![level(19_999, 1_000), level(19_990, 2_000)],
+        asks: vec![level(20_001, 1_500), level(20_010, 500)],
+    };
+    let mut jupiter_phoenix = sol_usdc_market(&ladder, 0);
+
+    // A limit between the two levels only counts the better one
+    assert_eq!(
+        jupiter_phoenix.fillable_to_limit(Side::Ask, 19_995),
+        (1_000_000_000, 19_999_000)
+    );
+    assert_eq!(
+        jupiter_phoenix.fillable_to_limit(Side::Bid, 20_005),
+        (1_500_000_000, 30_001_500)
+    );
+    // Levels right at the limit are included
+    assert_eq!(
+        jupiter_phoenix.fillable_to_limit(Side::Ask, 19_990),
+        (3_000_000_000, 19_999_000 + 39_980_000)
+    );
+    assert_eq!(
+        jupiter_phoenix.fillable_to_limit(Side::Bid, 20_010),
+        (2_000_000_000, 30_001_500 + 10_005_000)
+    );
+    // A limit beyond the whole side gives all of it, one short of the top of book nothing
+    assert_eq!(
+        jupiter_phoenix.fillable_to_limit(Side::Ask, 0),
+        jupiter_phoenix.fillable_to_limit(Side::Ask, 19_990)
+    );
+    assert_eq!(
+        jupiter_phoenix.fillable_to_limit(Side::Bid, u64::MAX),
+        jupiter_phoenix.fillable_to_limit(Side::Bid, 20_010)
+    );
+    assert_eq!(jupiter_phoenix.fillable_to_limit(Side::Ask, 20_000), (0, 0));
+    assert_eq!(jupiter_phoenix.fillable_to_limit(Side::Bid, 20_000), (0, 0));
+
+    jupiter_phoenix.clear_ladder();
+    assert_eq!(jupiter_phoenix.fillable_to_limit(Side::Ask, 0), (0, 0));
+    assert_eq!(
+        jupiter_phoenix.fillable_to_limit(Side::Bid, u64::MAX),
+        (0, 0)
+    );
+}
+
+#[test]
+fn test_clone_amm_shares_the_ladder() {
+    use crate::test_utils::{keyed_account, market_data, market_header};
+
+    let level = |price_in_ticks, size_in_base_lots| LadderOrder {
+        price_in_ticks,
+        size_in_base_lots,
+    };
+    let ladder = Ladder {
+        bids: vec![level(19_999, 1_000), level(19_990, 2_000)],
+        asks: vec![level(20_001, 1_500), level(20_010, 500)],
+    };
+    let mut header = market_header(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        1_000_000,
+        1,
+        1_000,
+    );
+    let keyed_account = keyed_account(market_data(&header, &ladder, 2));
+    let jupiter_phoenix = JupiterPhoenix::new_from_keyed_account(&keyed_account).unwrap();
+    let (base_mint, quote_mint) = (jupiter_phoenix.base_mint, jupiter_phoenix.quote_mint);
+    // Both within the book and past the end of it
+    let quote_params = [
+        (base_mint, quote_mint, 2_000_000_000),
+        (base_mint, quote_mint, 5_000_000_000),
+        (quote_mint, base_mint, 40_000_000),
+        (quote_mint, base_mint, 100_000_000),
+    ]
+    .map(|(input_mint, output_mint, in_amount)| QuoteParams {
+        in_amount,
+        input_mint,
+        output_mint,
+    });
+    let quotes = |amm: &dyn Amm| {
+        quote_params
+            .iter()
+            .map(|params| format!("{:?}", amm.quote(params)))
+            .collect::<Vec<_>>()
+    };
+    let original_quotes = quotes(&jupiter_phoenix);
+
+    let cloned_amm = jupiter_phoenix.clone_amm();
+    assert_eq!(quotes(cloned_amm.as_ref()), original_quotes);
+    assert_eq!(cloned_amm.key(), jupiter_phoenix.key());
+    assert_eq!(
+        cloned_amm.get_reserve_mints(),
+        jupiter_phoenix.get_reserve_mints()
+    );
+
+    // Clones share the ladder and its running totals until one of them reloads them
+    let mut cloned = jupiter_phoenix.clone();
+    assert!(Arc::ptr_eq(&cloned.ladder, &jupiter_phoenix.ladder));
+    assert!(Arc::ptr_eq(
+        &cloned.cumulative_liquidity,
+        &jupiter_phoenix.cumulative_liquidity
+    ));
+    header.market_sequence_number += 1;
+    let thinner = Ladder {
+        bids: vec![level(19_999, 500)],
+        asks: vec![level(20_001, 500)],
+    };
+    let accounts_map = HashMap::from([(
+        keyed_account.key,
+        PartialAccount::from(Account {
+            data: market_data(&header, &thinner, 2),
+            ..keyed_account.account.clone()
+        }),
+    )]);
+    cloned.update(&accounts_map).unwrap();
+    assert_eq!(*cloned.ladder, thinner);
+    assert_ne!(quotes(&cloned), original_quotes);
+    assert_eq!(*jupiter_phoenix.ladder, ladder);
+    assert_eq!(quotes(&jupiter_phoenix), original_quotes);
+    assert_eq!(quotes(cloned_amm.as_ref()), original_quotes);
 }
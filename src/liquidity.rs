@@ -0,0 +1,78 @@
+//! Running totals of the cached ladder's liquidity, so that quotes against deep books can binary
+//! search for the levels they clear instead of walking every one of them.
+
+use phoenix::state::markets::{Ladder, LadderOrder};
+
+/// The `CumulativeSide`s of a ladder's bids and asks
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CumulativeLiquidity {
+    pub(crate) bids: CumulativeSide,
+    pub(crate) asks: CumulativeSide,
+}
+
+impl CumulativeLiquidity {
+    /// `quote_atoms` is what a fill of some base lots at a price in ticks pays, or `None` if
+    /// that overflows a u64
+    pub(crate) fn new(ladder: &Ladder, quote_atoms: impl Fn(u64, u64) -> Option<u64>) -> Self {
+        Self {
+            bids: CumulativeSide::new(&ladder.bids, &quote_atoms),
+            asks: CumulativeSide::new(&ladder.asks, &quote_atoms),
+        }
+    }
+}
+
+/// Totals over the levels of one side resting before each level, best price first, with one
+/// more entry for the whole side. Zero-priced levels, which walks skip, add nothing. Totals
+/// that overflow saturate.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CumulativeSide {
+    pub(crate) base_lots: Vec<u64>,
+    /// Base lots times price in ticks
+    pub(crate) lot_ticks: Vec<u128>,
+    /// Quote atoms a taker receives for each level, each rounded down on its own like a walk
+    /// does
+    pub(crate) quote_atoms: Vec<u128>,
+    /// Number of priced levels
+    pub(crate) priced_levels: Vec<usize>,
+}
+
+impl CumulativeSide {
+    fn new(levels: &[LadderOrder], quote_atoms: impl Fn(u64, u64) -> Option<u64>) -> Self {
+        let mut side = Self {
+            base_lots: Vec::with_capacity(levels.len() + 1),
+            lot_ticks: Vec::with_capacity(levels.len() + 1),
+            quote_atoms: Vec::with_capacity(levels.len() + 1),
+            priced_levels: Vec::with_capacity(levels.len() + 1),
+        };
+        let (mut base_lots, mut lot_ticks, mut total_quote_atoms, mut priced_levels) =
+            (0u64, 0u128, 0u128, 0);
+        for level in levels {
+            side.push(base_lots, lot_ticks, total_quote_atoms, priced_levels);
+            if level.price_in_ticks == 0 {
+                continue;
+            }
+            base_lots = base_lots.saturating_add(level.size_in_base_lots);
+            lot_ticks = lot_ticks
+                .saturating_add(level.size_in_base_lots as u128 * level.price_in_ticks as u128);
+            total_quote_atoms = total_quote_atoms.saturating_add(
+                quote_atoms(level.size_in_base_lots, level.price_in_ticks)
+                    .map_or(u128::MAX, u128::from),
+            );
+            priced_levels += 1;
+        }
+        side.push(base_lots, lot_ticks, total_quote_atoms, priced_levels);
+        side
+    }
+
+    fn push(&mut self, base_lots: u64, lot_ticks: u128, quote_atoms: u128, priced_levels: usize) {
+        self.base_lots.push(base_lots);
+        self.lot_ticks.push(lot_ticks);
+        self.quote_atoms.push(quote_atoms);
+        self.priced_levels.push(priced_levels);
+    }
+
+    /// Number of levels the totals are over, zero for a default (never built) side
+    pub(crate) fn num_levels(&self) -> usize {
+        self.base_lots.len().saturating_sub(1)
+    }
+}
@@ -0,0 +1,114 @@
+//! Serde support for caching a hydrated `JupiterPhoenix` across restarts, behind the `serde`
+//! feature. The upstream ladder, metadata and market status types have no serde derives, so
+//! they go through the mirrors here.
+
+use std::sync::Arc;
+
+use phoenix::program::status::MarketStatus;
+use phoenix::program::MarketSizeParams;
+use phoenix::state::markets::{Ladder, LadderOrder};
+use phoenix_sdk_core::sdk_client_core::MarketMetadata;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use solana_sdk::pubkey::Pubkey;
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "MarketSizeParams")]
+struct MarketSizeParamsDef {
+    bids_size: u64,
+    asks_size: u64,
+    num_seats: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "MarketMetadata")]
+pub(crate) struct MarketMetadataDef {
+    base_mint: Pubkey,
+    quote_mint: Pubkey,
+    base_decimals: u32,
+    quote_decimals: u32,
+    base_atoms_per_raw_base_unit: u64,
+    quote_atoms_per_quote_unit: u64,
+    quote_atoms_per_quote_lot: u64,
+    base_atoms_per_base_lot: u64,
+    tick_size_in_quote_atoms_per_base_unit: u64,
+    num_base_lots_per_base_unit: u64,
+    raw_base_units_per_base_unit: u32,
+    #[serde(with = "MarketSizeParamsDef")]
+    market_size_params: MarketSizeParams,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LadderOrderDef {
+    price_in_ticks: u64,
+    size_in_base_lots: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LadderDef {
+    bids: Vec<LadderOrderDef>,
+    asks: Vec<LadderOrderDef>,
+}
+
+/// (De)serializes a shared `Ladder` as its bids and asks, best first
+pub(crate) mod ladder {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(ladder: &Ladder, serializer: S) -> Result<S::Ok, S::Error> {
+        let levels = |orders: &[LadderOrder]| {
+            orders
+                .iter()
+                .map(|order| LadderOrderDef {
+                    price_in_ticks: order.price_in_ticks,
+                    size_in_base_lots: order.size_in_base_lots,
+                })
+                .collect()
+        };
+        LadderDef {
+            bids: levels(&ladder.bids),
+            asks: levels(&ladder.asks),
+        }
+        .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Arc<Ladder>, D::Error> {
+        let levels = |orders: Vec<LadderOrderDef>| {
+            orders
+                .into_iter()
+                .map(|order| LadderOrder {
+                    price_in_ticks: order.price_in_ticks,
+                    size_in_base_lots: order.size_in_base_lots,
+                })
+                .collect()
+        };
+        let LadderDef { bids, asks } = LadderDef::deserialize(deserializer)?;
+        Ok(Arc::new(Ladder {
+            bids: levels(bids),
+            asks: levels(asks),
+        }))
+    }
+}
+
+/// (De)serializes a `MarketStatus` as the u64 the market header stores it as
+pub(crate) mod market_status {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        status: &MarketStatus,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        (*status as u64).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<MarketStatus, D::Error> {
+        let status = u64::deserialize(deserializer)?;
+        // `MarketStatus::from` panics on unknown statuses
+        if status > MarketStatus::Tombstoned as u64 {
+            return Err(de::Error::custom(format!("Invalid market status {status}")));
+        }
+        Ok(MarketStatus::from(status))
+    }
+}
@@ -0,0 +1,235 @@
+//! Synthetic Phoenix market accounts for unit tests that must not hit RPC.
+
+use std::mem::size_of;
+
+use crate::JupiterPhoenix;
+use jupiter_core::amm::{KeyedAccount, SwapParams};
+use phoenix::program::status::MarketStatus;
+use phoenix::program::{MarketHeader, MarketSizeParams, TokenParams};
+use phoenix::quantities::{
+    BaseAtomsPerBaseLot, BaseLots, BaseLotsPerBaseUnit, QuoteAtomsPerBaseUnitPerTick,
+    QuoteAtomsPerQuoteLot, QuoteLotsPerBaseUnitPerTick, WrapperU64,
+};
+use phoenix::state::markets::{FIFOMarket, FIFOOrderId, FIFORestingOrder, Ladder, LadderOrder};
+use sokoban::{FromSlice, NodeAllocatorMap};
+use solana_sdk::{account::Account, pubkey, pubkey::Pubkey};
+
+type TestMarket = FIFOMarket<Pubkey, 512, 512, 128>;
+
+/// Builds an active header with SOL/USDC-like decimals (9 and 6) and the given lot and tick
+/// sizes.
+pub fn market_header(
+    base_mint: Pubkey,
+    quote_mint: Pubkey,
+    base_atoms_per_base_lot: u64,
+    quote_atoms_per_quote_lot: u64,
+    tick_size_in_quote_atoms_per_base_unit: u64,
+) -> MarketHeader {
+    let mut header = MarketHeader::new(
+        MarketSizeParams {
+            bids_size: 512,
+            asks_size: 512,
+            num_seats: 128,
+        },
+        TokenParams {
+            decimals: 9,
+            vault_bump: 0,
+            mint_key: base_mint,
+            vault_key: Pubkey::default(),
+        },
+        BaseAtomsPerBaseLot::new(base_atoms_per_base_lot),
+        TokenParams {
+            decimals: 6,
+            vault_bump: 0,
+            mint_key: quote_mint,
+            vault_key: Pubkey::default(),
+        },
+        QuoteAtomsPerQuoteLot::new(quote_atoms_per_quote_lot),
+        QuoteAtomsPerBaseUnitPerTick::new(tick_size_in_quote_atoms_per_base_unit),
+        Pubkey::default(),
+        Pubkey::default(),
+        Pubkey::default(),
+        1,
+    );
+    // New markets start out post-only
+    header.status = MarketStatus::Active as u64;
+    header
+}
+
+/// A SOL/USDC-like market (0.001 SOL base lots, 1 atom quote lots, 0.001 USDC ticks)
+pub fn sol_usdc_market(ladder: &Ladder, taker_fee_bps: u64) -> JupiterPhoenix {
+    let header = market_header(
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        1_000_000,
+        1,
+        1_000,
+    );
+    JupiterPhoenix::new_from_keyed_account(&keyed_account(market_data(
+        &header,
+        ladder,
+        taker_fee_bps,
+    )))
+    .unwrap()
+}
+
+/// Serializes `header` followed by a market whose resting orders form `ladder`.
+pub fn market_data(header: &MarketHeader, ladder: &Ladder, taker_fee_bps: u64) -> Vec<u8> {
+    let resting_orders = |levels: &[LadderOrder]| -> Vec<(u64, FIFORestingOrder)> {
+        levels
+            .iter()
+            .map(|level| {
+                (
+                    level.price_in_ticks,
+                    FIFORestingOrder::new_default(0, BaseLots::new(level.size_in_base_lots)),
+                )
+            })
+            .collect()
+    };
+    market_data_with_resting_orders(
+        header,
+        &resting_orders(&ladder.bids),
+        &resting_orders(&ladder.asks),
+        taker_fee_bps,
+    )
+}
+
+/// Like `market_data`, but from `(price_in_ticks, order)` pairs, for orders with expiries.
+/// Orders at the same price rest in the order given.
+pub fn market_data_with_resting_orders(
+    header: &MarketHeader,
+    bids: &[(u64, FIFORestingOrder)],
+    asks: &[(u64, FIFORestingOrder)],
+    taker_fee_bps: u64,
+) -> Vec<u8> {
+    let mut data = vec![0; size_of::<MarketHeader>() + size_of::<TestMarket>()];
+    let (header_bytes, market_bytes) = data.split_at_mut(size_of::<MarketHeader>());
+    header_bytes.copy_from_slice(bytemuck::bytes_of(header));
+
+    let base_atoms_per_base_unit =
+        10u64.pow(header.base_params.decimals) * header.raw_base_units_per_base_unit.max(1) as u64;
+    let market = TestMarket::new_from_slice(market_bytes);
+    market.base_lots_per_base_unit = BaseLotsPerBaseUnit::new(
+        base_atoms_per_base_unit
+            .checked_div(header.get_base_lot_size().as_u64())
+            .unwrap_or_default(),
+    );
+    market.tick_size_in_quote_lots_per_base_unit = QuoteLotsPerBaseUnitPerTick::new(
+        header
+            .get_tick_size_in_quote_atoms_per_base_unit()
+            .as_u64()
+            .checked_div(header.get_quote_lot_size().as_u64())
+            .unwrap_or_default(),
+    );
+    market.taker_fee_bps = taker_fee_bps;
+
+    let mut sequence_number = 0;
+    for (price_in_ticks, resting_order) in bids.iter() {
+        sequence_number += 1;
+        // Bid sequence numbers have their bits inverted, see `FIFOOrderId`
+        market.bids.insert(
+            FIFOOrderId::new_from_untyped(*price_in_ticks, !sequence_number),
+            *resting_order,
+        );
+    }
+    for (price_in_ticks, resting_order) in asks.iter() {
+        sequence_number += 1;
+        market.asks.insert(
+            FIFOOrderId::new_from_untyped(*price_in_ticks, sequence_number),
+            *resting_order,
+        );
+    }
+    data
+}
+
+pub const MARKET_FIXTURES_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+/// Mainnet markets that `capture_market_fixtures` captures, by file stem
+pub const CAPTURED_MARKET_FIXTURES: [(&str, Pubkey); 2] = [
+    ("mainnet_sol_usdc_market", SOL_USDC_MARKET),
+    ("mainnet_bonk_usdc_market", BONK_USDC_MARKET),
+];
+pub const SOL_USDC_MARKET: Pubkey = pubkey!("4DoNfFBfF7UokCC2FQzriy7yHK6DY6NVdYpuekQ5pRgg");
+pub const BONK_USDC_MARKET: Pubkey = pubkey!("GBMoNx84HsFdVK63t8BZuDgyZhSBaeKWB4pHHpoeRM9z");
+const SOL_MINT: Pubkey = pubkey!("So11111111111111111111111111111111111111112");
+const BONK_MINT: Pubkey = pubkey!("DezXAZ8z7PnrnRJjz3wXBoRgixCa6xjnB7YaB1pPB263");
+const USDC_MINT: Pubkey = pubkey!("EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v");
+/// Made-up authority and fee recipient of the synthetic fixture markets
+const FIXTURE_AUTHORITY: Pubkey = pubkey!("2Wjjj8Nvrjwki3Ac9wcENHVBL7iHpgS3Pubm1hPJtKSa");
+const FIXTURE_FEE_RECIPIENT: Pubkey = pubkey!("3zUgmWxMDQxgHLcDiB3GbRCFRhHj3R227gP9xoAi7fRf");
+
+/// The market accounts checked into `tests/fixtures`, by file name. They are built here rather
+/// than captured from RPC, with the mainnet markets' mints and SOL/USDC- and BONK/USDC-like
+/// parameters: BONK has 5 decimals and a base unit of a million BONK, where SOL has 9 and a
+/// base unit of one SOL. Their header values are made up too.
+pub fn market_fixtures() -> [(&'static str, Vec<u8>); 2] {
+    let order = |num_base_lots| FIFORestingOrder::new_default(0, BaseLots::new(num_base_lots));
+
+    // 0.001 SOL base lots, 1 atom quote lots, 0.001 USDC ticks
+    let mut sol_usdc = market_header(SOL_MINT, USDC_MINT, 1_000_000, 1, 1_000);
+    sol_usdc.authority = FIXTURE_AUTHORITY;
+    sol_usdc.fee_recipient = FIXTURE_FEE_RECIPIENT;
+    sol_usdc.market_sequence_number = 1_234;
+    let sol_usdc = market_data_with_resting_orders(
+        &sol_usdc,
+        &[
+            (20_000, order(5_000)),
+            (20_000, order(3_000)),
+            (19_990, order(10_000)),
+            (19_950, order(50_000)),
+        ],
+        &[
+            (20_010, order(4_000)),
+            (20_020, order(6_000)),
+            (20_020, order(2_000)),
+            (20_100, order(40_000)),
+        ],
+        2,
+    );
+
+    // 1 BONK base lots, 1 atom quote lots, 0.001 USDC ticks per million BONK
+    let mut bonk_usdc = market_header(BONK_MINT, USDC_MINT, 100_000, 1, 1_000);
+    bonk_usdc.base_params.decimals = 5;
+    bonk_usdc.raw_base_units_per_base_unit = 1_000_000;
+    bonk_usdc.authority = FIXTURE_AUTHORITY;
+    bonk_usdc.fee_recipient = FIXTURE_FEE_RECIPIENT;
+    bonk_usdc.market_sequence_number = 5_678;
+    let bonk_usdc = market_data_with_resting_orders(
+        &bonk_usdc,
+        &[(10_000, order(50_000_000)), (9_990, order(100_000_000))],
+        &[(10_010, order(30_000_000)), (10_020, order(200_000_000))],
+        2,
+    );
+
+    [
+        ("sol_usdc_market.bin", sol_usdc),
+        ("bonk_usdc_market.bin", bonk_usdc),
+    ]
+}
+
+pub fn keyed_account(data: Vec<u8>) -> KeyedAccount {
+    KeyedAccount {
+        key: Pubkey::new_unique(),
+        account: Account {
+            lamports: 0,
+            data,
+            owner: phoenix::id(),
+            executable: false,
+            rent_epoch: 0,
+        },
+        params: None,
+    }
+}
+
+/// Swap params for a user swapping `source_mint` into `destination_mint` with fresh accounts
+pub fn swap_params(source_mint: Pubkey, destination_mint: Pubkey) -> SwapParams<'static> {
+    SwapParams {
+        source_mint,
+        destination_mint,
+        user_source_token_account: Pubkey::new_unique(),
+        user_destination_token_account: Pubkey::new_unique(),
+        user_transfer_authority: Pubkey::new_unique(),
+        open_order_address: None,
+        quote_mint_to_referrer: None,
+        in_amount: 0,
+    }
+}
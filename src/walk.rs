@@ -0,0 +1,277 @@
+//! The walk of one side of a ladder that every quote is built from, resumable so that quotes of
+//! many inputs can share the levels they all clear.
+
+use crate::liquidity::CumulativeSide;
+use crate::{FillLevel, JupiterPhoenix, LadderWalk, PhoenixAmmError};
+use anyhow::Result;
+use jupiter::Side;
+use phoenix::state::markets::{Ladder, LadderOrder};
+
+/// A position in the levels a taker on `side` fills against. Budgets are in the units the
+/// program matches in: base lots for sells, and for buys adjusted quote lots (quote lots times
+/// base lots per base unit), the unit a lot times a price comes out in.
+///
+/// Each `take` picks up where the last one stopped, partway into a level if need be, and adds
+/// its fills to a `LadderWalk`. A single quote takes once from the top of the book, consecutive
+/// swaps take one after another, and increasing inputs `take_whole_levels` to keep only the
+/// levels every larger input clears too.
+#[derive(Clone)]
+pub(crate) struct LadderWalker<'a> {
+    market: &'a JupiterPhoenix,
+    side: &'a Side,
+    levels: &'a [LadderOrder],
+    taker_fee_bps: u64,
+    /// The first level that earlier takes have not taken in full
+    next_level: usize,
+    /// Base lots earlier takes filled at `levels[next_level]`
+    taken_from_next_level: u64,
+}
+
+impl<'a> LadderWalker<'a> {
+    /// A walker at the top of `ladder`, charging `taker_fee_bps`. Fails if the market does not
+    /// accept takers.
+    pub(crate) fn new(
+        market: &'a JupiterPhoenix,
+        ladder: &'a Ladder,
+        side: &'a Side,
+        taker_fee_bps: u64,
+    ) -> Result<Self> {
+        if !market.is_swappable() {
+            return Err(PhoenixAmmError::MarketNotSwappable.into());
+        }
+        let levels = match side {
+            Side::Ask => &ladder.bids,
+            Side::Bid => &ladder.asks,
+        };
+        Ok(Self {
+            market,
+            side,
+            levels,
+            taker_fee_bps,
+            next_level: 0,
+            taken_from_next_level: 0,
+        })
+    }
+
+    pub(crate) fn levels(&self) -> &'a [LadderOrder] {
+        self.levels
+    }
+
+    /// The budget a swap of `in_amount` atoms matches with. Buys have it shrunk by the taker
+    /// fee up front, matching `FIFOMarket::adjusted_quote_lot_budget_post_fee_adjustment_for_buys`.
+    pub(crate) fn budget(&self, in_amount: u64) -> Result<u128> {
+        let market = self.market;
+        match self.side {
+            Side::Ask => Ok(market.input_lots(in_amount, market.base_atoms_per_base_lot) as u128),
+            Side::Bid => {
+                // The program holds adjusted quote lots in a u64, so larger budgets cannot swap
+                let adjusted_quote_lots_in =
+                    market.input_lots(in_amount, market.quote_atoms_per_quote_lot) as u128
+                        * market.num_base_lots_per_base_unit as u128;
+                if adjusted_quote_lots_in > u64::MAX as u128 {
+                    return Err(PhoenixAmmError::AmountOverflow.into());
+                }
+                let fee_adjustment = u64::MAX as u128
+                    + (u64::MAX as u128 * self.taker_fee_bps as u128).div_ceil(10000);
+                Ok(adjusted_quote_lots_in * u64::MAX as u128 / fee_adjustment)
+            }
+        }
+    }
+
+    /// Takes the levels before the last one `budget` reaches, all cleared in full, from the
+    /// `cumulative` totals of the side at once, and returns the budget they spent. Only valid
+    /// from the top of the book.
+    pub(crate) fn take_cleared(
+        &mut self,
+        walk: &mut LadderWalk,
+        cumulative: &CumulativeSide,
+        budget: u128,
+    ) -> Result<u128> {
+        debug_assert!(self.next_level == 0 && self.taken_from_next_level == 0);
+        let levels = &cumulative.base_lots[..self.levels.len()];
+        let quote_lots_per_base_unit_per_tick = self.quote_lots_per_base_unit_per_tick();
+        let adjusted_quote_lots =
+            |lot_ticks: u128| lot_ticks.saturating_mul(quote_lots_per_base_unit_per_tick);
+        let cleared_levels = match self.side {
+            Side::Ask => levels.partition_point(|&base_lots| (base_lots as u128) < budget),
+            Side::Bid => cumulative.lot_ticks[..levels.len()]
+                .partition_point(|&lot_ticks| adjusted_quote_lots(lot_ticks) < budget),
+        }
+        .saturating_sub(1);
+
+        let base_lots = cumulative.base_lots[cleared_levels];
+        walk.matched_lot_ticks = cumulative.lot_ticks[cleared_levels];
+        walk.levels_touched = cumulative.priced_levels[cleared_levels];
+        // The price of the worst level cleared with anything to take
+        walk.last_price_in_ticks = self.levels[..cleared_levels]
+            .iter()
+            .rev()
+            .find(|level| level.price_in_ticks != 0 && level.size_in_base_lots != 0)
+            .map_or(0, |level| level.price_in_ticks);
+        self.next_level = cleared_levels;
+        match self.side {
+            Side::Ask => {
+                walk.out_amount = u64::try_from(cumulative.quote_atoms[cleared_levels])
+                    .map_err(|_| PhoenixAmmError::AmountOverflow)?;
+                walk.in_amount_consumed = base_lots * self.market.base_atoms_per_base_lot;
+                Ok(base_lots as u128)
+            }
+            Side::Bid => {
+                walk.out_amount = base_lots
+                    .checked_mul(self.market.base_atoms_per_base_lot)
+                    .ok_or(PhoenixAmmError::AmountOverflow)?;
+                Ok(adjusted_quote_lots(walk.matched_lot_ticks))
+            }
+        }
+    }
+
+    /// Fills as much as `budget` buys into `walk`, pushing each level taken from onto `fills`,
+    /// and returns the budget left over. A fill that stops partway into a level uses up the
+    /// budget: what is left cannot buy a whole lot there, let alone at a worse price.
+    pub(crate) fn take(
+        &mut self,
+        walk: &mut LadderWalk,
+        budget: u128,
+        fills: Option<&mut Vec<FillLevel>>,
+    ) -> Result<u128> {
+        self.take_levels(walk, budget, true, fills)
+    }
+
+    /// Like `take`, but stops before the first level `budget` cannot clear in full
+    pub(crate) fn take_whole_levels(
+        &mut self,
+        walk: &mut LadderWalk,
+        budget: u128,
+    ) -> Result<u128> {
+        self.take_levels(walk, budget, false, None)
+    }
+
+    fn take_levels(
+        &mut self,
+        walk: &mut LadderWalk,
+        mut budget: u128,
+        partial: bool,
+        mut fills: Option<&mut Vec<FillLevel>>,
+    ) -> Result<u128> {
+        let quote_lots_per_base_unit_per_tick = self.quote_lots_per_base_unit_per_tick();
+        while let Some(level) = self.levels.get(self.next_level).filter(|_| budget > 0) {
+            let budget_per_base_lot = match self.side {
+                Side::Ask => 1,
+                Side::Bid => level.price_in_ticks as u128 * quote_lots_per_base_unit_per_tick,
+            };
+            // No real order rests at a zero price, and it cannot be divided by
+            if level.price_in_ticks == 0 || budget_per_base_lot == 0 {
+                self.next_level += 1;
+                continue;
+            }
+            let available_base_lots = level.size_in_base_lots - self.taken_from_next_level;
+            let base_lots = (available_base_lots as u128).min(budget / budget_per_base_lot) as u64;
+            let whole_level = base_lots == available_base_lots;
+            if !whole_level && !partial {
+                break;
+            }
+
+            walk.levels_touched += 1;
+            self.fill(
+                walk,
+                level.price_in_ticks,
+                base_lots,
+                fills.as_deref_mut(),
+                !whole_level,
+            )?;
+            if whole_level {
+                budget -= base_lots as u128 * budget_per_base_lot;
+                self.next_level += 1;
+                self.taken_from_next_level = 0;
+            } else {
+                self.taken_from_next_level += base_lots;
+                budget = 0;
+            }
+        }
+        Ok(budget)
+    }
+
+    /// Adds a fill of `base_lots` at `price_in_ticks` to `walk`
+    fn fill(
+        &self,
+        walk: &mut LadderWalk,
+        price_in_ticks: u64,
+        base_lots: u64,
+        fills: Option<&mut Vec<FillLevel>>,
+        partial: bool,
+    ) -> Result<()> {
+        let market = self.market;
+        if base_lots > 0 {
+            walk.last_price_in_ticks = price_in_ticks;
+        }
+        walk.matched_lot_ticks += base_lots as u128 * price_in_ticks as u128;
+        let quote_atoms = market.quote_atoms_for_base_lots(base_lots, price_in_ticks);
+        let out_atoms = match self.side {
+            Side::Ask => {
+                walk.in_amount_consumed += base_lots * market.base_atoms_per_base_lot;
+                quote_atoms
+            }
+            Side::Bid => base_lots.checked_mul(market.base_atoms_per_base_lot),
+        };
+        walk.out_amount = out_atoms
+            .and_then(|out_atoms| walk.out_amount.checked_add(out_atoms))
+            .ok_or(PhoenixAmmError::AmountOverflow)?;
+        // A budget too small for one more lot reaches a level without taking from it
+        if let Some(fills) = fills.filter(|_| base_lots > 0) {
+            fills.push(FillLevel {
+                price_in_ticks,
+                base_lots,
+                quote_atoms: quote_atoms.unwrap_or(u64::MAX),
+                partial,
+            });
+        }
+        Ok(())
+    }
+
+    /// `walk` with what is left of the budget and the fee it is charged, once nothing more is
+    /// taken. Buys pay for the match rounded up to whole quote lots, plus the fee on it.
+    pub(crate) fn finish(
+        &self,
+        mut walk: LadderWalk,
+        remaining_budget: u128,
+    ) -> Result<LadderWalk> {
+        let market = self.market;
+        walk.taker_fee_bps = self.taker_fee_bps;
+        match self.side {
+            Side::Ask => walk.remaining_budget = remaining_budget as u64,
+            Side::Bid => {
+                let base_lots_per_base_unit = market.num_base_lots_per_base_unit as u128;
+                let adjusted_quote_lots =
+                    walk.matched_lot_ticks * self.quote_lots_per_base_unit_per_tick();
+                let spent_quote_lots = adjusted_quote_lots.div_ceil(base_lots_per_base_unit)
+                    + market.fee_in_quote_lots(adjusted_quote_lots, self.taker_fee_bps);
+                walk.in_amount_consumed =
+                    u64::try_from(spent_quote_lots * market.quote_atoms_per_quote_lot as u128)
+                        .map_err(|_| PhoenixAmmError::AmountOverflow)?;
+                walk.remaining_budget = (remaining_budget / base_lots_per_base_unit) as u64;
+            }
+        }
+        Ok(walk)
+    }
+
+    /// The walk of `budget`, which must be at least the `spent` budget `filled` took. The levels
+    /// it clears in full are taken into `filled` for the next, larger budget, and the rest is
+    /// taken from a copy.
+    pub(crate) fn walk_increasing(
+        &mut self,
+        filled: &mut LadderWalk,
+        spent: &mut u128,
+        budget: u128,
+    ) -> Result<LadderWalk> {
+        let remaining_budget = self.take_whole_levels(filled, budget - *spent)?;
+        *spent = budget - remaining_budget;
+        let mut walk = *filled;
+        let remaining_budget = self.clone().take(&mut walk, remaining_budget, None)?;
+        self.finish(walk, remaining_budget)
+    }
+
+    fn quote_lots_per_base_unit_per_tick(&self) -> u128 {
+        (self.market.tick_size_in_quote_atoms_per_base_unit / self.market.quote_atoms_per_quote_lot)
+            as u128
+    }
+}